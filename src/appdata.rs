@@ -1,13 +1,18 @@
-use std::{collections::{HashMap, HashSet}, fs::{File, OpenOptions}, io::Write, net::{IpAddr, Ipv4Addr, SocketAddrV4}, path::{Path, PathBuf}, sync::LazyLock};
+mod migration;
+mod merge;
+pub mod doctor;
+pub mod transfer;
+pub mod category_file;
+use std::{collections::{BTreeSet, HashMap, HashSet}, fs::File, io::Write, net::{IpAddr, Ipv4Addr, SocketAddrV4}, path::{Path, PathBuf}, sync::{LazyLock, Mutex}};
 
-use crate::{message::Formatting, ui::theme::Theme};
+use crate::{message::{Formatting, SendMode}, ui::theme::Theme};
+use anyhow::{anyhow, bail};
 use local_ip_address::local_ip;
 use ron::ser::PrettyConfig;
 use serde::Serialize;
 use serde_versioning::Deserialize;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-use crate::{messangers::Key, send_categories::{NetworksPool, SendCategory}, ui::{main_screen, message_history::SaveMessageInfo}};
+use crate::{crypto, messangers::{AccountId, Key, Messenger}, paths, send_categories::{DigestBatch, NetworksPool, QueuedMessage, SendCategory}, ui::{main_screen, message_history::SaveMessageInfo}};
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -36,8 +41,14 @@ struct AppData1 {
 #[serde(default)]
 #[versioning(optimistic, previous_version = "AppData1")]
 pub struct AppData {
+    /// Settings format version. Files without it are older than explicit versioning and have version 0
+    #[serde(default)]
+    #[default(migration::CURRENT_VERSION)]
+    pub version: u32,
     /// Stores group keys mapped to group info
     pub groups: HashMap<Key, main_screen::Group>,
+    /// Groups never sent any message, whatever categories and general groups say
+    pub blocked_groups: HashSet<Key>,
     /// The address and port the application listens on for incoming messages
     #[default(default_address())]
     pub recieve_address: SocketAddrV4,
@@ -49,10 +60,14 @@ pub struct AppData {
     /// Length of message history
     #[default(50)]
     pub history_len: u32,
-    /// Has user logged to signal
+    /// Has user logged to signal. Used only by settings saved before multiple accounts, see `signal_accounts`
     pub signal_logged: bool,
-    /// Has user logged to whatsapp
+    /// Signal accounts user has added, the first one always exists
+    pub signal_accounts: Vec<Account>,
+    /// Has user logged to whatsapp. Used only by settings saved before multiple accounts, see `whatsapp_accounts`
     pub whatsapp_logged: bool,
+    /// Whatsapp accounts user has added, the first one always exists
+    pub whatsapp_accounts: Vec<Account>,
     /// App theme
     pub theme: Theme,
     /// List of categories (channels) user set up
@@ -67,13 +82,22 @@ pub struct AppData {
     pub message_file: bool,
     /// Not sent messages saved after close up
     pub saved_messages: Vec<SaveMessageInfo>,
+    /// Messages waiting for schedules of their categories to start
+    pub queued_messages: Vec<QueuedMessage>,
+    /// Messages collected for digests of categories
+    pub digests: Vec<DigestBatch>,
     /// Formatting used to send messages
     pub formatting: Option<Formatting>,
+    /// Formatting used instead of `formatting` for groups of the messenger
+    pub messenger_formatting: HashMap<Messenger, Formatting>,
+    /// Token required by the HTTP API to manage sent messages
+    pub api_token: Option<String>,
 }
 
 impl From<AppData1> for AppData {
     fn from(value: AppData1) -> Self {
         Self {
+            version: 0,
             groups: value.groups,
             recieve_address: value.recieve_address,
             autosend: value.autosend,
@@ -93,60 +117,337 @@ impl From<AppData1> for AppData {
     }
 }
 
-static SETTINGS_PATH: LazyLock<PathBuf> = LazyLock::new(
-    || match std::env::home_dir() {
-        Some(path) => path.join(".sender/data.ron"),
-        None => PathBuf::from("data.ron"),
+#[derive(Debug, Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct Account {
+    pub id: AccountId,
+    pub name: String,
+    /// Has user logged to this account
+    pub logged: bool,
+}
+
+static SETTINGS_PATH: LazyLock<PathBuf> = LazyLock::new(|| paths::profile_file("data.ron"));
+/// Number of previous settings versions kept as `data.ron.1` (the newest) to `data.ron.N`
+const BACKUP_COUNT: usize = 5;
+/// Settings file content as it was last read or written by the application, `None` when there was no file
+static SYNCED: Mutex<Option<String>> = Mutex::new(None);
+
+/// Settings file was changed by someone else since it was last read or written
+pub struct ExternalChange {
+    content: String,
+    /// Settings from the file
+    pub theirs: AppData,
+    /// Settings in memory merged with ones from the file
+    pub merge: merge::Merge,
+}
+
+impl ExternalChange {
+    /// Marks the file as seen, so settings chosen by user can be saved over it
+    pub fn accept(&self) {
+        *SYNCED.lock().unwrap() = Some(self.content.clone());
     }
-);
+}
 
 impl AppData {
-    pub fn load() -> anyhow::Result<Self> {
-        let data = File::open(SETTINGS_PATH.as_path())?;
-        let state = ron::de::from_reader(data)?;
+    /// Parses settings of any known version, migrating them to the current one
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut state: Self = ron::de::from_str(content)?;
+        migration::migrate(&mut state)?;
+        Ok(state)
+    }
+
+    /// Reads settings file, decrypting it when profile is encrypted
+    pub fn read(path: &Path) -> anyhow::Result<String> {
+        let content = crypto::unseal("settings", std::fs::read(path)?)?;
+        Ok(String::from_utf8(content)?)
+    }
+
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = Self::read(path)?;
+        let state = Self::parse(&content)?;
+        for issue in doctor::check(&content, &state).unwrap_or_default() {
+            log::warn!("Settings {}: {issue}", path.display());
+        }
+        *SYNCED.lock().unwrap() = Some(content);
         Ok(state)
     }
 
+    /// Checks whether settings file was changed by someone else, e.g. updated by administrator
+    pub fn external_change(&self) -> anyhow::Result<Option<ExternalChange>> {
+        let Ok(content) = Self::read(&SETTINGS_PATH) else {
+            return Ok(None)
+        };
+        let synced = SYNCED.lock().unwrap().clone();
+        if synced.as_ref() == Some(&content) {
+            return Ok(None)
+        }
+        let theirs = Self::parse(&content)?;
+        let base = match synced {
+            Some(synced) => Self::parse(&synced)?,
+            None => Self::initial(),
+        };
+        let merge = self.merge(&base, &theirs);
+        Ok(Some(ExternalChange { content, theirs, merge }))
+    }
+
     pub async fn load_from(path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let mut file = tokio::fs::File::open(path).await?;
-        let mut content = String::new();
-        file.read_to_string(&mut content).await?;
-        Ok(ron::de::from_str(&content)?)
+        let content = crypto::unseal("settings", tokio::fs::read(path).await?)?;
+        Self::parse(&String::from_utf8(content)?)
+    }
+
+    /// Settings file of active profile
+    pub fn settings_path() -> &'static Path {
+        SETTINGS_PATH.as_path()
     }
 
     pub async fn save_to(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
-        let mut file = tokio::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(path)
-            .await?;
+        let path = path.as_ref().to_owned();
         let s = ron::ser::to_string_pretty(
             self,
             PrettyConfig::default(),
         ).unwrap();
 
-        file.write_all(s.as_bytes()).await?;
-        Ok(())
+        tokio::task::spawn_blocking(move || write_atomic(&path, s.as_bytes()))
+            .await
+            .map_err(std::io::Error::other)?
+    }
+
+    /// Loads settings of active profile. Defaults are used only when there is no settings file yet,
+    /// unreadable file is an error, so it is not overwritten before user decides to restore a backup
+    pub fn open() -> anyhow::Result<Self> {
+        if crypto::enabled() && !crypto::unlocked() {
+            bail!("Profile is encrypted, set passphrase with SENDER_PASSPHRASE environment variable");
+        }
+        if !SETTINGS_PATH.exists() {
+            return Ok(Self::initial())
+        }
+        Self::load(&SETTINGS_PATH).map_err(|e| anyhow!("Settings file {} is unreadable: {e}", SETTINGS_PATH.display()))
+    }
+
+    fn initial() -> Self {
+        let mut state = Self::default();
+        state.migrate_accounts();
+        state
+    }
+
+    /// Existing backups of settings file, the newest first
+    pub fn backups() -> Vec<PathBuf> {
+        (1 ..= BACKUP_COUNT)
+            .map(backup_path)
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    /// Replaces settings file with the newest readable backup, returning loaded settings and the backup used
+    pub fn restore_backup() -> anyhow::Result<(Self, PathBuf)> {
+        for path in Self::backups() {
+            match Self::load(&path) {
+                Ok(state) => {
+                    set_aside_broken()?;
+                    std::fs::copy(&path, SETTINGS_PATH.as_path())?;
+                    log::warn!("Settings restored from {}", path.display());
+                    return Ok((state, path))
+                },
+                Err(e) => log::warn!("Backup {} is unreadable: {e}", path.display()),
+            }
+        }
+        bail!("No readable settings backup found")
+    }
+
+    /// Starts with default settings. Unreadable settings file is kept as `data.ron.broken`
+    pub fn reset() -> std::io::Result<Self> {
+        set_aside_broken()?;
+        Ok(Self::initial())
+    }
+
+    /// Settings without accounts get the first one, logged if user has logged to the messenger before
+    fn migrate_accounts(&mut self) {
+        if self.signal_accounts.is_empty() {
+            self.signal_accounts.push(Account {
+                id: 0,
+                name: Messenger::Signal.to_string(),
+                logged: std::mem::take(&mut self.signal_logged),
+            });
+        }
+        if self.whatsapp_accounts.is_empty() {
+            self.whatsapp_accounts.push(Account {
+                id: 0,
+                name: Messenger::Whatsapp.to_string(),
+                logged: std::mem::take(&mut self.whatsapp_logged),
+            });
+        }
+    }
+
+    pub fn accounts(&self, messenger: Messenger) -> &Vec<Account> {
+        match messenger {
+            Messenger::Signal => &self.signal_accounts,
+            Messenger::Whatsapp => &self.whatsapp_accounts,
+        }
+    }
+
+    fn accounts_mut(&mut self, messenger: Messenger) -> &mut Vec<Account> {
+        match messenger {
+            Messenger::Signal => &mut self.signal_accounts,
+            Messenger::Whatsapp => &mut self.whatsapp_accounts,
+        }
+    }
+
+    pub fn account(&self, messenger: Messenger, id: AccountId) -> Option<&Account> {
+        self.accounts(messenger).iter().find(|a| a.id == id)
+    }
+
+    pub fn account_mut(&mut self, messenger: Messenger, id: AccountId) -> Option<&mut Account> {
+        self.accounts_mut(messenger).iter_mut().find(|a| a.id == id)
+    }
+
+    /// User has logged to at least one account of the messenger
+    pub fn any_logged(&self, messenger: Messenger) -> bool {
+        self.accounts(messenger).iter().any(|a| a.logged)
+    }
+
+    /// Adds account with the next free id, returns `None` when all ids are taken
+    pub fn add_account(&mut self, messenger: Messenger) -> Option<AccountId> {
+        let id = (0 ..= AccountId::MAX).find(|id| self.account(messenger, *id).is_none())?;
+        self.accounts_mut(messenger).push(Account { id, name: format!("{messenger} {}", id as u32 + 1), logged: false });
+        Some(id)
     }
 
-    pub fn new() -> Self {
-        Self::load().unwrap_or_default()
+    /// Removes account together with its groups from the list and categories. The first account can't be removed
+    pub fn remove_account(&mut self, messenger: Messenger, id: AccountId) {
+        if id == 0 {
+            return
+        }
+        self.accounts_mut(messenger).retain(|a| a.id != id);
+        self.groups.retain(|key, _| key.account() != (messenger, id));
+        for category in self.categories.iter_mut() {
+            category.groups.retain(|key, _| key.account() != (messenger, id));
+        }
+    }
+
+    /// Updates group list with received titles, keeping chosen send modes
+    pub fn set_groups(&mut self, groups: Vec<(Key, String)>) {
+        let mut replace_map = groups
+            .into_iter()
+            .map(|(key, title)| {
+                let group = match self.groups.get(&key) {
+                    Some(group) => main_screen::Group { title, ..group.clone() },
+                    None => main_screen::Group::new(title, SendMode::Off),
+                };
+                (key, group)
+            })
+            .collect::<HashMap<_, _>>();
+
+        // Whatsapp sends full list of one account, so groups of other accounts are kept
+        let updated_accounts = replace_map.keys()
+            .filter_map(|key| match key {
+                Key::Whatsapp(account, _) => Some(*account),
+                Key::Signal(..) => None,
+            })
+            .collect::<BTreeSet<_>>();
+        replace_map.extend(
+            std::mem::take(&mut self.groups)
+            .into_iter()
+            .filter(|(key, _)| match key {
+                Key::Signal(..) => true,
+                Key::Whatsapp(account, _) => !updated_accounts.contains(account),
+            })
+        );
+
+        self.groups = replace_map;
     }
 
+    /// Writes settings atomically, keeping previous version as backup
     pub fn save(&self) -> anyhow::Result<()> {
-        let mut config_file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(SETTINGS_PATH.as_path())?;
         let s = ron::ser::to_string_pretty(
             self,
             PrettyConfig::default(),
         )?;
-        config_file.write_all(s.as_bytes())?;
+        let path = SETTINGS_PATH.as_path();
+        let mut synced = SYNCED.lock().unwrap();
+        match Self::read(path) {
+            Ok(old) if old == s => {
+                *synced = Some(s);
+                return Ok(())
+            },
+            Ok(old) if synced.as_ref() != Some(&old) => bail!("Settings file was changed externally, reload or merge it first"),
+            Err(_) if synced.is_some() && path.exists() => bail!("Settings file was changed externally and can't be read"),
+            _ => (),
+        }
+        rotate_backups()?;
+        write_atomic(path, &crypto::seal("settings", s.as_bytes())?)?;
+        *synced = Some(s);
         Ok(())
     }
+
+    /// Saves settings even if the file was changed externally
+    pub fn overwrite(&self) -> anyhow::Result<()> {
+        *SYNCED.lock().unwrap() = Self::read(&SETTINGS_PATH).ok();
+        self.save()
+    }
+
+    /// Replaces plain settings file and its backups with encrypted settings once encryption is enabled
+    pub fn save_encrypted(&self) -> anyhow::Result<()> {
+        let plain_files = (1 ..= BACKUP_COUNT)
+            .map(backup_path)
+            .chain([with_suffix(&SETTINGS_PATH, ".broken"), SETTINGS_PATH.clone()]);
+        for path in plain_files {
+            crypto::wipe(&path)?;
+        }
+        self.save()
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn backup_path(n: usize) -> PathBuf {
+    with_suffix(&SETTINGS_PATH, &format!(".{n}"))
+}
+
+/// Shifts backups by one, dropping the oldest, and copies current settings file to the first one
+fn rotate_backups() -> std::io::Result<()> {
+    if !SETTINGS_PATH.exists() {
+        return Ok(())
+    }
+    for n in (1 .. BACKUP_COUNT).rev() {
+        let path = backup_path(n);
+        if path.exists() {
+            std::fs::rename(&path, backup_path(n + 1))?;
+        }
+    }
+    std::fs::copy(SETTINGS_PATH.as_path(), backup_path(1))?;
+    Ok(())
+}
+
+fn set_aside_broken() -> std::io::Result<()> {
+    if SETTINGS_PATH.exists() {
+        std::fs::rename(SETTINGS_PATH.as_path(), with_suffix(&SETTINGS_PATH, ".broken"))?;
+    }
+    Ok(())
+}
+
+/// Writes to temporary file next to `path` and renames it over, so a crash or full disk never leaves the file half written
+fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let tmp = with_suffix(path, ".tmp");
+    let write = || {
+        let mut file = File::create(&tmp)?;
+        file.write_all(content)?;
+        file.sync_all()
+    };
+    if let Err(e) = write() {
+        _ = std::fs::remove_file(&tmp);
+        return Err(e)
+    }
+    std::fs::rename(&tmp, path)?;
+
+    // Rename itself has to reach the disk too
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
 }
 
 fn default_address() -> SocketAddrV4 {
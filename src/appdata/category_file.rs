@@ -0,0 +1,152 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::anyhow;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::{message_server::CategoryDto, messangers::{Key, Messenger}, send_categories::SendCategory, ui::main_screen::Group};
+use super::{transfer::ron_options, write_atomic};
+
+/// Categories shared as a standalone file, written as RON or as JSON when the file name ends with `.json`.
+/// Titles of groups are written with them, so groups are found where their keys are different
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CategoryFile {
+    pub categories: Vec<CategoryDto>,
+    /// Titles of groups categories send to or exclude, by key
+    pub groups: HashMap<String, String>,
+}
+
+/// Group of imported category which is not among known groups
+#[derive(Debug, Clone, PartialEq, derive_more::Display)]
+#[display("{category}: {group}")]
+pub struct Unresolved {
+    pub category: String,
+    /// Title of the group, or its key when the file has no title for it
+    pub group: String,
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+impl CategoryFile {
+    pub fn export<'a>(categories: impl IntoIterator<Item = &'a SendCategory>, groups: &HashMap<Key, Group>) -> Self {
+        let categories = categories.into_iter().collect::<Vec<_>>();
+        let titles = categories.iter()
+            .flat_map(|category| category.groups.keys().chain(category.excluded.iter()))
+            .filter_map(|key| Some((key.to_string(), groups.get(key)?.title.clone())))
+            .collect();
+        Self {
+            categories: categories.into_iter().map(CategoryDto::from).collect(),
+            groups: titles,
+        }
+    }
+
+    pub fn parse(content: &str, json: bool) -> anyhow::Result<Self> {
+        Ok(match json {
+            true => serde_json::from_str(content)?,
+            false => ron_options().from_str(content)?,
+        })
+    }
+
+    pub fn to_text(&self, json: bool) -> anyhow::Result<String> {
+        Ok(match json {
+            true => serde_json::to_string_pretty(self)?,
+            false => ron_options().to_string_pretty(self, PrettyConfig::default())?,
+        })
+    }
+
+    pub async fn load_from(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let content = tokio::fs::read_to_string(path.as_ref()).await?;
+        Self::parse(&content, is_json(path.as_ref()))
+    }
+
+    pub async fn save_to(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref().to_owned();
+        let s = self.to_text(is_json(&path))?;
+        tokio::task::spawn_blocking(move || write_atomic(&path, s.as_bytes())).await??;
+        Ok(())
+    }
+
+    /// Categories with groups matched to `known` ones. Groups which are not found are left out and returned
+    pub fn resolve(&self, known: &HashMap<Key, Group>) -> anyhow::Result<(Vec<SendCategory>, Vec<Unresolved>)> {
+        let mut categories = Vec::with_capacity(self.categories.len());
+        let mut unresolved = Vec::new();
+        for dto in self.categories.iter() {
+            let mut dto = dto.clone();
+            let mut resolve = |key: String| {
+                let title = self.groups.get(&key);
+                let local = resolve_key(&key, title.map(String::as_str), known);
+                if local.is_none() {
+                    unresolved.push(Unresolved { category: dto.name.clone(), group: title.cloned().unwrap_or(key) });
+                }
+                local.map(|key| key.to_string())
+            };
+            let groups = std::mem::take(&mut dto.groups).into_iter()
+                .filter_map(|(key, mode)| Some((resolve(key)?, mode)))
+                .collect();
+            let excluded = std::mem::take(&mut dto.excluded).into_iter()
+                .filter_map(&mut resolve)
+                .collect();
+            dto.groups = groups;
+            dto.excluded = excluded;
+            categories.push(SendCategory::try_from(dto).map_err(|e| anyhow!("{e}"))?);
+        }
+        unresolved.sort_by(|a, b| (&a.category, &a.group).cmp(&(&b.category, &b.group)));
+        Ok((categories, unresolved))
+    }
+}
+
+/// Known group `key` refers to: the same group, maybe reachable from another account, or a group with the same title.
+/// Groups of the same messenger are preferred when several have the title
+fn resolve_key(key: &str, title: Option<&str>, known: &HashMap<Key, Group>) -> Option<Key> {
+    let parsed = key.parse::<Key>().ok();
+    if let Some(parsed) = &parsed {
+        if known.contains_key(parsed) {
+            return Some(parsed.clone())
+        }
+        if let Some(local) = known.keys().find(|k| k.same_group(parsed)) {
+            return Some(local.clone())
+        }
+    }
+    let title = title?;
+    known.iter()
+        .filter(|(_, group)| group.title == title)
+        .map(|(key, _)| key)
+        .min_by_key(|key| (parsed.as_ref().is_none_or(|p| Messenger::from(*key) != Messenger::from(p)), key.to_string()))
+        .cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::{message::SendMode, messangers::Key, send_categories::SendCategory, ui::main_screen::Group};
+    use super::{CategoryFile, Unresolved};
+
+    #[test]
+    fn resolves_groups_by_title() {
+        let group = |title: &str| Group::new(title.to_owned(), SendMode::Off);
+        let mut north = SendCategory::new("North".to_owned());
+        north.groups.insert(Key::Signal(0, [1; 32]), SendMode::Normal);
+        north.groups.insert(Key::Signal(0, [2; 32]), SendMode::Frequency);
+        north.excluded.insert(Key::Signal(0, [3; 32]));
+        let theirs = HashMap::from([
+            (Key::Signal(0, [1; 32]), group("A")),
+            (Key::Signal(0, [2; 32]), group("B")),
+            (Key::Signal(0, [3; 32]), group("C")),
+        ]);
+        let file = CategoryFile::export([&north], &theirs);
+        let json = CategoryFile::parse(&file.to_text(true).unwrap(), true).unwrap();
+
+        let ours = HashMap::from([
+            (Key::Signal(1, [1; 32]), group("A")),
+            (Key::Signal(0, [7; 32]), group("B")),
+        ]);
+        let (categories, unresolved) = json.resolve(&ours).unwrap();
+        assert_eq!(categories[0].groups, HashMap::from([(Key::Signal(1, [1; 32]), SendMode::Normal), (Key::Signal(0, [7; 32]), SendMode::Frequency)]));
+        assert!(categories[0].excluded.is_empty());
+        assert_eq!(unresolved, [Unresolved { category: "North".to_owned(), group: "C".to_owned() }]);
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt::Display;
+
+use serde::de::{Deserializer, IgnoredAny, MapAccess, Visitor};
+
+use crate::messangers::Key;
+use super::AppData;
+
+/// Problem found in settings which doesn't prevent them from loading
+#[derive(Debug, PartialEq)]
+pub enum Issue {
+    /// Field is not used by the current version and is dropped on next save
+    UnknownField(String),
+    /// Category refers to group which is not in the group list
+    DanglingGroup { category: String, key: Key },
+    /// Category refers to network which is not in the networks list
+    MissingNetwork { category: String, id: u64 },
+    /// Regular expression of category can't be compiled and never matches
+    InvalidPattern { category: String, pattern: String },
+    /// Schedule of category uses unknown time zone, system one is used instead
+    UnknownTimezone { category: String, timezone: String },
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownField(name) => write!(f, "unknown field `{name}` is ignored"),
+            Self::DanglingGroup { category, key } => write!(f, "category \"{category}\" refers to unknown group {key}"),
+            Self::MissingNetwork { category, id } => write!(f, "category \"{category}\" refers to unknown network {id}"),
+            Self::InvalidPattern { category, pattern } => write!(f, "category \"{category}\" has invalid pattern `{pattern}`"),
+            Self::UnknownTimezone { category, timezone } => write!(f, "category \"{category}\" has schedule in unknown time zone `{timezone}`"),
+        }
+    }
+}
+
+/// Checks settings file `content` and settings loaded from it
+pub fn check(content: &str, data: &AppData) -> anyhow::Result<Vec<Issue>> {
+    let known = field_names(&ron::to_string(&AppData::default())?)?;
+    let mut issues = field_names(content)?
+        .into_iter()
+        .filter(|name| !known.contains(name))
+        .map(Issue::UnknownField)
+        .collect::<Vec<_>>();
+
+    for category in data.categories.iter() {
+        let mut keys = category.groups.keys().chain(category.excluded.iter())
+            .filter(|key| !data.groups.contains_key(key))
+            .collect::<Vec<_>>();
+        keys.sort_by_key(|key| key.to_string());
+        issues.extend(keys.into_iter().map(|key| Issue::DanglingGroup { category: category.name().to_owned(), key: key.clone() }));
+
+        issues.extend(
+            category.rule.networks().into_iter()
+            .filter(|id| !data.networks.contains_key(id))
+            .map(|id| Issue::MissingNetwork { category: category.name().to_owned(), id })
+        );
+        issues.extend(
+            category.rule.invalid_patterns().into_iter()
+            .map(|pattern| Issue::InvalidPattern { category: category.name().to_owned(), pattern: pattern.to_owned() })
+        );
+        if let Some(schedule) = &category.schedule && schedule.time_zone().is_none() {
+            issues.push(Issue::UnknownTimezone { category: category.name().to_owned(), timezone: schedule.timezone.clone() });
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Names of top level fields in settings file
+fn field_names(content: &str) -> anyhow::Result<Vec<String>> {
+    struct FieldNames;
+
+    impl<'de> Visitor<'de> for FieldNames {
+        type Value = Vec<String>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("settings struct")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut names = Vec::new();
+            while let Some(name) = map.next_key::<String>()? {
+                map.next_value::<IgnoredAny>()?;
+                names.push(name);
+            }
+            Ok(names)
+        }
+    }
+
+    let mut deserializer = ron::Deserializer::from_str(content)?;
+    Ok((&mut deserializer).deserialize_struct("AppData", &[], FieldNames)?)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::messangers::Key;
+    use super::{AppData, Issue, check};
+
+    #[test]
+    fn reports_issues() {
+        let content = format!(
+            "(version: 1, sync_interval: 60, categories: [(name: \"North\", parameters: Networks([7]), groups: {{SignalAccount(0, ({})): Normal}})])",
+            ["3"; 32].join(", "),
+        );
+        let data = AppData::parse(&content).unwrap();
+        assert_eq!(check(&content, &data).unwrap(), vec![
+            Issue::UnknownField("sync_interval".to_owned()),
+            Issue::DanglingGroup { category: "North".to_owned(), key: Key::Signal(0, [3; 32]) },
+            Issue::MissingNetwork { category: "North".to_owned(), id: 7 },
+        ]);
+    }
+
+    #[test]
+    fn golden_settings_are_clean() {
+        let content = include_str!("golden/v2.ron");
+        let data = AppData::parse(content).unwrap();
+        assert!(check(content, &data).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use std::{collections::{HashMap, HashSet}, hash::Hash};
+
+use super::AppData;
+
+/// In-memory settings combined with settings changed in the file
+pub struct Merge {
+    pub data: AppData,
+    /// Settings changed both in memory and in the file differently, in-memory values are kept for them
+    pub conflicts: Vec<String>,
+    /// Settings in memory were changed since the file was last read or written
+    pub local_changes: bool,
+}
+
+/// Three-way merge of one value, `None` when both sides changed it differently
+fn merge_value<T: PartialEq + Clone>(base: &T, ours: &T, theirs: &T, local_changes: &mut bool) -> Option<T> {
+    *local_changes |= ours != base;
+    if ours == theirs || theirs == base {
+        Some(ours.clone())
+    }
+    else if ours == base {
+        Some(theirs.clone())
+    }
+    else {
+        None
+    }
+}
+
+/// Merges map item by item, so different items changed on both sides don't conflict
+fn merge_map<K: Eq + Hash + Clone, V: PartialEq + Clone>(
+    base: &HashMap<K, V>,
+    ours: &HashMap<K, V>,
+    theirs: &HashMap<K, V>,
+    local_changes: &mut bool,
+    mut conflict: impl FnMut(&V),
+) -> HashMap<K, V> {
+    let keys = ours.keys().chain(theirs.keys()).chain(base.keys()).collect::<HashSet<_>>();
+    let mut merged = HashMap::new();
+    for key in keys {
+        let (b, o, t) = (base.get(key).cloned(), ours.get(key).cloned(), theirs.get(key).cloned());
+        let value = match merge_value(&b, &o, &t, local_changes) {
+            Some(value) => value,
+            None => {
+                conflict(o.as_ref().or(t.as_ref()).unwrap());
+                o
+            },
+        };
+        if let Some(value) = value {
+            merged.insert(key.clone(), value);
+        }
+    }
+    merged
+}
+
+macro_rules! merge_fields {
+    ($merged:ident, $base:ident, $theirs:ident, $conflicts:ident, $local_changes:ident; $($field:ident),* $(,)?) => {
+        $(
+            match merge_value(&$base.$field, &$merged.$field, &$theirs.$field, &mut $local_changes) {
+                Some(value) => $merged.$field = value,
+                None => $conflicts.push(format!("налаштування {}", stringify!($field))),
+            }
+        )*
+    };
+}
+
+impl AppData {
+    /// Merges settings changed in the file into these ones. `base` is the file as it was last read or written
+    pub fn merge(&self, base: &AppData, theirs: &AppData) -> Merge {
+        let mut merged = self.clone();
+        let mut conflicts = Vec::new();
+        let mut local_changes = false;
+
+        merge_fields!(merged, base, theirs, conflicts, local_changes;
+            recieve_address,
+            autosend,
+            markdown,
+            history_len,
+            signal_accounts,
+            whatsapp_accounts,
+            theme,
+            sources,
+            comments,
+            message_file,
+            formatting,
+            messenger_formatting,
+            api_token,
+            blocked_groups,
+        );
+
+        merged.groups = merge_map(&base.groups, &self.groups, &theirs.groups, &mut local_changes, |group| {
+            conflicts.push(format!("група \"{}\"", group.title));
+        });
+        merged.networks = merge_map(&base.networks, &self.networks, &theirs.networks, &mut local_changes, |network| {
+            conflicts.push(format!("мережа \"{}\"", network.name));
+        });
+
+        // Categories are merged by name, keeping order of in-memory ones and appending new ones from the file
+        let by_name = |data: &AppData| data.categories.iter()
+            .map(|c| (c.name().to_owned(), c.clone()))
+            .collect::<HashMap<_, _>>();
+        let categories = merge_map(&by_name(base), &by_name(self), &by_name(theirs), &mut local_changes, |category| {
+            conflicts.push(format!("категорія \"{}\"", category.name()));
+        });
+        let mut seen = HashSet::new();
+        merged.categories = self.categories.iter()
+            .chain(theirs.categories.iter())
+            .filter(|c| seen.insert(c.name()))
+            .filter_map(|c| categories.get(c.name()).cloned())
+            .collect();
+
+        Merge { data: merged, conflicts, local_changes }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{message::SendMode, messangers::Key, send_categories::SendCategory, ui::main_screen::Group};
+    use super::AppData;
+
+    #[test]
+    fn merges_independent_changes() {
+        let mut base = AppData::default();
+        base.groups.insert(Key::Signal(0, [1; 32]), Group::new("A".to_owned(), SendMode::Off));
+        base.categories.push(SendCategory::new("North".to_owned()));
+
+        let mut ours = base.clone();
+        ours.autosend = true;
+        ours.groups.get_mut(&Key::Signal(0, [1; 32])).unwrap().send_mode = SendMode::Normal;
+
+        let mut theirs = base.clone();
+        theirs.history_len = 10;
+        theirs.categories.push(SendCategory::new("South".to_owned()));
+
+        let merge = ours.merge(&base, &theirs);
+        assert!(merge.conflicts.is_empty());
+        assert!(merge.local_changes);
+        assert!(merge.data.autosend);
+        assert_eq!(merge.data.history_len, 10);
+        assert_eq!(merge.data.groups[&Key::Signal(0, [1; 32])].send_mode, SendMode::Normal);
+        assert_eq!(merge.data.categories.iter().map(|c| c.name()).collect::<Vec<_>>(), ["North", "South"]);
+    }
+
+    #[test]
+    fn reports_conflicts() {
+        let base = AppData::default();
+        let mut ours = base.clone();
+        ours.history_len = 20;
+        let mut theirs = base.clone();
+        theirs.history_len = 30;
+
+        let merge = ours.merge(&base, &theirs);
+        assert_eq!(merge.conflicts, ["налаштування history_len"]);
+        assert_eq!(merge.data.history_len, 20);
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::bail;
+
+use super::AppData;
+
+/// Version written to newly saved settings
+pub const CURRENT_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// `MIGRATIONS[n]` upgrades settings of version `n` to version `n + 1`.
+/// Changing settings format requires adding migration here together with golden file in `golden` directory
+const MIGRATIONS: [fn(&mut AppData); 3] = [
+    unversioned,
+    rules,
+    routing_options,
+];
+
+/// Brings settings of any older version to the current one
+pub fn migrate(data: &mut AppData) -> anyhow::Result<()> {
+    if data.version > CURRENT_VERSION {
+        bail!("Settings version {} is newer than supported {CURRENT_VERSION}, update Modern Sender", data.version);
+    }
+    for migration in &MIGRATIONS[data.version as usize ..] {
+        migration(data);
+    }
+    data.version = CURRENT_VERSION;
+    Ok(())
+}
+
+/// Settings saved before explicit versioning: `AppData1` and settings without accounts.
+/// Group keys and saved messages without account are converted by their deserialization
+fn unversioned(data: &mut AppData) {
+    data.migrate_accounts();
+}
+
+/// Categories matching on networks, sources or comments, including old format categories, get equivalent rules
+fn rules(data: &mut AppData) {
+    for category in data.categories.iter_mut() {
+        category.migrate_rule();
+    }
+}
+
+/// Blocked and excluded groups, schedules, templates of groups, categories and messengers, priorities, rate limits,
+/// queued messages and digests are added. All of them have defaults, so nothing is converted
+fn routing_options(_data: &mut AppData) {}
+
+#[cfg(test)]
+mod test {
+    use crate::{message::{Priority, SendMode}, messangers::{Key, Messenger}, send_categories::{Day, Rule}};
+    use super::{AppData, CURRENT_VERSION};
+
+    /// Settings of the oldest format with obsolete fields and categories without parameters
+    #[test]
+    fn appdata1() {
+        let data = AppData::parse(include_str!("golden/appdata1.ron")).unwrap();
+        assert_eq!(data.version, CURRENT_VERSION);
+        assert_eq!(data.recieve_address.to_string(), "192.168.1.5:8000");
+        assert_eq!(data.history_len, 30);
+        assert_eq!(data.groups[&Key::Signal(0, [1; 32])].send_mode, SendMode::Normal);
+        assert!(data.account(Messenger::Signal, 0).unwrap().logged);
+        assert!(!data.account(Messenger::Whatsapp, 0).unwrap().logged);
+
+        let category = &data.categories[0];
+        assert_eq!(category.name(), "Old");
+        assert!(category.active);
+        assert!(!category.use_general);
+        assert!(category.contains_network(&5));
+        assert_eq!(category.groups[&Key::Signal(0, [1; 32])], SendMode::Frequency);
+        assert_eq!(data.saved_messages[0].groups_signal, vec![([1; 32], SendMode::Normal)]);
+    }
+
+    /// Settings saved before explicit versioning and multiple accounts
+    #[test]
+    fn unversioned() {
+        let data = AppData::parse(include_str!("golden/v0.ron")).unwrap();
+        assert_eq!(data.version, CURRENT_VERSION);
+        assert_eq!(data.api_token.as_deref(), Some("secret"));
+        assert_eq!(data.groups[&Key::Signal(0, [1; 32])].title, "Signal group");
+        assert_eq!(data.signal_accounts.len(), 1);
+        assert!(data.account(Messenger::Signal, 0).unwrap().logged);
+        assert!(data.account(Messenger::Whatsapp, 0).unwrap().logged);
+        assert!(!data.signal_logged && !data.whatsapp_logged);
+
+        let category = &data.categories[0];
+        assert!(!category.active);
+        assert_eq!(category.rule, Rule::Any(vec![Rule::Source("Radio".to_owned())]));
+        assert_eq!(category.groups[&Key::Signal(0, [1; 32])], SendMode::Normal);
+    }
+
+    /// Settings with explicit version and several accounts
+    #[test]
+    fn version1() {
+        let data = AppData::parse(include_str!("golden/v1.ron")).unwrap();
+        assert_eq!(data.version, CURRENT_VERSION);
+        assert_eq!(data.signal_accounts.len(), 2);
+        assert_eq!(data.account(Messenger::Signal, 1).unwrap().name, "Work");
+        assert_eq!(data.groups[&Key::Signal(1, [2; 32])].send_mode, SendMode::Frequency);
+        assert!(data.categories[0].contains_network(&7));
+        assert_eq!(data.saved_messages[0].signal_groups, vec![(1, [2; 32], SendMode::Normal)]);
+    }
+
+    /// Settings with categories matched by rules
+    #[test]
+    fn version2() {
+        let data = AppData::parse(include_str!("golden/v2.ron")).unwrap();
+        assert_eq!(data.version, CURRENT_VERSION);
+        assert_eq!(data.categories[0].rule, Rule::All(vec![
+            Rule::Network(7),
+            Rule::Not(Box::new(Rule::Title("тест".to_owned()))),
+        ]));
+        assert!(data.categories[0].contains_network(&7));
+    }
+
+    /// Settings with blocked groups, schedules, templates, priorities, rate limits, queued messages and digests
+    #[test]
+    fn version3() {
+        let data = AppData::parse(include_str!("golden/v3.ron")).unwrap();
+        assert_eq!(data.version, CURRENT_VERSION);
+        let (a, b, c) = (Key::Signal(0, [1; 32]), Key::Signal(1, [2; 32]), Key::Signal(0, [3; 32]));
+        assert!(data.blocked_groups.contains(&c));
+        assert_eq!(data.groups[&a].rate_limit, Some(20));
+        assert_eq!(data.groups[&a].formatting.as_ref().unwrap().to_string(), "Група: %текст%");
+        assert_eq!(data.messenger_formatting[&Messenger::Whatsapp].to_string(), "%текст%");
+
+        let north = &data.categories[0];
+        assert!(north.excluded.contains(&a));
+        let schedule = north.schedule.as_ref().unwrap();
+        assert!(schedule.queue && schedule.days.contains(&Day::Friday));
+        assert_eq!(schedule.ranges[0].to_string(), "08:00–20:00");
+        assert_eq!(north.formatting.as_ref().unwrap().to_string(), "%частота%\n%текст%");
+        assert_eq!(north.priority, Priority::High);
+
+        let digest = data.categories[1].digest.as_ref().unwrap();
+        assert_eq!((digest.interval, digest.max_items), (30, 10));
+        assert_eq!(data.digests[0].items.len(), 1);
+        assert_eq!(data.digests[0].groups[&a], SendMode::Normal);
+
+        assert_eq!(data.queued_messages[0].overrides[&b], "У черзі");
+        assert_eq!(data.queued_messages[0].priority, Priority::High);
+        assert_eq!(data.saved_messages[0].overrides[&b], "Привіт");
+        assert_eq!(data.saved_messages[0].priority, Priority::Urgent);
+
+        let saved = ron::ser::to_string_pretty(&data, Default::default()).unwrap();
+        let loaded = AppData::parse(&saved).unwrap();
+        assert_eq!(loaded.categories, data.categories);
+        assert_eq!(loaded.queued_messages, data.queued_messages);
+        assert_eq!(loaded.digests, data.digests);
+        assert_eq!(loaded.blocked_groups, data.blocked_groups);
+    }
+
+    #[test]
+    fn saved_settings_load_back() {
+        let data = AppData::parse(include_str!("golden/v1.ron")).unwrap();
+        let saved = ron::ser::to_string_pretty(&data, Default::default()).unwrap();
+        let loaded = AppData::parse(&saved).unwrap();
+        assert_eq!(loaded.version, CURRENT_VERSION);
+        assert_eq!(loaded.groups.len(), data.groups.len());
+        assert_eq!(loaded.signal_accounts.len(), 2);
+        assert!(loaded.categories[0].contains_network(&7));
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let content = format!("(version: {})", CURRENT_VERSION + 1);
+        assert!(AppData::parse(&content).is_err());
+    }
+}
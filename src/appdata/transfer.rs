@@ -0,0 +1,338 @@
+use std::{collections::HashMap, path::Path};
+
+use derive_more::Display;
+use ron::{extensions::Extensions, ser::PrettyConfig};
+use serde::{Deserialize, Serialize};
+
+use crate::{crypto, message::{Formatting, SendMode}, messangers::{Key, Messenger}, send_categories::{NetworkInfo, SendCategory}, ui::main_screen::Group};
+use super::{AppData, write_atomic};
+
+/// Part of settings that can be exported and imported separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, clap::ValueEnum)]
+pub enum Section {
+    /// Send categories with their groups and rules
+    #[display("Категорії")]
+    Categories,
+    /// Network pool
+    #[display("Мережі")]
+    Networks,
+    /// Message formatting templates, the global one and ones of messengers
+    #[display("Шаблон повідомлень")]
+    Formatting,
+    /// Send modes, templates and rate limits of groups
+    #[display("Режими груп")]
+    Groups,
+}
+
+impl Section {
+    pub const ALL: [Section; 4] = [Section::Categories, Section::Networks, Section::Formatting, Section::Groups];
+}
+
+/// How imported items are combined with current ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Display, clap::ValueEnum)]
+pub enum Strategy {
+    /// Imported section fully replaces the current one
+    #[display("Замінити")]
+    Replace,
+    /// Imported items replace current ones with the same name, others are kept
+    #[default]
+    #[display("Обʼєднати за назвою")]
+    Merge,
+    /// Only items missing from current settings are added
+    #[display("Лише нові")]
+    SkipExisting,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 3] = [Strategy::Replace, Strategy::Merge, Strategy::SkipExisting];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum ChangeKind {
+    #[display("додано")]
+    Added,
+    #[display("змінено")]
+    Changed,
+    #[display("видалено")]
+    Removed,
+    #[display("пропущено, вже існує")]
+    Skipped,
+    /// Group is not in the group list, its send mode can't be applied
+    #[display("пропущено, групи немає в списку")]
+    Missing,
+}
+
+/// One change made by import, shown to user before it is applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub section: Section,
+    pub kind: ChangeKind,
+    pub name: String,
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} — {}", self.section, self.name, self.kind)
+    }
+}
+
+/// Exported part of settings. Its fields have the same names as in [`AppData`],
+/// so a full settings file can be imported selectively too
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SettingsPart {
+    pub categories: Option<Vec<SendCategory>>,
+    pub networks: Option<HashMap<u64, NetworkInfo>>,
+    pub formatting: Option<Formatting>,
+    pub messenger_formatting: Option<HashMap<Messenger, Formatting>>,
+    pub groups: Option<HashMap<Key, Group>>,
+}
+
+pub(super) fn ron_options() -> ron::Options {
+    ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME)
+}
+
+impl SettingsPart {
+    pub fn export(data: &AppData, sections: &[Section]) -> Self {
+        Self {
+            categories: sections.contains(&Section::Categories).then(|| data.categories.clone()),
+            networks: sections.contains(&Section::Networks).then(|| data.networks.clone()),
+            formatting: sections.contains(&Section::Formatting).then(|| data.formatting.clone()).flatten(),
+            messenger_formatting: sections.contains(&Section::Formatting).then(|| data.messenger_formatting.clone()),
+            groups: sections.contains(&Section::Groups).then(|| data.groups.clone()),
+        }
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut part: Self = ron_options().from_str(content)?;
+        for category in part.categories.iter_mut().flatten() {
+            category.migrate_rule();
+        }
+        Ok(part)
+    }
+
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        Ok(ron_options().to_string_pretty(self, PrettyConfig::default())?)
+    }
+
+    pub async fn load_from(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let content = crypto::unseal("settings", tokio::fs::read(path).await?)?;
+        Self::parse(&String::from_utf8(content)?)
+    }
+
+    pub async fn save_to(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref().to_owned();
+        let s = self.to_ron()?;
+        tokio::task::spawn_blocking(move || write_atomic(&path, s.as_bytes())).await??;
+        Ok(())
+    }
+
+    /// Sections present in the file
+    pub fn sections(&self) -> Vec<Section> {
+        Section::ALL.into_iter()
+            .filter(|section| match section {
+                Section::Categories => self.categories.is_some(),
+                Section::Networks => self.networks.is_some(),
+                Section::Formatting => self.formatting.is_some() || self.messenger_formatting.is_some(),
+                Section::Groups => self.groups.is_some(),
+            })
+            .collect()
+    }
+
+    /// Applies chosen sections to `data` and lists what was changed. Applying to a copy of settings gives preview
+    pub fn apply(&self, data: &mut AppData, sections: &[Section], strategy: Strategy) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        if let Some(categories) = self.categories.as_ref().filter(|_| sections.contains(&Section::Categories)) {
+            apply_items(&mut data.categories, categories, strategy, |c| c.name().to_owned(), |c| c.name().to_owned(), Section::Categories, &mut changes);
+        }
+
+        if let Some(networks) = self.networks.as_ref().filter(|_| sections.contains(&Section::Networks)) {
+            let sorted = |networks: &HashMap<u64, NetworkInfo>| {
+                let mut networks = networks.values().cloned().collect::<Vec<_>>();
+                networks.sort_by_key(|n| n.id);
+                networks
+            };
+            let mut current = sorted(&data.networks);
+            apply_items(&mut current, &sorted(networks), strategy, |n| n.id, |n| n.name.clone(), Section::Networks, &mut changes);
+            data.networks = current.into_iter().map(|n| (n.id, n)).collect();
+        }
+
+        if let Some(formatting) = self.formatting.as_ref().filter(|_| sections.contains(&Section::Formatting)) {
+            let change = |kind| Change { section: Section::Formatting, kind, name: "шаблон".to_owned() };
+            match &data.formatting {
+                Some(current) if current == formatting => (),
+                Some(_) if strategy == Strategy::SkipExisting => changes.push(change(ChangeKind::Skipped)),
+                current => {
+                    changes.push(change(if current.is_some() { ChangeKind::Changed } else { ChangeKind::Added }));
+                    data.formatting = Some(formatting.clone());
+                },
+            }
+        }
+
+        if let Some(templates) = self.messenger_formatting.as_ref().filter(|_| sections.contains(&Section::Formatting)) {
+            if strategy == Strategy::Replace {
+                for messenger in [Messenger::Signal, Messenger::Whatsapp].into_iter().filter(|m| !templates.contains_key(m)) {
+                    if data.messenger_formatting.remove(&messenger).is_some() {
+                        changes.push(Change { section: Section::Formatting, kind: ChangeKind::Removed, name: format!("шаблон {messenger}") });
+                    }
+                }
+            }
+            let mut templates = templates.iter().collect::<Vec<_>>();
+            templates.sort_by_key(|(messenger, _)| messenger.to_string());
+            for (messenger, formatting) in templates {
+                let change = |kind| Change { section: Section::Formatting, kind, name: format!("шаблон {messenger}") };
+                match data.messenger_formatting.get(messenger) {
+                    Some(current) if current == formatting => (),
+                    Some(_) if strategy == Strategy::SkipExisting => changes.push(change(ChangeKind::Skipped)),
+                    current => {
+                        changes.push(change(if current.is_some() { ChangeKind::Changed } else { ChangeKind::Added }));
+                        data.messenger_formatting.insert(*messenger, formatting.clone());
+                    },
+                }
+            }
+        }
+
+        if let Some(groups) = self.groups.as_ref().filter(|_| sections.contains(&Section::Groups)) {
+            apply_send_modes(&mut data.groups, groups, strategy, &mut changes);
+        }
+
+        changes
+    }
+}
+
+/// Combines imported items with current ones matched by `key`, keeping order of current items
+fn apply_items<T: Clone + PartialEq, K: PartialEq>(
+    current: &mut Vec<T>,
+    imported: &[T],
+    strategy: Strategy,
+    key: impl Fn(&T) -> K,
+    name: impl Fn(&T) -> String,
+    section: Section,
+    changes: &mut Vec<Change>,
+) {
+    let mut change = |item: &T, kind| changes.push(Change { section, kind, name: name(item) });
+
+    if strategy == Strategy::Replace {
+        for item in current.iter().filter(|item| !imported.iter().any(|i| key(i) == key(item))) {
+            change(item, ChangeKind::Removed);
+        }
+        current.retain(|item| imported.iter().any(|i| key(i) == key(item)));
+    }
+
+    for item in imported {
+        match current.iter_mut().find(|c| key(c) == key(item)) {
+            Some(existing) if existing == item => (),
+            Some(_) if strategy == Strategy::SkipExisting => change(item, ChangeKind::Skipped),
+            Some(existing) => {
+                *existing = item.clone();
+                change(item, ChangeKind::Changed);
+            },
+            None => {
+                current.push(item.clone());
+                change(item, ChangeKind::Added);
+            },
+        }
+    }
+}
+
+/// Applies send modes, templates and rate limits to known groups only, as groups themselves come from messengers.
+/// Groups of another account are matched by group id
+fn apply_send_modes(current: &mut HashMap<Key, Group>, imported: &HashMap<Key, Group>, strategy: Strategy, changes: &mut Vec<Change>) {
+    let mut imported = imported.iter().collect::<Vec<_>>();
+    imported.sort_by(|(_, a), (_, b)| a.title.cmp(&b.title));
+
+    let mut matched = Vec::new();
+    for (key, group) in imported {
+        let local = match current.contains_key(key) {
+            true => Some(key.clone()),
+            false => current.keys().find(|k| k.same_group(key)).cloned(),
+        };
+        let Some(local) = local else {
+            changes.push(Change { section: Section::Groups, kind: ChangeKind::Missing, name: group.title.clone() });
+            continue
+        };
+        let existing = current.get_mut(&local).unwrap();
+        matched.push(local);
+        if existing.send_mode == group.send_mode && existing.formatting == group.formatting && existing.rate_limit == group.rate_limit {
+            continue
+        }
+        let kind = if strategy == Strategy::SkipExisting && existing.send_mode.active() {
+            ChangeKind::Skipped
+        }
+        else {
+            existing.send_mode = group.send_mode;
+            existing.formatting = group.formatting.clone();
+            existing.rate_limit = group.rate_limit;
+            ChangeKind::Changed
+        };
+        changes.push(Change { section: Section::Groups, kind, name: existing.title.clone() });
+    }
+
+    if strategy == Strategy::Replace {
+        let mut unmatched = current.iter_mut()
+            .filter(|(key, group)| group.send_mode.active() && !matched.contains(*key))
+            .map(|(_, group)| group)
+            .collect::<Vec<_>>();
+        unmatched.sort_by(|a, b| a.title.cmp(&b.title));
+        for group in unmatched {
+            group.send_mode = SendMode::Off;
+            changes.push(Change { section: Section::Groups, kind: ChangeKind::Changed, name: group.title.clone() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{crypto, message::SendMode, messangers::Key, send_categories::SendCategory, ui::main_screen::Group};
+    use super::{AppData, Change, ChangeKind, Section, SettingsPart, Strategy};
+
+    fn data() -> AppData {
+        let mut data = AppData::default();
+        data.categories.push(SendCategory::new("North".to_owned()));
+        data.groups.insert(Key::Signal(0, [1; 32]), Group::new("A".to_owned(), SendMode::Off));
+        data
+    }
+
+    #[test]
+    fn exports_only_chosen_sections() {
+        let part = SettingsPart::export(&data(), &[Section::Categories]);
+        let loaded = SettingsPart::parse(&part.to_ron().unwrap()).unwrap();
+        assert_eq!(loaded.sections(), [Section::Categories]);
+        assert_eq!(loaded.categories.unwrap()[0].name(), "North");
+    }
+
+    #[test]
+    fn full_settings_file_is_importable() {
+        let content = include_str!("golden/v1.ron");
+        let part = SettingsPart::parse(content).unwrap();
+        assert!(part.sections().contains(&Section::Categories));
+        assert!(part.sections().contains(&Section::Groups));
+    }
+
+    #[test]
+    fn strategies() {
+        let mut theirs = data();
+        theirs.categories.push(SendCategory::new("South".to_owned()));
+        theirs.categories[0].active = !theirs.categories[0].active;
+        theirs.groups.insert(Key::Signal(1, [1; 32]), Group::new("A".to_owned(), SendMode::Normal));
+        theirs.groups.insert(Key::Signal(1, [9; 32]), Group::new("B".to_owned(), SendMode::Normal));
+        theirs.groups.remove(&Key::Signal(0, [1; 32]));
+        let part = SettingsPart::export(&theirs, &Section::ALL);
+
+        let mut ours = data();
+        let changes = part.apply(&mut ours, &[Section::Categories], Strategy::SkipExisting);
+        assert_eq!(changes, [
+            Change { section: Section::Categories, kind: ChangeKind::Skipped, name: "North".to_owned() },
+            Change { section: Section::Categories, kind: ChangeKind::Added, name: "South".to_owned() },
+        ]);
+        assert_eq!(ours.categories[0], data().categories[0]);
+
+        let mut ours = data();
+        let changes = part.apply(&mut ours, &[Section::Categories, Section::Groups], Strategy::Merge);
+        assert_eq!(changes.iter().filter(|c| c.kind == ChangeKind::Changed).count(), 2);
+        assert_eq!(changes.last().unwrap().kind, ChangeKind::Missing);
+        assert_eq!(ours.categories[0], theirs.categories[0]);
+        assert_eq!(ours.groups[&Key::Signal(0, [1; 32])].send_mode, SendMode::Normal);
+    }
+}
@@ -0,0 +1,379 @@
+use std::{path::PathBuf, sync::{Arc, atomic::Ordering}, time::Duration};
+
+use clap::{Parser, Subcommand};
+use futures::{StreamExt, channel::mpsc::UnboundedReceiver};
+use qrcode::{QrCode, render::unicode::Dense1x2};
+
+use crate::{appdata::{AppData, doctor, transfer::{Section, SettingsPart, Strategy}}, message::OperatorMessage, message_server::SendRequest, messangers::{AccountId, Key, Messenger}, runtime::{Core, Job}, send_categories::RoutingInfo, ui::{Message, main_screen, message_history::{SendMessageInfo, SendStatus}, side_menu::{self, LinkState}}};
+
+/// How often sending progress is checked, as not every status change is reported
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Sends intercepted messages to Signal and Whatsapp groups")]
+pub struct Cli {
+    /// Run without GUI, controlled via HTTP API
+    #[arg(long)]
+    pub headless: bool,
+    /// Directory with settings and messenger data, `~/.sender` by default
+    #[arg(long, global = true, env = "SENDER_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// Profile to use instead of the last used one
+    #[arg(long, global = true, env = "SENDER_PROFILE")]
+    pub profile: Option<String>,
+    /// Passphrase of encrypted profile. Prefer the environment variable, command line is visible to other users
+    #[arg(long, global = true, env = "SENDER_PASSPHRASE", hide_env_values = true)]
+    pub passphrase: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Link messenger, printing QR code to the terminal
+    Link {
+        messenger: Messenger,
+        /// Account to link, the first one by default
+        #[arg(long, conflicts_with = "new_account")]
+        account: Option<AccountId>,
+        /// Add new account and link it
+        #[arg(long)]
+        new_account: bool,
+    },
+    /// Known groups
+    Groups {
+        #[command(subcommand)]
+        command: ListCommand,
+    },
+    /// Send categories
+    Categories {
+        #[command(subcommand)]
+        command: ListCommand,
+    },
+    /// Send text to groups of categories or to named groups
+    Send {
+        /// Category name, can be repeated
+        #[arg(long)]
+        category: Vec<String>,
+        /// Group title, can be repeated
+        #[arg(long)]
+        group: Vec<String>,
+        #[arg(long)]
+        text: String,
+        #[arg(long)]
+        freq: Option<String>,
+        /// Seconds to wait for messengers to connect and send
+        #[arg(long, default_value_t = 120)]
+        timeout: u64,
+    },
+    /// Send messages from file in the same format as "Message from file" in the side menu
+    SendFile {
+        path: PathBuf,
+        /// Seconds to wait for messengers to connect and send
+        #[arg(long, default_value_t = 120)]
+        timeout: u64,
+    },
+    /// Settings file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ListCommand {
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Write current settings to file
+    Export {
+        path: PathBuf,
+        /// Write only these sections, comma separated
+        #[arg(long, value_delimiter = ',')]
+        only: Vec<Section>,
+    },
+    /// Replace current settings with ones from file
+    Import {
+        path: PathBuf,
+        /// Import only these sections, comma separated, keeping the rest of current settings
+        #[arg(long, value_delimiter = ',')]
+        only: Vec<Section>,
+        /// How imported sections are combined with current ones
+        #[arg(long, requires = "only")]
+        strategy: Option<Strategy>,
+        /// Print changes without applying them
+        #[arg(long, requires = "only")]
+        dry_run: bool,
+    },
+    /// Replace current settings with the newest readable backup
+    Restore,
+    /// Check settings for unknown fields, groups and networks missing from their lists
+    Doctor {
+        /// Settings file to check instead of the current one
+        path: Option<PathBuf>,
+    },
+}
+
+pub fn run(command: Command) -> anyhow::Result<()> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(run_command(command))
+}
+
+async fn run_command(command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Link { messenger, account, new_account } => link(messenger, account, new_account).await,
+        Command::Groups { command: ListCommand::List } => {
+            let data = AppData::open()?;
+            let mut groups = data.groups.iter().collect::<Vec<_>>();
+            groups.sort_by(|(_, a), (_, b)| a.title.cmp(&b.title));
+            for (key, group) in groups {
+                let (messenger, account) = key.account();
+                let messenger = data.account(messenger, account).map_or(key.messenger(), |account| account.name.as_str());
+                println!("{messenger}\t{:?}\t{}", group.send_mode, group.title);
+            }
+            Ok(())
+        },
+        Command::Categories { command: ListCommand::List } => {
+            let data = AppData::open()?;
+            for category in data.categories.iter() {
+                let groups = category.groups.values().filter(|m| m.active()).count();
+                let state = match category.active {
+                    true if !category.is_live(jiff::Timestamp::now()) => "off schedule",
+                    true => "active",
+                    false => "inactive",
+                };
+                println!("{}\t{state}\t{groups} groups", category.name());
+            }
+            Ok(())
+        },
+        Command::Send { category, group, text, freq, timeout } => {
+            let request = SendRequest {
+                text,
+                info: RoutingInfo { freq, ..Default::default() },
+                groups: group,
+                categories: category,
+                ..Default::default()
+            };
+            let mut session = Session::start()?;
+            let (message, held) = session.core.direct_message(request)?;
+            if held > 0 {
+                println!("Held {held} messages for scheduled and digest categories");
+            }
+            let res = match message {
+                Some(message) => session.send(vec![message], Duration::from_secs(timeout)).await,
+                None => Ok(()),
+            };
+            // Keeps messages queued for categories off their schedule
+            session.core.save()?;
+            res
+        },
+        Command::SendFile { path, timeout } => {
+            let s = tokio::fs::read_to_string(&path).await?;
+            let messages = serde_json::from_str::<Vec<OperatorMessage>>(&s)?;
+            let mut session = Session::start()?;
+            session.core.accept_messages(&messages);
+            let messages = messages.iter()
+                .map(|m| session.core.route_operator_message(m))
+                .collect();
+            let res = session.send(messages, Duration::from_secs(timeout)).await;
+            session.core.save()?;
+            res
+        },
+        Command::Config { command: ConfigCommand::Export { path, only } } => {
+            let data = AppData::open()?;
+            if only.is_empty() {
+                data.save_to(&path).await?;
+            }
+            else {
+                SettingsPart::export(&data, &only).save_to(&path).await?;
+            }
+            println!("Settings exported to {}", path.display());
+            Ok(())
+        },
+        Command::Config { command: ConfigCommand::Import { path, only, .. } } if only.is_empty() => {
+            let data = AppData::load_from(&path).await?;
+            data.overwrite()?;
+            println!("Settings imported from {}", path.display());
+            Ok(())
+        },
+        Command::Config { command: ConfigCommand::Import { path, only, strategy, dry_run } } => {
+            let part = SettingsPart::load_from(&path).await?;
+            let mut data = AppData::open()?;
+            let changes = part.apply(&mut data, &only, strategy.unwrap_or_default());
+            for change in changes.iter() {
+                println!("{:?} {:?}: {}", change.kind, change.section, change.name);
+            }
+            if changes.is_empty() {
+                println!("Nothing to import from {}", path.display());
+            }
+            else if !dry_run {
+                data.save()?;
+                println!("Settings imported from {}", path.display());
+            }
+            Ok(())
+        },
+        Command::Config { command: ConfigCommand::Doctor { path } } => {
+            let path = path.unwrap_or_else(|| AppData::settings_path().to_owned());
+            let content = AppData::read(&path)?;
+            let data = AppData::parse(&content)?;
+            let issues = doctor::check(&content, &data)?;
+            for issue in issues.iter() {
+                println!("{issue}");
+            }
+            if issues.is_empty() {
+                println!("No issues found in {}", path.display());
+            }
+            Ok(())
+        },
+        Command::Config { command: ConfigCommand::Restore } => {
+            let (_, backup) = AppData::restore_backup()?;
+            println!("Settings restored from {}", backup.display());
+            Ok(())
+        },
+    }
+}
+
+async fn link(messenger: Messenger, account: Option<AccountId>, new_account: bool) -> anyhow::Result<()> {
+    let mut session = Session::start()?;
+    let account = if new_account {
+        session.core.add_account(messenger).ok_or_else(|| anyhow::anyhow!("No more {messenger} accounts can be added"))?
+    }
+    else {
+        account.unwrap_or(0)
+    };
+    match session.core.data.account(messenger, account) {
+        Some(a) if !a.logged => spawn(session.core.link(messenger, account)),
+        Some(_) => (),
+        None => anyhow::bail!("Unknown {messenger} account {account}"),
+    }
+
+    let mut linking = false;
+    while let Some(message) = session.next().await {
+        match (messenger, message) {
+            (Messenger::Signal, Message::MainScrMessage(main_screen::Message::SetRegisterUrl(Some(url)))) => {
+                print_qr("Scan QR code in Signal: Settings > Linked devices", url.as_str())?;
+            },
+            (Messenger::Whatsapp, Message::MainScrMessage(main_screen::Message::SetWhatsappUrl(Some(code)))) => {
+                print_qr("Scan QR code in Whatsapp: Settings > Linked devices", &code)?;
+            },
+            _ => (),
+        }
+
+        match session.core.link_state(messenger, account) {
+            LinkState::Linked => {
+                session.core.save()?;
+                println!("Linked to {messenger}");
+                return Ok(())
+            },
+            LinkState::Unlinked if linking => anyhow::bail!("Failed to link to {messenger}"),
+            LinkState::Unlinked => (),
+            _ => linking = true,
+        }
+    }
+    anyhow::bail!("Linking was interrupted")
+}
+
+fn print_qr(title: &str, data: &str) -> anyhow::Result<()> {
+    let code = QrCode::new(data.as_bytes())?;
+    let image = code.render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+    println!("{title}\n{image}\n{data}");
+    Ok(())
+}
+
+/// Connection to messengers without GUI
+struct Session {
+    core: Core,
+    rx: UnboundedReceiver<Message>,
+}
+
+impl Session {
+    fn start() -> anyhow::Result<Self> {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let mut core = Core::new(AppData::open()?);
+        spawn(core.setup(tx));
+        Ok(Self { core, rx })
+    }
+
+    /// Waits for the next message, applying the ones which change messengers state
+    async fn next(&mut self) -> Option<Message> {
+        let message = self.rx.next().await?;
+        if let Message::SignalMessage(account, m) = message {
+            self.core.signal(account, m);
+            return Some(Message::None)
+        }
+        match &message {
+            Message::SideMenuMessage(side_menu::Message::SetSignalState(account, state)) => self.core.set_signal_state(*account, *state),
+            Message::SideMenuMessage(side_menu::Message::SetWhatsappState(account, state)) => self.core.set_whatsapp_state(*account, *state),
+            Message::SetWhatsappClient(account, client) => {
+                let state = self.core.set_whatsapp_client(*account, client.clone());
+                self.core.set_whatsapp_state(*account, state);
+            },
+            Message::SignalDisconnected(account) => self.core.signal_disconnected(*account),
+            Message::MainScrMessage(main_screen::Message::SetGroups(groups)) => self.core.set_groups(groups.clone()),
+            Message::Notification(text) => eprintln!("{text}"),
+            _ => (),
+        }
+        Some(message)
+    }
+
+    /// Messengers user has linked before are connected
+    fn connected(&self) -> bool {
+        [Messenger::Signal, Messenger::Whatsapp].into_iter().all(|messenger| {
+            self.core.data.accounts(messenger).iter().all(|a| !a.logged || self.core.link_state(messenger, a.id) == LinkState::Linked)
+        })
+    }
+
+    async fn send(&mut self, messages: Vec<Arc<SendMessageInfo>>, timeout: Duration) -> anyhow::Result<()> {
+        if !self.core.data.any_logged(Messenger::Signal) && !self.core.data.any_logged(Messenger::Whatsapp) {
+            anyhow::bail!("No messenger is linked, use `link` command first");
+        }
+
+        let res = tokio::time::timeout(timeout, async {
+            while !self.connected() {
+                if self.next().await.is_none() {
+                    return
+                }
+            }
+            for message in messages.iter() {
+                spawn(self.core.send_message(message.clone()));
+            }
+            // Sent and failed statuses are set once every account finished sending
+            while !messages.iter().all(|m| matches!(m.status(Ordering::Relaxed), SendStatus::Sent | SendStatus::Failed | SendStatus::Deleted)) {
+                _ = tokio::time::timeout(POLL_INTERVAL, self.next()).await;
+            }
+        }).await;
+
+        let sent = messages.iter().filter(|m| m.status(Ordering::Relaxed) == SendStatus::Sent).count();
+        println!("Sent {sent} of {} messages", messages.len());
+        for message in messages.iter() {
+            let signal = message.groups_signal.iter()
+                .filter(|g| !g.sent())
+                .map(|g| (Key::Signal(g.account, g.key), g.failed()));
+            let whatsapp = message.groups_whatsapp.iter()
+                .filter(|g| !g.sent(Ordering::Relaxed))
+                .map(|g| (Key::Whatsapp(g.account, g.key.clone()), g.failed()));
+            for (key, failed) in signal.chain(whatsapp) {
+                let title = self.core.data.groups.get(&key).map_or_else(|| key.to_string(), |group| group.title.clone());
+                eprintln!("{} {title}", if failed { "Failed to send to" } else { "Not sent to" });
+            }
+        }
+        match res {
+            Ok(()) if sent == messages.len() => Ok(()),
+            Ok(()) => anyhow::bail!("Some messages were not sent"),
+            Err(_) => anyhow::bail!("Timed out waiting for messages to be sent"),
+        }
+    }
+}
+
+fn spawn(jobs: Vec<Job>) {
+    for job in jobs {
+        tokio::spawn(job);
+    }
+}
@@ -0,0 +1,206 @@
+use std::{fs::OpenOptions, io::Write, path::Path, sync::RwLock};
+
+use anyhow::{anyhow, bail};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{AeadCore, KeyInit, XChaCha20Poly1305, XNonce, aead::{Aead, OsRng, rand_core::RngCore}};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::paths;
+
+/// File in profile directory with KDF parameters. Its presence means the profile is encrypted
+const KEY_FILE: &str = "encryption.ron";
+/// Prefix of encrypted files, followed by nonce and ciphertext
+const MAGIC: &[u8] = b"MSENC\x01";
+const NONCE_LEN: usize = 24;
+/// Encrypted with the check key to verify passphrase
+const CHECK: &[u8] = b"modern-sender";
+/// Argon2id parameters for new profiles: 64 MiB of memory, 3 passes
+const MEMORY_KIB: u32 = 64 * 1024;
+const ITERATIONS: u32 = 3;
+const PARALLELISM: u32 = 1;
+
+/// Master key of unlocked profile
+static MASTER_KEY: RwLock<Option<Zeroizing<[u8; 32]>>> = RwLock::new(None);
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    /// `CHECK` encrypted with the check key
+    check: String,
+}
+
+/// Active profile is protected with passphrase
+pub fn enabled() -> bool {
+    paths::profile_file(KEY_FILE).exists()
+}
+
+pub fn unlocked() -> bool {
+    MASTER_KEY.read().unwrap().is_some()
+}
+
+/// Derives master key from passphrase, failing if passphrase is wrong
+pub fn unlock(passphrase: &str) -> anyhow::Result<()> {
+    let key_file: KeyFile = ron::from_str(&std::fs::read_to_string(paths::profile_file(KEY_FILE))?)?;
+    let master = derive(passphrase, &from_hex(&key_file.salt)?, key_file.memory_kib, key_file.iterations, key_file.parallelism)?;
+    match decrypt_with(&subkey(&master, "check"), &from_hex(&key_file.check)?) {
+        Ok(check) if check == CHECK => (),
+        _ => bail!("Wrong passphrase"),
+    }
+    *MASTER_KEY.write().unwrap() = Some(master);
+    Ok(())
+}
+
+/// Protects active profile with passphrase. Settings are encrypted on the next save,
+/// messenger stores created afterwards are encrypted too
+pub fn enable(passphrase: &str) -> anyhow::Result<()> {
+    if enabled() {
+        bail!("Encryption is already enabled");
+    }
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let master = derive(passphrase, &salt, MEMORY_KIB, ITERATIONS, PARALLELISM)?;
+    let key_file = KeyFile {
+        salt: to_hex(&salt),
+        memory_kib: MEMORY_KIB,
+        iterations: ITERATIONS,
+        parallelism: PARALLELISM,
+        check: to_hex(&encrypt_with(&subkey(&master, "check"), CHECK)?),
+    };
+    std::fs::write(paths::profile_file(KEY_FILE), ron::ser::to_string_pretty(&key_file, Default::default())?)?;
+    *MASTER_KEY.write().unwrap() = Some(master);
+    Ok(())
+}
+
+fn derive(passphrase: &str, salt: &[u8], memory_kib: u32, iterations: u32, parallelism: u32) -> anyhow::Result<Zeroizing<[u8; 32]>> {
+    let params = Params::new(memory_kib, iterations, parallelism, Some(32)).map_err(|e| anyhow!("Invalid KDF parameters: {e}"))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key[..])
+        .map_err(|e| anyhow!("Key derivation failed: {e}"))?;
+    Ok(key)
+}
+
+/// Independent key for every purpose, so settings and stores never share a key
+fn subkey(master: &[u8; 32], purpose: &str) -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(None, master)
+        .expand(purpose.as_bytes(), &mut key[..])
+        .expect("32 bytes is a valid HKDF output length");
+    key
+}
+
+fn encrypt_with(key: &[u8; 32], plain: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plain).map_err(|_| anyhow!("Encryption failed"))?;
+    Ok([nonce.as_slice(), ciphertext.as_slice()].concat())
+}
+
+fn decrypt_with(key: &[u8; 32], data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        bail!("Encrypted data is too short");
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Decryption failed, data is damaged or passphrase is wrong"))
+}
+
+fn with_master<T>(f: impl FnOnce(&[u8; 32]) -> T) -> Option<T> {
+    MASTER_KEY.read().unwrap().as_ref().map(|master| f(&**master))
+}
+
+/// Encrypts file content when profile is unlocked, otherwise returns it as is
+pub fn seal(purpose: &str, plain: &[u8]) -> anyhow::Result<Vec<u8>> {
+    match with_master(|master| encrypt_with(&subkey(master, purpose), plain)) {
+        Some(sealed) => Ok([MAGIC, sealed?.as_slice()].concat()),
+        None => Ok(plain.to_owned()),
+    }
+}
+
+/// Decrypts file content written by [`seal`], plain content is returned as is
+pub fn unseal(purpose: &str, data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    let Some(sealed) = data.strip_prefix(MAGIC) else {
+        return Ok(data)
+    };
+    with_master(|master| decrypt_with(&subkey(master, purpose), sealed))
+        .ok_or_else(|| anyhow!("Data is encrypted, passphrase is required to unlock it"))?
+}
+
+/// Raw SQLCipher key of messenger store in hex, `None` when encryption is disabled
+pub fn store_key(store: &str) -> Option<String> {
+    with_master(|master| to_hex(&subkey(master, store)[..]))
+}
+
+/// Store file exists and was created without encryption
+pub fn is_plain_sqlite(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    std::fs::File::open(path)
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut header))
+        .is_ok_and(|_| &header == b"SQLite format 3\0")
+}
+
+/// Overwrites file with zeroes before removing it, missing file is not an error.
+/// SSDs and copy-on-write filesystems may keep old blocks, only encryption fully protects from that
+pub fn wipe(path: &Path) -> std::io::Result<()> {
+    let len = match std::fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let zeroes = [0u8; 64 * 1024];
+    let mut left = len;
+    while left > 0 {
+        let n = left.min(zeroes.len() as u64) as usize;
+        file.write_all(&zeroes[.. n])?;
+        left -= n as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+    std::fs::remove_file(path)
+}
+
+/// Wipes SQLite database together with its journal files
+pub fn wipe_store(path: &Path) -> std::io::Result<()> {
+    for path in [path.to_owned(), path.with_extension("db-shm"), path.with_extension("db-wal")] {
+        wipe(&path)?;
+    }
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> anyhow::Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        bail!("Invalid hex string");
+    }
+    (0 .. s.len()).step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&s[i .. i + 2], 16)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decrypt_with, derive, encrypt_with, subkey};
+
+    #[test]
+    fn roundtrip() {
+        let master = derive("passphrase", b"0123456789abcdef", 1024, 1, 1).unwrap();
+        let key = subkey(&master, "settings");
+        let sealed = encrypt_with(&key, b"data").unwrap();
+        assert_eq!(decrypt_with(&key, &sealed).unwrap(), b"data");
+        assert!(decrypt_with(&subkey(&master, "check"), &sealed).is_err());
+
+        let other = derive("other", b"0123456789abcdef", 1024, 1, 1).unwrap();
+        assert!(decrypt_with(&subkey(&other, "settings"), &sealed).is_err());
+    }
+}
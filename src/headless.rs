@@ -0,0 +1,179 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use futures::{StreamExt, channel::mpsc::UnboundedSender};
+
+use crate::{appdata::AppData, crypto, messangers::Messenger, runtime::{self, Core, Job}, ui::{Message, main_screen, message_history::SendMessageInfo, side_menu}};
+
+const GROUP_UPDATE_INTERVAL: Duration = Duration::from_secs(180);
+const SETTINGS_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Runs sender without GUI. Received messages are sent according to categories, control is done via HTTP API
+pub fn run() -> anyhow::Result<()> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let data = AppData::open().map_err(|e| {
+        if crypto::enabled() && !crypto::unlocked() {
+            e
+        }
+        else {
+            anyhow::anyhow!("{e}. Run `modern-sender config restore` to restore the newest backup")
+        }
+    })?;
+    rt.block_on(Daemon::new(data).run());
+    Ok(())
+}
+
+struct Daemon {
+    core: Core,
+    history: VecDeque<Arc<SendMessageInfo>>,
+}
+
+impl Daemon {
+    fn new(data: AppData) -> Self {
+        Self {
+            core: Core::new(data),
+            history: VecDeque::new(),
+        }
+    }
+
+    async fn run(mut self) {
+        let (tx, mut rx) = futures::channel::mpsc::unbounded();
+        let saved_messages = std::mem::take(&mut self.core.data.saved_messages).into_iter()
+            .map(|msg_info| Arc::new(SendMessageInfo::from(msg_info)))
+            .collect();
+
+        spawn(self.core.setup(tx.clone()));
+        spawn(vec![self.core.server()]);
+        if !self.core.data.any_logged(Messenger::Signal) && !self.core.data.any_logged(Messenger::Whatsapp) {
+            log::warn!("No messenger is linked, link Signal or Whatsapp using GUI first");
+        }
+        self.handle(Message::LoadMessages(saved_messages));
+        tokio::spawn(send_periodically(tx.clone(), GROUP_UPDATE_INTERVAL, || Message::UpdateGroupList));
+        tokio::spawn(send_periodically(tx.clone(), SETTINGS_CHECK_INTERVAL, || Message::CheckSettingsFile));
+        tokio::spawn(send_periodically(tx.clone(), SCHEDULE_CHECK_INTERVAL, || Message::CheckSchedules));
+        tokio::spawn(close_on_ctrl_c(tx));
+
+        while let Some(message) = rx.next().await {
+            if !self.handle(message) {
+                break
+            }
+        }
+    }
+
+    /// Handles the same messages as GUI, which are not related to the interface.
+    /// Returns `false` when daemon should stop
+    fn handle(&mut self, message: Message) -> bool {
+        match message {
+            Message::SignalMessage(account, m) => self.core.signal(account, m),
+            Message::SignalDisconnected(account) => self.core.signal_disconnected(account),
+            Message::SetWhatsappClient(account, client) => {
+                let state = self.core.set_whatsapp_client(account, client);
+                self.core.set_whatsapp_state(account, state);
+            },
+            Message::SideMenuMessage(side_menu::Message::SetSignalState(account, state)) => self.core.set_signal_state(account, state),
+            Message::SideMenuMessage(side_menu::Message::SetWhatsappState(account, state)) => self.core.set_whatsapp_state(account, state),
+            Message::MainScrMessage(main_screen::Message::SetGroups(groups)) => self.core.set_groups(groups),
+            Message::MainScrMessage(main_screen::Message::SetRegisterUrl(Some(url))) => {
+                log::warn!("Signal is not linked, link it using GUI. Linking url: {url}");
+            },
+            Message::Synced(account) => {
+                self.core.signal_synced(account);
+                spawn(self.core.update_groups());
+            },
+            Message::UpdateGroupList => spawn(self.core.update_groups()),
+            Message::CheckSettingsFile => self.check_settings_file(),
+            Message::CheckSchedules => {
+                let mut messages = self.core.release_queued();
+                messages.extend(self.core.release_digests());
+                if !messages.is_empty() {
+                    self.save();
+                }
+                for message in messages {
+                    runtime::push_history(&mut self.history, message.clone(), self.core.data.history_len);
+                    spawn(self.core.send_message(message));
+                }
+            },
+            Message::LoadMessages(messages) => {
+                for message in messages {
+                    runtime::push_history(&mut self.history, message.clone(), self.core.data.history_len);
+                    spawn(self.core.send_message(message));
+                }
+            },
+            Message::SendMessage(message) => spawn(self.core.send_message(message)),
+            Message::DeleteMessage(message) => spawn(self.core.delete_message(message)),
+            Message::EditMessage(message, timestamps, whatsapp_ids) => spawn(self.core.edit_message(message, timestamps, whatsapp_ids)),
+            Message::CancelMessage(message) => self.core.cancel_message(message),
+            Message::AcceptMessage(messages) => {
+                self.core.accept_messages(&messages);
+                for message in messages.iter() {
+                    let message = self.core.route_operator_message(message);
+                    runtime::push_history(&mut self.history, message.clone(), self.core.data.history_len);
+                    spawn(self.core.send_message(message));
+                }
+            },
+            Message::RecivedNetworks(networks) => {
+                self.core.set_networks(networks);
+                self.save();
+            },
+            Message::Api(request) => spawn(self.core.handle_api(request, &mut self.history)),
+            Message::Notification(text) => log::info!("{text}"),
+            Message::OnClose => {
+                log::warn!("Stopping, saving data...");
+                self.core.store_unsent(&self.history);
+                self.save();
+                return false
+            },
+            _ => (),
+        }
+        true
+    }
+
+    /// Settings file changed externally is merged, values changed both via API and in the file keep API ones
+    fn check_settings_file(&mut self) {
+        match self.core.data.external_change() {
+            Ok(Some(change)) => {
+                for conflict in change.merge.conflicts.iter() {
+                    log::warn!("Settings file conflicts with current settings, keeping current value: {conflict}");
+                }
+                change.accept();
+                self.core.data = change.merge.data;
+                self.core.publish_status();
+                log::warn!("Settings file was changed externally, changes are merged");
+                self.save();
+            },
+            Ok(None) => (),
+            Err(e) => log::error!("Settings file was changed externally and can't be loaded: {e}"),
+        }
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.core.save() {
+            log::error!("Error saving data: {e}");
+        }
+    }
+}
+
+fn spawn(jobs: Vec<Job>) {
+    for job in jobs {
+        tokio::spawn(job);
+    }
+}
+
+async fn send_periodically(channel: UnboundedSender<Message>, interval: Duration, message: fn() -> Message) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if channel.unbounded_send(message()).is_err() {
+            break
+        }
+    }
+}
+
+async fn close_on_ctrl_c(channel: UnboundedSender<Message>) {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("Unable to listen for shutdown signal: {e}");
+        return
+    }
+    _ = channel.unbounded_send(Message::OnClose);
+}
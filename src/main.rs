@@ -1,17 +1,24 @@
 #![feature(string_remove_matches)]
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::panic::PanicHookInfo;
+use std::{panic::PanicHookInfo, path::PathBuf};
+use clap::Parser;
 use tracing::level_filters::LevelFilter;
 
-use crate::ui::App;
+use crate::{cli::Cli, ui::App};
 
 mod ui;
+mod cli;
+mod headless;
+mod runtime;
 mod message;
 mod messangers;
 mod message_server;
 mod send_categories;
 mod appdata;
+mod metrics;
+mod paths;
+mod crypto;
 
 fn panic_message_box(info: &PanicHookInfo) {
     rfd::MessageDialog::new()
@@ -23,11 +30,38 @@ fn panic_message_box(info: &PanicHookInfo) {
 }
 
 fn main() {
-    std::panic::set_hook(Box::new(panic_message_box));
-    let have_home = create_save_folder().is_ok();
+    let args = Cli::parse();
+    let have_home = paths::init(args.data_dir, args.profile).is_ok();
+    if let Some(passphrase) = args.passphrase.as_deref()
+        && crypto::enabled()
+        && let Err(e) = crypto::unlock(passphrase)
+    {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    if let Some(command) = args.command {
+        tracing_subscriber::FmtSubscriber::builder()
+        .with_max_level(LevelFilter::WARN)
+        .with_writer(std::io::stderr)
+        .init();
+        if let Err(e) = cli::run(command) {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+        return
+    }
+
+    let headless = args.headless;
+    if !headless {
+        std::panic::set_hook(Box::new(panic_message_box));
+    }
+
+    if headless {
+        init_headless_logging(have_home);
+    }
 
     #[cfg(debug_assertions)]
-    {
+    if !headless {
         tracing_subscriber::FmtSubscriber::builder()
         .pretty()
         .with_max_level(LevelFilter::INFO)
@@ -36,18 +70,8 @@ fn main() {
     }
 
     #[cfg(not(debug_assertions))]
-    {
-        use std::path::PathBuf;
-        let path = if have_home {
-            let mut home = std::env::home_dir().unwrap();
-            home.extend([".sender", "sender.log"]);
-            home
-        }
-        else {
-            PathBuf::from("sender.log")
-        };
-
-        let log_file = std::fs::File::create(&path).unwrap();
+    if !headless {
+        let log_file = std::fs::File::create(log_path(have_home)).unwrap();
         tracing_subscriber::FmtSubscriber::builder()
         .pretty()
         .with_max_level(LevelFilter::WARN)
@@ -62,6 +86,14 @@ fn main() {
     );
     tracing::warn!("Starting {}", title);
 
+    if headless {
+        if let Err(e) = headless::run() {
+            tracing::error!("Headless mode failed: {e}");
+            std::process::exit(1);
+        }
+        return
+    }
+
     iced::application::timed(
         App::new,
         App::update,
@@ -78,9 +110,27 @@ fn main() {
     .unwrap()
 }
 
-fn create_save_folder() -> anyhow::Result<()> {
-    let mut home = std::env::home_dir().ok_or(std::io::Error::from(std::io::ErrorKind::NotFound))?;
-    home.push(".sender");
-    std::fs::create_dir(&home)?;
-    Ok(())
+fn log_path(have_home: bool) -> PathBuf {
+    if have_home {
+        paths::profile_file("sender.log")
+    }
+    else {
+        PathBuf::from("sender.log")
+    }
+}
+
+/// Headless mode logs both to stdout and to the log file
+fn init_headless_logging(have_home: bool) {
+    use tracing_subscriber::fmt::writer::MakeWriterExt;
+
+    let writer = match std::fs::File::create(log_path(have_home)) {
+        Ok(log_file) => std::io::stdout.and(Some(log_file)),
+        Err(_) => std::io::stdout.and(None),
+    };
+    tracing_subscriber::FmtSubscriber::builder()
+    .with_max_level(LevelFilter::INFO)
+    .with_writer(writer)
+    .with_ansi(false)
+    .init();
 }
+
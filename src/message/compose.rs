@@ -12,7 +12,7 @@ use crate::message::OperatorMessage;
 #[grammar = "message/compose.pest"]
 struct FormattingParser;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Display)]
+#[derive(Debug, Clone, Serialize, Deserialize, Display, PartialEq, Eq, Hash)]
 pub enum FormatPart {
     #[display("%частота%")]
     Freq,
@@ -38,7 +38,7 @@ pub enum FormatPart {
     Literal(String),
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Formatting {
     parts: Vec<FormatPart>,
 }
@@ -127,4 +127,15 @@ impl Formatting {
 
         res
     }
+
+    /// Renders `text` typed by hand, codes of operator message fields are left empty
+    pub fn format_text(&self, text: &str) -> String {
+        self.parts.iter()
+            .map(|part| match part {
+                FormatPart::Text => text,
+                FormatPart::Literal(s) => s,
+                _ => "",
+            })
+            .collect()
+    }
 }
@@ -20,6 +20,7 @@ pub static TEST_MESSAGE: LazyLock<Message> = LazyLock::new(|| Message(MessageInn
     title: "УКХ р/м управління підрозділу".to_owned(),
     source: "Прийшло з апарату".to_owned(),
     network_id: None,
+    priority: None,
 }));
 
 #[derive(Deserialize, Display, Debug, Default)]
@@ -65,6 +66,8 @@ pub struct MessageInner {
     pub source: String,
     #[serde(rename = "radionetworkID")]
     pub network_id: Option<u64>,
+    /// Priority set by operator, raises priority given by categories
+    pub priority: Option<super::Priority>,
 }
 
 impl Display for MessageInner {
@@ -101,6 +104,13 @@ struct MessageOuter {
 #[serde(transparent)]
 pub struct MessageGroup(Vec<IndividualMessage>);
 
+impl MessageGroup {
+    /// Texts of individual intercepted messages
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|m| &**m)
+    }
+}
+
 impl Display for MessageGroup {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let last_index = self.0.len() - 1;
@@ -31,7 +31,7 @@ fn construct_ranges<'a>(pairs: impl Iterator<Item = Pair<'a, Rule>>) -> (String,
                 message_parts.push(text);
             },
             Rule::bold => {
-                match bold {
+                match bold.take() {
                     Some(start) => ranges.push(
                         BodyRange {
                             start: Some(start),
@@ -45,7 +45,7 @@ fn construct_ranges<'a>(pairs: impl Iterator<Item = Pair<'a, Rule>>) -> (String,
                 }
             },
             Rule::italic => {
-                match italic {
+                match italic.take() {
                     Some(start) => ranges.push(
                         BodyRange {
                             start: Some(start),
@@ -59,7 +59,7 @@ fn construct_ranges<'a>(pairs: impl Iterator<Item = Pair<'a, Rule>>) -> (String,
                 }
             },
             Rule::strikethrough => {
-                match strikethrough {
+                match strikethrough.take() {
                     Some(start) => ranges.push(
                         BodyRange {
                             start: Some(start),
@@ -73,7 +73,7 @@ fn construct_ranges<'a>(pairs: impl Iterator<Item = Pair<'a, Rule>>) -> (String,
                 }
             },
             Rule::monospace => {
-                match monospace {
+                match monospace.take() {
                     Some(start) => ranges.push(
                         BodyRange {
                             start: Some(start),
@@ -104,21 +104,6 @@ pub fn parse_message_with_format(message: &str) -> Result<(String, Vec<BodyRange
     )
 }
 
-pub fn parse_message_with_whatsapp_format(message: &str) -> Result<String, pest::error::Error<Rule>> {
-    let mut buf = String::with_capacity(message.len());
-    for rule in parse_into_rules(message)? {
-        match rule.as_rule() {
-            Rule::text => buf.push_str(rule.as_str()),
-            Rule::bold => buf.push('*'),
-            Rule::italic => buf.push('_'),
-            Rule::strikethrough => buf.push('~'),
-            Rule::monospace => buf.push_str("```"),
-            _ => continue,
-        }
-    }
-    Ok(buf)
-}
-
 #[cfg(test)]
 mod test {
     use crate::message::format::parse_message_with_format;
@@ -133,4 +118,14 @@ mod test {
             println!("{:?}: {}", range.associated_value, &message[range.start.unwrap() as usize .. (range.start.unwrap() + range.length.unwrap()) as usize]);
         }
     }
+
+    #[test]
+    fn repeated_style() {
+        let (message, ranges) = parse_message_with_format("**a** b **c** and *x* and *y*").unwrap();
+        assert_eq!(message, "a b c and x and y");
+        let styled = ranges.iter()
+            .map(|range| &message[range.start() as usize..(range.start() + range.length()) as usize])
+            .collect::<Vec<_>>();
+        assert_eq!(styled, ["a", "c", "x", "y"]);
+    }
 }
@@ -1,11 +1,13 @@
 mod deserialize;
 mod format;
 mod compose;
+mod split;
 
 #[allow(unused)]
 pub use deserialize::{Message as OperatorMessage, MessageInner, TEST_MESSAGE};
-pub use format::{parse_message_with_format, parse_message_with_whatsapp_format};
+pub use format::parse_message_with_format;
 pub use compose::{FormatPart, Formatting};
+pub use split::{SIGNAL_MAX_LENGTH, WHATSAPP_MAX_LENGTH, markup_parts, message_parts};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -27,3 +29,22 @@ impl SendMode {
         }
     }
 }
+
+/// Order messages are sent in, messages of higher priority go first
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, Hash, PartialEq, Eq, PartialOrd, Ord, derive_more::Display)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    #[display("Низький")]
+    Low,
+    #[default]
+    #[display("Звичайний")]
+    Normal,
+    #[display("Високий")]
+    High,
+    #[display("Терміновий")]
+    Urgent,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [Priority::Low, Priority::Normal, Priority::High, Priority::Urgent];
+}
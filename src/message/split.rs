@@ -0,0 +1,172 @@
+use presage::proto::{BodyRange, body_range::{AssociatedValue, Style}};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::message::parse_message_with_format;
+
+/// Longest message body sent to Signal, in graphemes like style ranges
+pub const SIGNAL_MAX_LENGTH: usize = 2000;
+/// Longest message body sent to Whatsapp, in graphemes
+pub const WHATSAPP_MAX_LENGTH: usize = 4096;
+/// Room left in every part for its number like "(12/15)\n"
+const LABEL_RESERVE: usize = 10;
+
+/// Parts `content` is sent to a group as. `freq` is put above the first part, markdown is turned into style ranges
+pub fn message_parts(content: &str, freq: Option<&str>, markdown: bool, max: usize) -> Vec<(String, Vec<BodyRange>)> {
+    let (body, ranges) = if markdown {
+        parse_message_with_format(content).unwrap_or_else(|_| (content.to_owned(), Vec::new()))
+    }
+    else {
+        (content.to_owned(), Vec::new())
+    };
+    match freq {
+        Some(freq) => {
+            let shift = freq.graphemes(true).count() as u32 + 1;
+            let ranges = ranges.into_iter()
+                .map(|range| BodyRange { start: Some(range.start() + shift), ..range })
+                .collect::<Vec<_>>();
+            split_message(&format!("{freq}\n{body}"), &ranges, max)
+        },
+        None => split_message(&body, &ranges, max),
+    }
+}
+
+/// Splits body longer than `max` graphemes into parts numbered like "(1/3)", cutting at line ends when possible.
+/// Style ranges are cut at part boundaries and moved to stay over the same text
+pub fn split_message(body: &str, ranges: &[BodyRange], max: usize) -> Vec<(String, Vec<BodyRange>)> {
+    let graphemes = body.graphemes(true).collect::<Vec<_>>();
+    if graphemes.len() <= max {
+        return vec![(body.to_owned(), ranges.to_vec())]
+    }
+
+    let budget = max.saturating_sub(LABEL_RESERVE).max(1);
+    // Parts as grapheme ranges, line breaks parts are cut at are dropped
+    let mut bounds = Vec::new();
+    let mut start = 0;
+    while start < graphemes.len() {
+        let limit = (start + budget).min(graphemes.len());
+        if limit == graphemes.len() {
+            bounds.push((start, limit));
+            break
+        }
+        let line_end = graphemes[start..=limit].iter().rposition(|g| *g == "\n" || *g == "\r\n").filter(|&i| i > 0);
+        let space = graphemes[start..limit].iter().rposition(|g| g.trim().is_empty()).filter(|&i| i > 0);
+        match line_end.or(space) {
+            Some(i) => {
+                bounds.push((start, start + i));
+                start += i + 1;
+            },
+            None => {
+                bounds.push((start, limit));
+                start = limit;
+            },
+        }
+    }
+
+    let count = bounds.len();
+    bounds.into_iter()
+        .enumerate()
+        .map(|(i, (start, end))| {
+            let label = format!("({}/{count})\n", i + 1);
+            let shift = label.graphemes(true).count() as u32;
+            let ranges = ranges.iter()
+                .filter_map(|range| {
+                    let range_start = (range.start() as usize).max(start);
+                    let range_end = ((range.start() + range.length()) as usize).min(end);
+                    (range_start < range_end).then(|| BodyRange {
+                        start: Some((range_start - start) as u32 + shift),
+                        length: Some((range_end - range_start) as u32),
+                        associated_value: range.associated_value.clone(),
+                    })
+                })
+                .collect();
+            (label + &graphemes[start..end].concat(), ranges)
+        })
+        .collect()
+}
+
+/// Parts of `content` written with Whatsapp markup. Markers are counted too, so parts are cut shorter until each fits into `max`
+pub fn markup_parts(content: &str, freq: Option<&str>, markdown: bool, max: usize) -> Vec<String> {
+    let mut limit = max;
+    loop {
+        let parts = message_parts(content, freq, markdown, limit).into_iter()
+            .map(|(body, ranges)| whatsapp_markup(&body, &ranges))
+            .collect::<Vec<_>>();
+        let longest = parts.iter().map(|part| part.graphemes(true).count()).max().unwrap_or(0);
+        if longest <= max || limit <= LABEL_RESERVE + 1 {
+            return parts
+        }
+        limit = limit.saturating_sub(longest - max).max(LABEL_RESERVE + 1);
+    }
+}
+
+/// Writes style ranges as Whatsapp markup
+pub fn whatsapp_markup(body: &str, ranges: &[BodyRange]) -> String {
+    let marker = |range: &BodyRange| match range.associated_value {
+        Some(AssociatedValue::Style(style)) => match Style::try_from(style) {
+            Ok(Style::Bold) => "*",
+            Ok(Style::Italic) => "_",
+            Ok(Style::Strikethrough) => "~",
+            Ok(Style::Monospace) => "```",
+            _ => "",
+        },
+        _ => "",
+    };
+    // Ranges closing earlier are closed first and opened last, so markers stay nested
+    let mut opens = ranges.iter().filter(|range| range.length() > 0).collect::<Vec<_>>();
+    opens.sort_by_key(|range| (range.start(), std::cmp::Reverse(range.start() + range.length())));
+    let mut closes = opens.clone();
+    closes.sort_by_key(|range| (range.start() + range.length(), std::cmp::Reverse(range.start())));
+
+    let mut buf = String::with_capacity(body.len());
+    let (mut opens, mut closes) = (opens.into_iter().peekable(), closes.into_iter().peekable());
+    for (i, grapheme) in body.graphemes(true).map(Some).chain([None]).enumerate() {
+        let i = i as u32;
+        while let Some(range) = closes.next_if(|range| range.start() + range.length() == i) {
+            buf.push_str(marker(range));
+        }
+        while let Some(range) = opens.next_if(|range| range.start() == i) {
+            buf.push_str(marker(range));
+        }
+        if let Some(grapheme) = grapheme {
+            buf.push_str(grapheme);
+        }
+    }
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    use super::{markup_parts, message_parts, split_message, whatsapp_markup};
+
+    #[test]
+    fn split_keeps_ranges() {
+        let text = format!("**{}\n{}** end", "a".repeat(20), "b".repeat(20));
+        let parts = message_parts(&text, Some("145.100"), true, 40);
+        let texts = parts.iter().map(|(body, _)| body.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, [format!("(1/2)\n145.100\n{}", "a".repeat(20)), format!("(2/2)\n{} end", "b".repeat(20))]);
+
+        let styled = |(body, ranges): &(String, Vec<presage::proto::BodyRange>)| ranges.iter()
+            .map(|r| body[r.start() as usize..(r.start() + r.length()) as usize].to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(styled(&parts[0]), ["a".repeat(20)]);
+        assert_eq!(styled(&parts[1]), ["b".repeat(20)]);
+        assert_eq!(whatsapp_markup(&parts[1].0, &parts[1].1), format!("(2/2)\n*{}* end", "b".repeat(20)));
+    }
+
+    #[test]
+    fn short_message_is_whole() {
+        assert_eq!(split_message("short", &[], 100), [("short".to_owned(), Vec::new())]);
+        assert_eq!(split_message(&"x".repeat(30), &[], 20).len(), 3);
+    }
+
+    #[test]
+    fn markup_fits_limit() {
+        let text = "**a** ".repeat(8);
+        assert_eq!(message_parts(&text, None, true, 20).len(), 1);
+        let parts = markup_parts(&text, None, true, 20);
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|part| part.graphemes(true).count() <= 20));
+    }
+}
@@ -1,13 +1,64 @@
-use std::{net::SocketAddrV4, time::Duration};
+mod api;
+use std::{net::SocketAddrV4, sync::{Arc, LazyLock, RwLock}, time::Duration};
 
-use axum::{Router, routing::post, http::StatusCode};
-use futures::{SinkExt, channel::mpsc::UnboundedSender};
+pub use api::{ApiError, ApiReply, ApiRequest, CategoryDto, GroupDto, MessageId, SendRequest, SendResponse, AccountStatusDto, RouteDto, StatusDto};
+use axum::{Json, Router, extract::{FromRequestParts, Path, State}, http::{HeaderMap, HeaderName, StatusCode, header::{AUTHORIZATION, CONTENT_TYPE}, request::Parts}, response::Html, routing::{get, patch, post}};
+use futures::{SinkExt, channel::{mpsc::UnboundedSender, oneshot}};
 use log::info;
+use subtle::ConstantTimeEq;
 use tokio::net::TcpListener;
 
-use crate::{message::OperatorMessage, send_categories::parse_networks_data, ui};
+use crate::{message::OperatorMessage, metrics::METRICS, send_categories::{NetworkInfo, parse_networks_data}, ui};
+use api::EditRequest;
 
-pub async fn start_server(addr: SocketAddrV4, mut msg_send_channel: UnboundedSender<crate::ui::Message>) -> anyhow::Result<()> {
+const OPENAPI_TEMPLATE: &str = include_str!("message_server/openapi.json");
+const DOCS_PAGE: &str = include_str!("message_server/docs.html");
+
+/// OpenAPI document with the actual application version
+static OPENAPI: LazyLock<String> = LazyLock::new(|| {
+    let mut doc: serde_json::Value = serde_json::from_str(OPENAPI_TEMPLATE).expect("OpenAPI document must be valid JSON");
+    doc["info"]["version"] = env!("CARGO_PKG_VERSION").into();
+    doc.to_string()
+});
+
+/// Status of accounts published by the application, `None` until it is set up
+pub static STATUS: RwLock<Option<StatusDto>> = RwLock::new(None);
+
+#[derive(Clone)]
+struct ServerState {
+    msg_send_channel: UnboundedSender<ui::Message>,
+    api_token: Option<Arc<str>>,
+}
+
+impl ServerState {
+    /// Passes request to the application and waits for its answer
+    async fn request<T>(&self, request: impl FnOnce(ApiReply<T>) -> ApiRequest) -> Result<T, ApiError> {
+        let (tx, rx) = oneshot::channel();
+        self.msg_send_channel.clone().send(ui::Message::Api(request(tx))).await.map_err(|_| ApiError::Unavailable)?;
+        rx.await.map_err(|_| ApiError::Unavailable)?
+    }
+}
+
+/// Extractor which checks that request carries configured API token
+struct Authorized;
+
+impl FromRequestParts<ServerState> for Authorized {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &ServerState) -> Result<Self, Self::Rejection> {
+        let token = state.api_token.as_deref().ok_or(ApiError::NoToken)?;
+        let provided = parts.headers.get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+
+        match provided {
+            Some(provided) if bool::from(provided.as_bytes().ct_eq(token.as_bytes())) => Ok(Self),
+            _ => Err(ApiError::Unauthorized),
+        }
+    }
+}
+
+pub async fn start_server(addr: SocketAddrV4, api_token: Option<String>, mut msg_send_channel: UnboundedSender<crate::ui::Message>) -> anyhow::Result<()> {
     info!("Binding on addr {}", &addr);
     let listener = loop {
         match TcpListener::bind(&addr).await {
@@ -22,39 +73,233 @@ pub async fn start_server(addr: SocketAddrV4, mut msg_send_channel: UnboundedSen
         }
     };
 
-    let mut msg_send_channel2 = msg_send_channel.clone();
-    let router = Router::new()
-    .route("/", post(move |s: String| async move {
-        log::info!("Got messages on server");
-        let message = match serde_json::from_str::<Vec<OperatorMessage>>(&s) {
-            Ok(msgs) => msgs,
-            Err(e) => {
-                log::error!("Message parse error: {e}");
-                vec![]
-            }
-        };
-
-        msg_send_channel.send(ui::Message::AcceptMessage(message)).await.unwrap();
-
-        (StatusCode::OK, "Recieved".to_owned())
-    }))
-    .route("/networks", post(move |s: String| async move {
-        log::info!("Got networks on server");
-        let networks = match parse_networks_data(&s) {
-            Ok(networks) => networks,
-            Err(e) => {
-                log::error!("Error parsing networks: {}", &e);
-                msg_send_channel2.send(ui::Message::Notification(format!("Error parsing networks: {}", &e))).await.unwrap();
-                return (StatusCode::BAD_REQUEST, "Invalid data".to_owned())
-            }
-        };
-
-        msg_send_channel2.send(ui::Message::RecivedNetworks(networks)).await.unwrap();
+    let state = ServerState {
+        msg_send_channel,
+        api_token: api_token.filter(|t| !t.is_empty()).map(Into::into),
+    };
 
-        (StatusCode::OK, "Recieved".to_owned())
-    }));
+    let router = Router::new()
+    .route("/", post(accept_messages))
+    .route("/networks", post(accept_networks).get(get_networks).put(set_networks))
+    .route("/send", post(send_message))
+    .route("/route/preview", post(route_preview))
+    .route("/messages/{id}", patch(edit_message).delete(delete_message))
+    .route("/categories", get(get_categories).post(create_category))
+    .route("/categories/{name}", get(get_category).put(update_category).delete(delete_category))
+    .route("/groups", get(get_groups))
+    .route("/openapi.json", get(|| async { ([(CONTENT_TYPE, "application/json")], OPENAPI.as_str()) }))
+    .route("/docs", get(|| async { Html(DOCS_PAGE) }))
+    .route("/healthz", get(|| async { "OK" }))
+    .route("/readyz", get(readiness))
+    .route("/metrics", get(metrics))
+    .with_state(state);
 
     info!("Starting serving");
     axum::serve(listener, router).await?;
     Ok(())
 }
+
+async fn accept_messages(State(state): State<ServerState>, s: String) -> (StatusCode, String) {
+    log::info!("Got messages on server");
+    let message = match serde_json::from_str::<Vec<OperatorMessage>>(&s) {
+        Ok(msgs) => msgs,
+        Err(e) => {
+            log::error!("Message parse error: {e}");
+            vec![]
+        }
+    };
+
+    state.msg_send_channel.clone().send(ui::Message::AcceptMessage(message)).await.unwrap();
+
+    (StatusCode::OK, "Recieved".to_owned())
+}
+
+async fn accept_networks(State(state): State<ServerState>, s: String) -> (StatusCode, String) {
+    log::info!("Got networks on server");
+    let mut msg_send_channel = state.msg_send_channel.clone();
+    let networks = match parse_networks_data(&s) {
+        Ok(networks) => networks,
+        Err(e) => {
+            log::error!("Error parsing networks: {}", &e);
+            msg_send_channel.send(ui::Message::Notification(format!("Error parsing networks: {}", &e))).await.unwrap();
+            return (StatusCode::BAD_REQUEST, "Invalid data".to_owned())
+        }
+    };
+
+    msg_send_channel.send(ui::Message::RecivedNetworks(networks)).await.unwrap();
+
+    (StatusCode::OK, "Recieved".to_owned())
+}
+
+/// Accepts either plain text or JSON `SendRequest`, depending on content type
+async fn send_message(
+    _: Authorized,
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<(StatusCode, Json<SendResponse>), ApiError> {
+    log::info!("Got direct message on server");
+    let is_json = headers.get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    let request = if is_json {
+        serde_json::from_str::<SendRequest>(&body).map_err(|e| ApiError::BadRequest(e.to_string()))?
+    }
+    else {
+        SendRequest::from(body)
+    };
+    if request.text.trim().is_empty() {
+        return Err(ApiError::BadRequest("Message text is empty".to_owned()));
+    }
+
+    let res = state.request(|reply| ApiRequest::Send(request, reply)).await?;
+    Ok((StatusCode::ACCEPTED, Json(res)))
+}
+
+/// Same body as `/send`, answers with groups message would be sent to
+async fn route_preview(
+    _: Authorized,
+    State(state): State<ServerState>,
+    Json(request): Json<SendRequest>,
+) -> Result<Json<Vec<RouteDto>>, ApiError> {
+    Ok(Json(state.request(|reply| ApiRequest::RoutePreview(request, reply)).await?))
+}
+
+async fn edit_message(
+    _: Authorized,
+    State(state): State<ServerState>,
+    Path(id): Path<u64>,
+    Json(request): Json<EditRequest>,
+) -> Result<(StatusCode, Json<MessageId>), ApiError> {
+    log::info!("Got edit request for message {id}");
+    let res = state.request(|reply| ApiRequest::EditMessage(id, request.text, reply)).await?;
+    Ok((StatusCode::ACCEPTED, Json(res)))
+}
+
+async fn delete_message(
+    _: Authorized,
+    State(state): State<ServerState>,
+    Path(id): Path<u64>,
+) -> Result<(StatusCode, Json<MessageId>), ApiError> {
+    log::info!("Got delete request for message {id}");
+    let res = state.request(|reply| ApiRequest::DeleteMessage(id, reply)).await?;
+    Ok((StatusCode::ACCEPTED, Json(res)))
+}
+
+async fn get_categories(
+    _: Authorized,
+    State(state): State<ServerState>,
+) -> Result<Json<Vec<CategoryDto>>, ApiError> {
+    Ok(Json(state.request(ApiRequest::GetCategories).await?))
+}
+
+async fn get_category(
+    _: Authorized,
+    State(state): State<ServerState>,
+    Path(name): Path<String>,
+) -> Result<Json<CategoryDto>, ApiError> {
+    state.request(ApiRequest::GetCategories).await?
+    .into_iter()
+    .find(|c| c.name == name)
+    .map(Json)
+    .ok_or(ApiError::NotFound)
+}
+
+async fn create_category(
+    _: Authorized,
+    State(state): State<ServerState>,
+    Json(category): Json<CategoryDto>,
+) -> Result<(StatusCode, Json<CategoryDto>), ApiError> {
+    let res = state.request(|reply| ApiRequest::CreateCategory(category, reply)).await?;
+    Ok((StatusCode::CREATED, Json(res)))
+}
+
+async fn update_category(
+    _: Authorized,
+    State(state): State<ServerState>,
+    Path(name): Path<String>,
+    Json(category): Json<CategoryDto>,
+) -> Result<Json<CategoryDto>, ApiError> {
+    Ok(Json(state.request(|reply| ApiRequest::UpdateCategory(name, category, reply)).await?))
+}
+
+async fn delete_category(
+    _: Authorized,
+    State(state): State<ServerState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    state.request(|reply| ApiRequest::DeleteCategory(name, reply)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_groups(
+    _: Authorized,
+    State(state): State<ServerState>,
+) -> Result<Json<Vec<GroupDto>>, ApiError> {
+    Ok(Json(state.request(ApiRequest::GetGroups).await?))
+}
+
+async fn get_networks(
+    _: Authorized,
+    State(state): State<ServerState>,
+) -> Result<Json<Vec<NetworkInfo>>, ApiError> {
+    Ok(Json(state.request(ApiRequest::GetNetworks).await?))
+}
+
+/// Unlike `POST /networks`, accepts networks in the same short form they are stored in
+async fn set_networks(
+    _: Authorized,
+    State(state): State<ServerState>,
+    Json(networks): Json<Vec<NetworkInfo>>,
+) -> Result<StatusCode, ApiError> {
+    state.request(|reply| ApiRequest::SetNetworks(networks, reply)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Reads published status, so it answers even when the application is busy
+async fn readiness() -> Result<(StatusCode, Json<StatusDto>), ApiError> {
+    let status = STATUS.read().unwrap().clone().ok_or(ApiError::Unavailable)?;
+    let code = if status.ready() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    Ok((code, Json(status)))
+}
+
+async fn metrics() -> ([(HeaderName, &'static str); 1], String) {
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], METRICS.render())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use regex::Regex;
+
+    use super::OPENAPI_TEMPLATE;
+
+    /// Every route method is documented, and token is required in the document exactly where its handler takes `Authorized`
+    #[test]
+    fn openapi_describes_all_routes() {
+        let doc: serde_json::Value = serde_json::from_str(OPENAPI_TEMPLATE).unwrap();
+        let source = include_str!("message_server.rs");
+        let handler = Regex::new(r"\b(get|post|put|patch|delete)\((\w+)?").unwrap();
+
+        let mut routes = HashSet::new();
+        for line in source.lines().filter_map(|line| line.trim().strip_prefix(".route(\"")) {
+            let (path, handlers) = line.split_once('"').unwrap();
+            for capture in handler.captures_iter(handlers) {
+                // Closures don't take extractors
+                let protected = capture.get(2)
+                    .and_then(|name| source.split_once(&format!("fn {}(", name.as_str())))
+                    .is_some_and(|(_, rest)| rest[.. rest.find('{').unwrap()].contains("Authorized"));
+                routes.insert((path.to_owned(), capture[1].to_owned(), protected));
+            }
+        }
+
+        let documented = doc["paths"].as_object().unwrap().iter()
+            .flat_map(|(path, item)| item.as_object().unwrap().iter()
+                .filter(|(method, _)| *method != "parameters")
+                .map(move |(method, operation)| (path.clone(), method.clone(), operation.get("security").is_some()))
+            )
+            .collect::<HashSet<_>>();
+        assert_eq!(routes, documented);
+    }
+}
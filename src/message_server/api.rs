@@ -0,0 +1,289 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{http::StatusCode, response::{IntoResponse, Response}};
+use derive_more::Display;
+use futures::channel::oneshot;
+use serde::{Deserialize, Serialize};
+
+use crate::{message::{Formatting, Priority, SendMode}, messangers::{AccountId, Key}, send_categories::{Destination, Digest, NetworkInfo, Parameters, RouteReason, RoutingInfo, Rule, Schedule, SendCategory}, ui::{main_screen::Group, side_menu::LinkState}};
+
+/// Channel used by the UI to answer a request made through the HTTP API
+pub type ApiReply<T> = oneshot::Sender<Result<T, ApiError>>;
+
+/// Requests from the HTTP API, which need access to the application state
+#[derive(Debug)]
+pub enum ApiRequest {
+    Send(SendRequest, ApiReply<SendResponse>),
+    EditMessage(u64, String, ApiReply<MessageId>),
+    DeleteMessage(u64, ApiReply<MessageId>),
+    GetCategories(ApiReply<Vec<CategoryDto>>),
+    CreateCategory(CategoryDto, ApiReply<CategoryDto>),
+    /// Replaces category with given name
+    UpdateCategory(String, CategoryDto, ApiReply<CategoryDto>),
+    DeleteCategory(String, ApiReply<()>),
+    GetGroups(ApiReply<Vec<GroupDto>>),
+    GetNetworks(ApiReply<Vec<NetworkInfo>>),
+    SetNetworks(Vec<NetworkInfo>, ApiReply<()>),
+    /// Explains where message would be routed, without sending it
+    RoutePreview(SendRequest, ApiReply<Vec<RouteDto>>),
+}
+
+#[derive(Debug, Display)]
+pub enum ApiError {
+    #[display("Unauthorized")]
+    Unauthorized,
+    #[display("API token is not configured")]
+    NoToken,
+    #[display("Not found")]
+    NotFound,
+    #[display("{_0}")]
+    Conflict(String),
+    #[display("{_0}")]
+    BadRequest(String),
+    #[display("Application is not available")]
+    Unavailable,
+}
+
+impl std::error::Error for ApiError {}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::NoToken => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// Message sent directly, without operator message schema.
+/// If `groups` and `categories` are empty, message is routed by categories as usual
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct SendRequest {
+    pub text: String,
+    /// Message properties categories are matched against, `freq` is also shown with the message
+    #[serde(flatten)]
+    pub info: RoutingInfo,
+    /// Titles of groups to send to
+    pub groups: Vec<String>,
+    /// Names of categories to send to
+    pub categories: Vec<String>,
+}
+
+impl From<String> for SendRequest {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EditRequest {
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageId {
+    pub id: u64,
+}
+
+/// Answer to `/send`. Message which was only held for schedules or digests has no id
+#[derive(Debug, Serialize)]
+pub struct SendResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    /// Groups the message is being sent to right away
+    pub groups: usize,
+    /// Queued messages and digest items created for categories off their schedule or in digest mode
+    pub held: usize,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// `SendCategory` representation with group keys in text form
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategoryDto {
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub active: bool,
+    #[serde(default = "default_true")]
+    pub use_general: bool,
+    #[serde(default)]
+    pub rule: Rule,
+    /// Matching parameters sent by clients written before rules were added, converted to `rule`
+    #[serde(default, skip_serializing)]
+    pub parameters: Option<Parameters>,
+    #[serde(default)]
+    pub groups: HashMap<String, SendMode>,
+    /// Keys of groups never sent messages the category matches
+    #[serde(default)]
+    pub excluded: Vec<String>,
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    /// Template operator messages are rendered with, global one is used when absent
+    #[serde(default)]
+    pub formatting: Option<String>,
+    /// Messages the category matches are sent before ones of lower priority
+    #[serde(default)]
+    pub priority: Priority,
+    /// Matched messages are collected and sent as one message
+    #[serde(default)]
+    pub digest: Option<Digest>,
+}
+
+impl From<&SendCategory> for CategoryDto {
+    fn from(value: &SendCategory) -> Self {
+        Self {
+            name: value.name().to_owned(),
+            active: value.active,
+            use_general: value.use_general,
+            rule: value.rule.clone(),
+            parameters: None,
+            groups: value.groups.iter().map(|(key, mode)| (key.to_string(), *mode)).collect(),
+            excluded: value.excluded.iter().map(Key::to_string).collect(),
+            schedule: value.schedule.clone(),
+            formatting: value.formatting.as_ref().map(Formatting::to_string),
+            priority: value.priority,
+            digest: value.digest.clone(),
+        }
+    }
+}
+
+impl TryFrom<CategoryDto> for SendCategory {
+    type Error = ApiError;
+
+    fn try_from(value: CategoryDto) -> Result<Self, Self::Error> {
+        if value.name.is_empty() {
+            return Err(ApiError::BadRequest("Category name is empty".to_owned()));
+        }
+        let mut category = SendCategory::new(value.name);
+        category.active = value.active;
+        category.use_general = value.use_general;
+        category.rule = value.parameters.map(Rule::from).unwrap_or(value.rule);
+        category.groups = value.groups.into_iter()
+            .map(|(key, mode)| Ok((key.parse::<Key>().map_err(|e| ApiError::BadRequest(e.to_string()))?, mode)))
+            .collect::<Result<_, ApiError>>()?;
+        if let Some(schedule) = &value.schedule && schedule.time_zone().is_none() {
+            return Err(ApiError::BadRequest(format!("Unknown time zone: {}", schedule.timezone)));
+        }
+        category.schedule = value.schedule;
+        category.formatting = value.formatting
+            .map(|f| f.parse::<Formatting>().map_err(|e| ApiError::BadRequest(e.to_string())))
+            .transpose()?;
+        category.priority = value.priority;
+        category.digest = value.digest;
+        category.excluded = value.excluded.into_iter()
+            .map(|key| key.parse::<Key>().map_err(|e| ApiError::BadRequest(e.to_string())))
+            .collect::<Result<_, ApiError>>()?;
+        Ok(category)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupDto {
+    pub key: String,
+    pub title: String,
+    pub messenger: &'static str,
+    pub send_mode: SendMode,
+    /// Group is in the global blocklist and never receives messages
+    pub blocked: bool,
+    /// Most messages sent to the group per minute
+    pub rate_limit: Option<u32>,
+}
+
+impl GroupDto {
+    pub fn new(key: &Key, group: &Group, blocked: &HashSet<Key>) -> Self {
+        Self {
+            key: key.to_string(),
+            title: group.title.clone(),
+            messenger: key.messenger(),
+            send_mode: group.send_mode,
+            blocked: blocked.contains(key),
+            rate_limit: group.rate_limit,
+        }
+    }
+}
+
+/// Group message would be sent to and what caused it
+#[derive(Debug, Serialize)]
+pub struct RouteDto {
+    pub key: String,
+    pub title: String,
+    pub messenger: &'static str,
+    pub send_mode: SendMode,
+    pub reasons: Vec<RouteReasonDto>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RouteReasonDto {
+    pub kind: RouteReasonKind,
+    /// Category which included or excluded the group
+    pub category: Option<String>,
+    pub send_mode: SendMode,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteReasonKind {
+    Category,
+    General,
+    Excluded,
+    Blocked,
+    Queued,
+    Digest,
+}
+
+impl RouteDto {
+    pub fn new(key: &Key, title: String, destination: Destination) -> Self {
+        Self {
+            key: key.to_string(),
+            title,
+            messenger: key.messenger(),
+            send_mode: destination.mode,
+            reasons: destination.reasons.into_iter()
+                .map(|(reason, send_mode)| {
+                    let (kind, category) = match reason {
+                        RouteReason::Category(name) => (RouteReasonKind::Category, Some(name)),
+                        RouteReason::General => (RouteReasonKind::General, None),
+                        RouteReason::Excluded(name) => (RouteReasonKind::Excluded, Some(name)),
+                        RouteReason::Blocked => (RouteReasonKind::Blocked, None),
+                        RouteReason::Queued(name) => (RouteReasonKind::Queued, Some(name)),
+                        RouteReason::Digest(name) => (RouteReasonKind::Digest, Some(name)),
+                    };
+                    RouteReasonDto { kind, category, send_mode }
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusDto {
+    pub signal: Vec<AccountStatusDto>,
+    pub whatsapp: Vec<AccountStatusDto>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountStatusDto {
+    pub account: AccountId,
+    pub name: String,
+    pub state: LinkState,
+    /// Account has received all queued messages after linking. Whatsapp accounts are synced once linked
+    pub synced: bool,
+}
+
+impl StatusDto {
+    /// At least one account is able to send messages
+    pub fn ready(&self) -> bool {
+        self.signal.iter().chain(self.whatsapp.iter()).any(|s| s.state == LinkState::Linked && s.synced)
+    }
+}
@@ -1,3 +1,6 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, bail};
 use derive_more::From;
 use iced::widget::svg;
 use serde::{Deserialize, Serialize};
@@ -5,22 +8,56 @@ use wacore_binary::jid::Jid;
 
 use crate::ui::icons::{SIGNAL_ICON, WHATSAPP_ICON};
 
+pub mod rate_limit;
 pub mod signal;
 pub mod whatsapp;
 
+/// Identifier of linked messenger account. The first account of each messenger has id 0
+pub type AccountId = u8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, derive_more::Display, clap::ValueEnum)]
+pub enum Messenger {
+    Signal,
+    Whatsapp,
+}
 
 #[derive(Debug, From, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[serde(from = "StoredKey")]
 pub enum Key {
+    /// Group master key together with the account it is reachable from
+    #[serde(rename = "SignalAccount")]
+    Signal(AccountId, [u8; 32]),
+    /// Group id together with the account it is reachable from
+    #[serde(rename = "WhatsappAccount")]
+    Whatsapp(AccountId, Jid),
+}
+
+/// Saved form of [`Key`]. Keys saved before multiple accounts were supported belong to the first account
+#[derive(Deserialize)]
+enum StoredKey {
     Signal([u8; 32]),
+    SignalAccount(AccountId, [u8; 32]),
     Whatsapp(Jid),
+    WhatsappAccount(AccountId, Jid),
+}
+
+impl From<StoredKey> for Key {
+    fn from(value: StoredKey) -> Self {
+        match value {
+            StoredKey::Signal(key) => Self::Signal(0, key),
+            StoredKey::SignalAccount(account, key) => Self::Signal(account, key),
+            StoredKey::Whatsapp(jid) => Self::Whatsapp(0, jid),
+            StoredKey::WhatsappAccount(account, jid) => Self::Whatsapp(account, jid),
+        }
+    }
 }
 
 impl PartialOrd for Key {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        fn val(v: &Key) -> usize {
+        fn val(v: &Key) -> (usize, AccountId) {
             match v {
-                Key::Signal(_) => 0,
-                Key::Whatsapp(_) => 1,
+                Key::Signal(account, _) => (0, *account),
+                Key::Whatsapp(account, _) => (1, *account),
             }
         }
 
@@ -35,11 +72,135 @@ impl Ord for Key {
 }
 
 impl Key {
+    pub fn messenger(&self) -> &'static str {
+        match self {
+            Self::Signal(..) => "Signal",
+            Self::Whatsapp(..) => "Whatsapp",
+        }
+    }
+
+    pub fn icon(&self) -> svg::Svg<'static> {
+        Messenger::from(self).icon()
+    }
+
+    /// Account the group belongs to
+    pub fn account(&self) -> (Messenger, AccountId) {
+        match self {
+            Self::Signal(account, _) => (Messenger::Signal, *account),
+            Self::Whatsapp(account, _) => (Messenger::Whatsapp, *account),
+        }
+    }
+
+    /// Both keys refer to the same group, possibly reachable from different accounts
+    pub fn same_group(&self, other: &Key) -> bool {
+        match (self, other) {
+            (Self::Signal(_, a), Self::Signal(_, b)) => a == b,
+            (Self::Whatsapp(_, a), Self::Whatsapp(_, b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl From<&Key> for Messenger {
+    fn from(value: &Key) -> Self {
+        value.account().0
+    }
+}
+
+impl Messenger {
     pub fn icon(&self) -> svg::Svg<'static> {
         let bytes = match self {
-            Self::Signal(_) => SIGNAL_ICON,
-            Self::Whatsapp(_) => WHATSAPP_ICON,
+            Self::Signal => SIGNAL_ICON,
+            Self::Whatsapp => WHATSAPP_ICON,
         };
         svg(svg::Handle::from_memory(bytes))
     }
+
+    /// Local store of the account
+    pub fn store_path(self, account: AccountId) -> PathBuf {
+        match self {
+            Self::Signal => signal::store_path(account),
+            Self::Whatsapp => whatsapp::store_path(account),
+        }
+    }
+}
+
+/// Text form of the key: `signal:<hex master key>` or `whatsapp:<jid>` for the first account,
+/// `signal:<account>:<hex master key>` or `whatsapp:<account>:<jid>` for others
+impl Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (messenger, account) = self.account();
+        write!(f, "{}:", messenger.to_string().to_lowercase())?;
+        if account != 0 {
+            write!(f, "{account}:")?;
+        }
+        match self {
+            Self::Signal(_, key) => {
+                for b in key {
+                    write!(f, "{b:02x}")?;
+                }
+                Ok(())
+            },
+            Self::Whatsapp(_, jid) => write!(f, "{jid}"),
+        }
+    }
+}
+
+/// Splits optional account prefix. Group ids of both messengers never contain `:`
+fn split_account(s: &str) -> anyhow::Result<(AccountId, &str)> {
+    match s.split_once(':') {
+        Some((account, rest)) => Ok((account.parse().map_err(|_| anyhow!("Invalid account: {account}"))?, rest)),
+        None => Ok((0, s)),
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(rest) = s.strip_prefix("signal:") {
+            let (account, hex) = split_account(rest)?;
+            if hex.len() != 64 || !hex.is_ascii() {
+                bail!("Invalid signal group key: {hex}");
+            }
+            let mut key = [0u8; 32];
+            for (i, b) in key.iter_mut().enumerate() {
+                *b = u8::from_str_radix(&hex[i * 2 .. i * 2 + 2], 16)?;
+            }
+            Ok(Self::Signal(account, key))
+        }
+        else if let Some(rest) = s.strip_prefix("whatsapp:") {
+            let (account, jid) = split_account(rest)?;
+            Ok(Self::Whatsapp(account, jid.parse().map_err(|_| anyhow!("Invalid whatsapp group id: {jid}"))?))
+        }
+        else {
+            bail!("Unknown group key format: {s}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Key;
+
+    #[test]
+    fn signal_key_roundtrip() {
+        let key = Key::Signal(0, std::array::from_fn(|i| i as u8 * 7));
+        let s = key.to_string();
+        assert_eq!(s.len(), "signal:".len() + 64);
+        assert_eq!(s.parse::<Key>().unwrap(), key);
+        assert!("signal:zz".parse::<Key>().is_err());
+
+        let key = Key::Signal(2, [3; 32]);
+        assert!(key.to_string().starts_with("signal:2:"));
+        assert_eq!(key.to_string().parse::<Key>().unwrap(), key);
+    }
+
+    #[test]
+    fn old_key_belongs_to_first_account() {
+        let key: Key = ron::from_str(&format!("Signal(({}))", ["1"; 32].join(","))).unwrap();
+        assert_eq!(key, Key::Signal(0, [1; 32]));
+        let saved = ron::to_string(&Key::Signal(1, [1; 32])).unwrap();
+        assert_eq!(ron::from_str::<Key>(&saved).unwrap(), Key::Signal(1, [1; 32]));
+    }
 }
@@ -0,0 +1,136 @@
+use std::{collections::{HashMap, VecDeque}, sync::{LazyLock, Mutex}, time::{Duration, Instant}};
+
+use crate::{message::Priority, messangers::Key};
+
+/// Window rate limits are counted in
+const PERIOD: Duration = Duration::from_secs(60);
+/// How often send waiting behind one of higher priority checks its turn
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Default)]
+struct GroupState {
+    /// Most messages per `PERIOD`
+    limit: Option<u32>,
+    /// Times of sends during the last `PERIOD`
+    sent: VecDeque<Instant>,
+    /// Priorities of sends waiting for their turn
+    waiting: Vec<Priority>,
+}
+
+/// Shared by all accounts and Signal worker threads
+static GROUPS: LazyLock<Mutex<HashMap<Key, GroupState>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Replaces limits of groups, groups missing in `limits` are not limited
+pub fn set_limits(limits: impl Iterator<Item = (Key, u32)>) {
+    let mut groups = GROUPS.lock().unwrap();
+    for state in groups.values_mut() {
+        state.limit = None;
+    }
+    for (key, limit) in limits.filter(|(_, limit)| *limit > 0) {
+        groups.entry(key).or_default().limit = Some(limit);
+    }
+}
+
+/// Removes send from waiting ones when it took its turn or was cancelled
+struct Waiting<'a> {
+    key: &'a Key,
+    priority: Priority,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if let Some(state) = GROUPS.lock().unwrap().get_mut(self.key)
+            && let Some(index) = state.waiting.iter().position(|p| *p == self.priority) {
+            state.waiting.swap_remove(index);
+        }
+    }
+}
+
+/// Waits until the group may get one more message. Sends of higher priority waiting for the same group go first
+pub async fn acquire(key: &Key, priority: Priority) {
+    match GROUPS.lock().unwrap().get_mut(key) {
+        Some(state) if state.limit.is_some() => state.waiting.push(priority),
+        _ => return,
+    }
+    let _waiting = Waiting { key, priority };
+
+    while let Err(wait) = try_acquire(key, priority, 1) {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Takes turn of `count` messages to the group if it may get them now, otherwise returns how long to wait.
+/// Message split into more parts than the limit is let through once the group got nothing during the last `PERIOD`
+pub fn try_acquire(key: &Key, priority: Priority, count: usize) -> Result<(), Duration> {
+    let mut groups = GROUPS.lock().unwrap();
+    let Some(state) = groups.get_mut(key) else {
+        return Ok(())
+    };
+    let Some(limit) = state.limit else {
+        return Ok(())
+    };
+    let now = Instant::now();
+    while state.sent.front().is_some_and(|time| now.duration_since(*time) >= PERIOD) {
+        state.sent.pop_front();
+    }
+
+    if state.waiting.iter().any(|p| *p > priority) {
+        return Err(POLL_INTERVAL)
+    }
+    // Sends which have to leave the window before these fit in it
+    let excess = (state.sent.len() + count).saturating_sub(limit as usize).min(state.sent.len());
+    match excess.checked_sub(1) {
+        Some(last) => Err(PERIOD - now.duration_since(state.sent[last])),
+        None => {
+            state.sent.extend(std::iter::repeat_n(now, count));
+            Ok(())
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{message::Priority, messangers::Key};
+    use super::{GROUPS, PERIOD, POLL_INTERVAL, acquire, try_acquire};
+
+    /// Limits are set directly, as `set_limits` would reset ones of other tests running at the same time
+    fn limited(key: &Key, limit: u32) {
+        GROUPS.lock().unwrap().entry(key.clone()).or_default().limit = Some(limit);
+    }
+
+    #[test]
+    fn limit_is_counted_in_parts() {
+        let key = Key::Signal(0, [201; 32]);
+        limited(&key, 3);
+        assert_eq!(try_acquire(&key, Priority::Normal, 2), Ok(()));
+        let wait = try_acquire(&key, Priority::Normal, 2).unwrap_err();
+        assert!(wait > PERIOD - Duration::from_secs(1) && wait <= PERIOD);
+        assert_eq!(try_acquire(&key, Priority::Normal, 1), Ok(()));
+        assert!(try_acquire(&key, Priority::Normal, 1).is_err());
+
+        let long = Key::Signal(0, [202; 32]);
+        limited(&long, 2);
+        assert_eq!(try_acquire(&long, Priority::Normal, 5), Ok(()));
+        assert!(try_acquire(&long, Priority::Normal, 1).is_err());
+
+        assert_eq!(try_acquire(&Key::Signal(0, [203; 32]), Priority::Normal, 100), Ok(()));
+    }
+
+    #[test]
+    fn higher_priority_goes_first() {
+        let key = Key::Signal(0, [204; 32]);
+        limited(&key, 10);
+        GROUPS.lock().unwrap().get_mut(&key).unwrap().waiting.push(Priority::Urgent);
+        assert_eq!(try_acquire(&key, Priority::Normal, 1), Err(POLL_INTERVAL));
+        assert_eq!(try_acquire(&key, Priority::Urgent, 1), Ok(()));
+
+        GROUPS.lock().unwrap().get_mut(&key).unwrap().waiting.clear();
+        futures::executor::block_on(acquire(&key, Priority::Normal));
+        let groups = GROUPS.lock().unwrap();
+        let state = &groups[&key];
+        assert_eq!(state.sent.len(), 2);
+        assert!(state.waiting.is_empty());
+    }
+}
@@ -1,24 +1,25 @@
-use std::{collections::VecDeque, net::TcpStream, path::PathBuf, sync::{Arc, LazyLock}, time::{Duration, SystemTime}};
+use std::{collections::VecDeque, net::TcpStream, path::PathBuf, sync::Arc, time::{Duration, Instant, SystemTime}};
 
 use futures::{FutureExt, SinkExt, StreamExt, channel::mpsc::{UnboundedReceiver, UnboundedSender}, pin_mut};
 use log::info;
-use presage::{libsignal_service::configuration::SignalServers, manager::Registered, proto::{DataMessage, EditMessage, GroupContextV2, data_message::Delete}, store::ContentsStore};
+use presage::{libsignal_service::configuration::SignalServers, manager::Registered, proto::{BodyRange, DataMessage, EditMessage, GroupContextV2, data_message::Delete}, store::ContentsStore};
 use presage_store_sqlite::{OnNewIdentity, SqliteConnectOptions, SqliteStore, SqliteStoreError};
 use tokio::task::{AbortHandle, LocalSet};
 use tracing::{error, warn};
 
-use crate::{message::SendMode, messangers::Key, notification, ui::{self, message_history::{GroupInfoSignal, SendMessageInfo, SendStatus}, side_menu::LinkState}};
+use crate::{crypto, message::{Priority, SIGNAL_MAX_LENGTH, SendMode, message_parts}, messangers::{AccountId, Key, rate_limit}, metrics::METRICS, notification, paths, ui::{self, message_history::{GroupInfoSignal, SendMessageInfo, SendStatus}, side_menu::LinkState}};
 
 type Manager = presage::Manager<SqliteStore, Registered>;
 
-static DB_STR: LazyLock<PathBuf> = LazyLock::new(|| {
-    let mut home = match std::env::home_dir() {
-        Some(v) => v,
-        None => return "signal_data.db".into(),
-    };
-    home.extend([".sender", "signal_data.db"]);
-    home
-});
+/// Store of the account. The first account keeps the store name used before multiple accounts were supported
+pub fn store_path(account: AccountId) -> PathBuf {
+    if account == 0 {
+        paths::profile_file("signal_data.db")
+    }
+    else {
+        paths::profile_file(&format!("signal_data_{account}.db"))
+    }
+}
 
 #[derive(Debug)]
 pub enum SignalMessage {
@@ -27,12 +28,27 @@ pub enum SignalMessage {
     Disconnect,
     GetGroups,
     SendMessage(Arc<SendMessageInfo>, bool),
+    /// Message which rate limits of these groups didn't let through before
+    SendToGroups(Arc<SendMessageInfo>, bool, Vec<[u8; 32]>),
     DeleteMessage(Arc<SendMessageInfo>),
-    EditMessage(Arc<SendMessageInfo>, Vec<u64>, bool),
+    /// Message with timestamps of its parts in every group
+    EditMessage(Arc<SendMessageInfo>, Vec<Vec<u64>>, bool),
     Finished,
 }
 
+impl SignalMessage {
+    /// Priority of message being sent, edited or deleted
+    fn priority(&self) -> Priority {
+        match self {
+            Self::SendMessage(message, _) | Self::SendToGroups(message, ..) | Self::DeleteMessage(message) | Self::EditMessage(message, ..) => message.priority,
+            _ => Priority::default(),
+        }
+    }
+}
+
+/// Handles messages of one Signal account
 pub struct SignalWorker {
+    account: AccountId,
     signal_reciever: UnboundedReceiver<SignalMessage>,
     ui_message_sender: UnboundedSender<crate::ui::Message>,
     message_queue: VecDeque<SignalMessage>,
@@ -45,11 +61,13 @@ pub struct SignalWorker {
 
 impl SignalWorker {
     pub fn new(
+        account: AccountId,
         signal_reciever: UnboundedReceiver<SignalMessage>,
         ui_message_sender: UnboundedSender<crate::ui::Message>,
         signal_sender: UnboundedSender<SignalMessage>
     ) -> Self {
         Self {
+            account,
             signal_reciever,
             ui_message_sender,
             signal_sender,
@@ -62,6 +80,7 @@ impl SignalWorker {
     }
 
     pub fn spawn_new(
+        account: AccountId,
         signal_reciever: UnboundedReceiver<SignalMessage>,
         ui_message_sender: UnboundedSender<crate::ui::Message>,
         signal_sender: UnboundedSender<SignalMessage>
@@ -71,7 +90,7 @@ impl SignalWorker {
                 .enable_all()
                 .build()
                 .expect("No runtime must be built");
-            let worker = Self::new(signal_reciever, ui_message_sender, signal_sender);
+            let worker = Self::new(account, signal_reciever, ui_message_sender, signal_sender);
             let local = LocalSet::new();
             local.spawn_local(worker.future());
             rt.block_on(local)
@@ -79,12 +98,13 @@ impl SignalWorker {
     }
 
     async fn future(mut self) {
+        let account = self.account;
         while let Some(m) = self.signal_reciever.next().await {
             let ui_message_sender = self.ui_message_sender.clone();
             match m {
                 SignalMessage::LinkBegin => {
                     let mut finish_send = self.signal_sender.clone();
-                    let handle = tokio::task::spawn_local(link(ui_message_sender.clone()).then(move |res| async move {
+                    let handle = tokio::task::spawn_local(link(account, ui_message_sender.clone()).then(move |res| async move {
                         _ = finish_send.send(SignalMessage::Linked(res)).await;
                     }));
                     self.link_abort = Some(handle.abort_handle());
@@ -92,17 +112,17 @@ impl SignalWorker {
                 SignalMessage::Linked(mng_res) => {
                     match mng_res {
                         Ok(mng) => {
-                            tokio::task::spawn_local(sync(self.ui_message_sender.clone(), mng.clone()));
+                            tokio::task::spawn_local(sync(account, self.ui_message_sender.clone(), mng.clone()));
                             self.manager = Some(mng);
                             self.has_connected = true;
                             self.execute_next_maybe();
-                            send_ui_message(ui_message_sender.clone(), ui::side_menu::Message::SetSignalState(LinkState::Linked))
+                            send_ui_message(ui_message_sender.clone(), ui::side_menu::Message::SetSignalState(account, LinkState::Linked))
                         },
                         Err(_e) => send_ui_message(ui_message_sender.clone(), if self.has_connected {
-                            ui::side_menu::Message::SetSignalState(LinkState::Disconnected)
+                            ui::side_menu::Message::SetSignalState(account, LinkState::Disconnected)
                         }
                         else {
-                            ui::side_menu::Message::SetSignalState(LinkState::Unlinked)
+                            ui::side_menu::Message::SetSignalState(account, LinkState::Unlinked)
                         }),
                     }
                 },
@@ -115,19 +135,21 @@ impl SignalWorker {
                 SignalMessage::Finished => {
                     self.abort_handle = None;
                     _ = self.message_queue.pop_front();
+                    METRICS.set_queue_depth(self.message_queue.len());
                     self.execute_next_maybe();
                 },
                 SignalMessage::GetGroups => {
                     if let Some(manager) = &self.manager {
                         let manager = manager.clone();
                         tokio::task::spawn_local(async move {
-                            let groups = get_groups(manager).await;
+                            let groups = get_groups(account, manager).await;
                             _ = send_ui_message(ui_message_sender, groups.map(ui::main_screen::Message::SetGroups));
                         });
                     }
                 }
                 message => {
-                    self.message_queue.push_back(message);
+                    enqueue(&mut self.message_queue, self.abort_handle.is_some(), message);
+                    METRICS.set_queue_depth(self.message_queue.len());
                     self.execute_next_maybe();
                 },
             };
@@ -162,9 +184,10 @@ impl SignalWorker {
         let ui_message_sender = self.ui_message_sender.clone();
         let mut finish_send = self.signal_sender.clone();
         let abort_handle = match signal_message {
-            SignalMessage::SendMessage(message, markdown) => message_task!(message.clone(), send_message(ui_message_sender, self.manager.as_ref().unwrap().clone(), message.clone(), *markdown), finish_send),
-            SignalMessage::DeleteMessage(message) => message_task!(message.clone(), delete_message(ui_message_sender, self.manager.as_ref().unwrap().clone(), message.clone()), finish_send),
-            SignalMessage::EditMessage(message, timestamps, markdown) => message_task!(message.clone(), edit_message(ui_message_sender, self.manager.as_ref().unwrap().clone(), message.clone(), timestamps.clone(), *markdown), finish_send),
+            SignalMessage::SendMessage(message, markdown) => message_task!(message.clone(), send_message(self.account, ui_message_sender, self.signal_sender.clone(), self.manager.as_ref().unwrap().clone(), message.clone(), *markdown, None), finish_send),
+            SignalMessage::SendToGroups(message, markdown, groups) => message_task!(message.clone(), send_message(self.account, ui_message_sender, self.signal_sender.clone(), self.manager.as_ref().unwrap().clone(), message.clone(), *markdown, Some(groups.clone())), finish_send),
+            SignalMessage::DeleteMessage(message) => message_task!(message.clone(), delete_message(self.account, ui_message_sender, self.manager.as_ref().unwrap().clone(), message.clone()), finish_send),
+            SignalMessage::EditMessage(message, timestamps, markdown) => message_task!(message.clone(), edit_message(self.account, ui_message_sender, self.manager.as_ref().unwrap().clone(), message.clone(), timestamps.clone(), *markdown), finish_send),
             _m => panic!("Other messages should not be here!")
         };
     
@@ -172,23 +195,39 @@ impl SignalWorker {
     }
 }
 
-pub async fn get_groups(manager: Manager) -> anyhow::Result<Vec<(Key, String)>> {
+/// Puts message to the queue. Higher priority goes first, the same priority keeps order. Message being sent stays in front
+fn enqueue(queue: &mut VecDeque<SignalMessage>, running: bool, message: SignalMessage) {
+    let running = usize::from(running);
+    let priority = message.priority();
+    let index = queue.iter()
+        .skip(running)
+        .position(|queued| queued.priority() < priority)
+        .map_or(queue.len(), |index| index + running);
+    queue.insert(index, message);
+}
+
+pub async fn get_groups(account: AccountId, manager: Manager) -> anyhow::Result<Vec<(Key, String)>> {
     Ok(
         manager.store().groups().await?
         .flatten()
         .map(|(key, group)| {
-            (Key::from(key), group.title)
+            (Key::Signal(account, key), group.title)
         })
         .collect()
     )
 }
 
-async fn get_store() -> Result<SqliteStore, SqliteStoreError> {
+async fn get_store(account: AccountId) -> Result<SqliteStore, SqliteStoreError> {
+    let mut options = SqliteConnectOptions::default()
+        // .filename("signal_data.db")
+        .filename(store_path(account))
+        .create_if_missing(true);
+    if let Some(key) = crypto::store_key(&format!("signal-{account}")) {
+        // Raw SQLCipher key, so SQLCipher doesn't derive it once more
+        options = options.pragma("key", format!("\"x'{key}'\""));
+    }
     let store = SqliteStore::open_with_options(
-        SqliteConnectOptions::default()
-            // .filename("signal_data.db")
-            .filename(DB_STR.as_path())
-            .create_if_missing(true),
+        options,
         OnNewIdentity::Trust,
     )
     .await?;
@@ -197,8 +236,8 @@ async fn get_store() -> Result<SqliteStore, SqliteStoreError> {
     Ok(store)
 }
 
-async fn link(mut msg_send_channel: UnboundedSender<crate::ui::Message>) -> anyhow::Result<Manager> {
-    send_ui_message(msg_send_channel.clone(), ui::side_menu::Message::SetSignalState(LinkState::Linking));
+async fn link(account: AccountId, mut msg_send_channel: UnboundedSender<crate::ui::Message>) -> anyhow::Result<Manager> {
+    send_ui_message(msg_send_channel.clone(), ui::side_menu::Message::SetSignalState(account, LinkState::Linking));
     loop {
         // Ping to google.com at start
         match TcpStream::connect("209.85.233.101:80") {
@@ -206,14 +245,14 @@ async fn link(mut msg_send_channel: UnboundedSender<crate::ui::Message>) -> anyh
                 break;
             },
             Err(_) => {
-                send_ui_message(msg_send_channel.clone(), ui::side_menu::Message::SetSignalState(LinkState::Disconnected));
+                send_ui_message(msg_send_channel.clone(), ui::side_menu::Message::SetSignalState(account, LinkState::Disconnected));
                 send_ui_message(msg_send_channel.clone(), ui::Message::Notification("Немає підключення до інтернету".to_owned()));
                 tokio::time::sleep(Duration::from_secs(5)).await;
             }
         }
     }
 
-    let store = get_store().await?;
+    let store = get_store(account).await?;
     info!("Registering from store");
     match Manager::load_registered(store).await {
         Ok(mng) => {
@@ -223,7 +262,7 @@ async fn link(mut msg_send_channel: UnboundedSender<crate::ui::Message>) -> anyh
         Err(e) => {
             warn!("{e}");
             let (tx, rx) = futures::channel::oneshot::channel();
-            let store = get_store().await?;
+            let store = get_store(account).await?;
             info!("Starting linking with device");
             let (mng_res, _) = futures::future::join(
                 async move {
@@ -266,7 +305,7 @@ async fn count_groups(manager: &Manager) -> anyhow::Result<usize> {
     Ok(manager.store().groups().await?.count())
 }
 
-async fn sync(mut msg_send_channel: UnboundedSender<crate::ui::Message>, mut manager: Manager) -> anyhow::Result<()> {
+async fn sync(account: AccountId, mut msg_send_channel: UnboundedSender<crate::ui::Message>, mut manager: Manager) -> anyhow::Result<()> {
     let reciever = manager.receive_messages().await?;
     pin_mut!(reciever);
     let mut group_count = count_groups(&manager).await.unwrap_or(0);
@@ -286,98 +325,147 @@ async fn sync(mut msg_send_channel: UnboundedSender<crate::ui::Message>, mut man
                 }
             }
             presage::model::messages::Received::QueueEmpty => {
-                _ = msg_send_channel.send(crate::ui::Message::Synced).await;
+                _ = msg_send_channel.send(crate::ui::Message::Synced(account)).await;
             }
         }
     }
     log::error!("Sync suspended");
-    _ = msg_send_channel.send(ui::Message::SignalDisconnected).await;
+    _ = msg_send_channel.send(ui::Message::SignalDisconnected(account)).await;
 
     Ok(())
 }
 
+/// Sends message to groups of the account, or only to `groups` rate limits didn't let it through before.
+/// Groups which may not get it yet are sent to later, so the queue isn't held up by waiting
 async fn send_message(
+    account: AccountId,
     msg_send_channel: UnboundedSender<crate::ui::Message>,
+    mut signal_sender: UnboundedSender<SignalMessage>,
     manager: Manager,
     message: Arc<SendMessageInfo>,
     markdown: bool,
+    groups: Option<Vec<[u8; 32]>>,
 ) {
+    // Message deleted while waiting for rate limits isn't sent anymore
+    if groups.is_some() && message.status(std::sync::atomic::Ordering::Relaxed) != SendStatus::Sending {
+        return
+    }
     message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
     send_ui_message(msg_send_channel.clone(), ui::main_screen::Message::UpdateMessageHistory);
-    for group in message.groups_signal.iter() {
+    let mut throttled = Vec::new();
+    let mut wait = Duration::MAX;
+    for group in message.groups_signal.iter().filter(|g| g.account == account && groups.as_ref().is_none_or(|keys| keys.contains(&g.key))) {
+        let start = Instant::now();
         match send_message_inner(
             manager.clone(),
+            account,
             group,
-            &message.content,
-            message.freq.as_deref(),
+            &message,
             markdown,
         ).await {
-            Ok(_) => {
+            Ok(Some(group_wait)) => {
+                throttled.push(group.key);
+                wait = wait.min(group_wait);
+            },
+            Ok(None) => {
+                METRICS.sent(Key::Signal(account, group.key), start.elapsed());
                 message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
                 send_ui_message(msg_send_channel.clone(), ui::main_screen::Message::UpdateMessageHistory);
             }
             Err(e) => {
-                message.set_status(SendStatus::Failed, std::sync::atomic::Ordering::Relaxed);
+                METRICS.failed(Key::Signal(account, group.key));
+                group.set_failed(true);
                 error!("Error sending to group: {e}");
                 send_ui_message(msg_send_channel.clone(), notification!("Помилка відправки у групу: {}", e));
             }
         }
     }
-    message.set_status(SendStatus::Sent, std::sync::atomic::Ordering::Relaxed);
+    if !throttled.is_empty() {
+        tokio::task::spawn_local(async move {
+            tokio::time::sleep(wait).await;
+            _ = signal_sender.send(SignalMessage::SendToGroups(message, markdown, throttled)).await;
+        });
+        return
+    }
+    message.finish_job();
     send_ui_message(msg_send_channel.clone(), ui::main_screen::Message::UpdateMessageHistory);
 }
 
+/// Sends every part of the message to the group. When rate limit of the group doesn't let that many parts through,
+/// nothing is sent and the time to wait is returned
 async fn send_message_inner(
-    mut manager: Manager,
+    manager: Manager,
+    account: AccountId,
     group: &GroupInfoSignal,
-    message: &str,
-    freq: Option<&str>,
+    message: &SendMessageInfo,
     markdown: bool,
-) -> anyhow::Result<()> {
-    let mut message = if markdown {
-        let (message, ranges) = crate::message::parse_message_with_format(message).unwrap_or_else(|_| (message.to_owned(), Vec::new()));
-        let message = if let SendMode::Frequency = group.send_mode && let Some(freq) = freq {
-            format!("{}\n{}", freq, message)
-        }
-        else {
-            message
-        };
-
-        DataMessage {
-            body: Some(message),
-            body_ranges: ranges,
-            ..Default::default()
-        }
+) -> anyhow::Result<Option<Duration>> {
+    let freq = message.freq.as_deref().filter(|_| matches!(group.send_mode, SendMode::Frequency));
+    let key = Key::Signal(account, group.key);
+    let parts = message_parts(message.content_for(&key), freq, markdown, SIGNAL_MAX_LENGTH);
+    if let Err(wait) = rate_limit::try_acquire(&key, message.priority, parts.len()) {
+        return Ok(Some(wait))
     }
-    else {
-        DataMessage {
-            body: Some(
-                if let SendMode::Frequency = group.send_mode && let Some(freq) = freq {
-                    format!("{}\n{}", freq, message)
-                }
-                else {
-                    message.to_owned()
-                }
-            ),
-            ..Default::default()
-        }
-    };
-    let timestamp = std::time::SystemTime::now()
+    group.set_timestamps(Vec::new());
+    group.set_failed(false);
+    for (body, ranges) in parts {
+        let timestamp = send_part(manager.clone(), &group.key, body, ranges).await?;
+        group.push_timestamp(timestamp);
+    }
+
+    Ok(None)
+}
+
+fn timestamp_now() -> u64 {
+    std::time::SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
-        .as_millis() as u64;
-    message.group_v2 = Some(GroupContextV2 {
-        master_key: Some(group.key.to_vec()),
-        revision: Some(0),
+        .as_millis() as u64
+}
+
+fn data_message(key: &[u8; 32], body: String, ranges: Vec<BodyRange>, timestamp: u64) -> DataMessage {
+    DataMessage {
+        body: Some(body),
+        body_ranges: ranges,
+        group_v2: Some(GroupContextV2 {
+            master_key: Some(key.to_vec()),
+            revision: Some(0),
+            ..Default::default()
+        }),
+        timestamp: Some(timestamp),
         ..Default::default()
-    });
-    message.timestamp = Some(timestamp);
+    }
+}
+
+/// Sends one part of a message, returns its timestamp
+async fn send_part(mut manager: Manager, key: &[u8; 32], body: String, ranges: Vec<BodyRange>) -> anyhow::Result<u64> {
+    let timestamp = timestamp_now();
+    manager.send_message_to_group(
+        key,
+        data_message(key, body, ranges, timestamp),
+        timestamp
+    ).await?;
 
-    group.set_timestamp(timestamp, std::sync::atomic::Ordering::Relaxed);
+    Ok(timestamp)
+}
 
+async fn delete_part(mut manager: Manager, key: &[u8; 32], target_timestamp: u64) -> anyhow::Result<()> {
+    let timestamp = timestamp_now();
+    let delete_message = DataMessage {
+        delete: Some(Delete {
+            target_sent_timestamp: Some(target_timestamp),
+        }),
+        group_v2: Some(GroupContextV2 {
+            master_key: Some(key.to_vec()),
+            revision: Some(0),
+            ..Default::default()
+        }),
+        timestamp: Some(timestamp),
+        ..Default::default()
+    };
     manager.send_message_to_group(
-        &group.key,
-        message,
+        key,
+        delete_message,
         timestamp
     ).await?;
 
@@ -385,113 +473,80 @@ async fn send_message_inner(
 }
 
 async fn delete_message(
+    account: AccountId,
     msg_send_channel: UnboundedSender<crate::ui::Message>,
-    mut manager: Manager,
+    manager: Manager,
     message: Arc<SendMessageInfo>,
 ) {
     message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
     send_ui_message(msg_send_channel.clone(), ui::main_screen::Message::UpdateMessageHistory);
     
-    for group in message.groups_signal.iter() {
-        let target_timestamp = group.timestamp(std::sync::atomic::Ordering::Relaxed).unwrap();
-        let cur_timestamp = std::time::SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64;
-
-        let delete_message = DataMessage {
-            delete: Some(Delete {
-                target_sent_timestamp: Some(target_timestamp),
-            }),
-            group_v2: Some(GroupContextV2 {
-                master_key: Some(group.key.to_vec()),
-                revision: Some(0),
-                ..Default::default()
-            }),
-            timestamp: Some(cur_timestamp),
-            ..Default::default()
-        };
-
-        match manager.send_message_to_group(
-            &group.key,
-            delete_message.clone(),
-            cur_timestamp
-        ).await {
-            Ok(_) => {
-                message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
-                group.set_timestamp(0, std::sync::atomic::Ordering::Relaxed);
-                send_ui_message(msg_send_channel.clone(), ui::main_screen::Message::UpdateMessageHistory);
-            }
-            Err(e) => {
-                message.set_status(SendStatus::Failed, std::sync::atomic::Ordering::Relaxed);
-                group.set_timestamp(0, std::sync::atomic::Ordering::Relaxed);
-                error!("Error deleting from group: {e}");
-                send_ui_message(msg_send_channel.clone(), notification!("Помилка видалення повідомлення: {}", e));
+    for group in message.groups_signal.iter().filter(|g| g.account == account) {
+        // Every part of split message is deleted
+        for target_timestamp in group.timestamps() {
+            match delete_part(manager.clone(), &group.key, target_timestamp).await {
+                Ok(_) => {
+                    message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
+                }
+                Err(e) => {
+                    message.set_status(SendStatus::Failed, std::sync::atomic::Ordering::Relaxed);
+                    error!("Error deleting from group: {e}");
+                    send_ui_message(msg_send_channel.clone(), notification!("Помилка видалення повідомлення: {}", e));
+                }
             }
         }
+        group.set_timestamps(Vec::new());
+        send_ui_message(msg_send_channel.clone(), ui::main_screen::Message::UpdateMessageHistory);
     }
 
     message.set_status(SendStatus::Deleted, std::sync::atomic::Ordering::Relaxed);
     send_ui_message(msg_send_channel.clone(), ui::main_screen::Message::UpdateMessageHistory);
 }
 
+/// Replaces sent parts with new ones. Parts the message didn't have before are sent, parts it doesn't have anymore are deleted
+async fn edit_parts(mut manager: Manager, group: &GroupInfoSignal, parts: Vec<(String, Vec<BodyRange>)>, targets: Vec<u64>) -> anyhow::Result<()> {
+    let mut targets = targets.into_iter();
+    for (body, ranges) in parts {
+        match targets.next() {
+            Some(target) => {
+                let now = timestamp_now();
+                let edit_message = EditMessage {
+                    target_sent_timestamp: Some(target),
+                    data_message: Some(data_message(&group.key, body, ranges, now)),
+                };
+                manager.send_message_to_group(
+                    &group.key,
+                    edit_message,
+                    now
+                ).await?;
+                group.push_timestamp(now);
+            },
+            None => group.push_timestamp(send_part(manager.clone(), &group.key, body, ranges).await?),
+        }
+    }
+    for target in targets {
+        delete_part(manager.clone(), &group.key, target).await?;
+    }
+
+    Ok(())
+}
+
 async fn edit_message(
+    account: AccountId,
     msg_send_channel: UnboundedSender<crate::ui::Message>,
-    mut manager: Manager,
+    manager: Manager,
     message: Arc<SendMessageInfo>,
-    timestamps: Vec<u64>,
+    timestamps: Vec<Vec<u64>>,
     markdown: bool,
 ) {
     message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
     send_ui_message(msg_send_channel.clone(), ui::main_screen::Message::UpdateMessageHistory);
-    for (group, timestamp) in message.groups_signal.iter().zip(timestamps) {
-        let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64;
-        let freq = message.freq.as_ref();
-
-        let mut data_message = if markdown {
-            let (message, ranges) = crate::message::parse_message_with_format(&message.content).unwrap_or_else(|_| (message.content.clone(), Vec::new()));
-            DataMessage {
-                body: Some(if let SendMode::Frequency = group.send_mode && let Some(freq) = freq {
-                    format!("{}\n{}", freq, message)
-                }
-                else {
-                    message
-                }),
-                body_ranges: ranges,
-                ..Default::default()
-            }
-        }
-        else {
-            DataMessage {
-                body: Some(if let SendMode::Frequency = group.send_mode && let Some(freq) = freq {
-                    format!("{}\n{}", freq, message.content)
-                }
-                else {
-                    message.content.clone()
-                }),
-                ..Default::default()
-            }
-        };
-        data_message.group_v2 = Some(GroupContextV2 {
-            master_key: Some(group.key.to_vec()),
-            revision: Some(0),
-            ..Default::default()
-        });
-        data_message.timestamp = Some(now);
-        
-        let edit_message = EditMessage {
-            target_sent_timestamp: Some(timestamp),
-            data_message: Some(data_message)
-        };
-        
-        match manager.send_message_to_group(
-            &group.key,
-            edit_message.clone(),
-            now
-        ).await {
+    // Timestamps are taken from groups of all accounts, so they are matched before filtering
+    for (group, targets) in message.groups_signal.iter().zip(timestamps).filter(|(g, _)| g.account == account) {
+        let freq = message.freq.as_deref().filter(|_| matches!(group.send_mode, SendMode::Frequency));
+        let parts = message_parts(message.content_for(&Key::Signal(account, group.key)), freq, markdown, SIGNAL_MAX_LENGTH);
+
+        match edit_parts(manager.clone(), group, parts, targets).await {
             Ok(_) => {
                 message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
                 send_ui_message(msg_send_channel.clone(), ui::main_screen::Message::UpdateMessageHistory);
@@ -502,8 +557,6 @@ async fn edit_message(
                 send_ui_message(msg_send_channel.clone(), notification!("Помилка редагування повідомлення: {}", e));
             }
         }
-
-        group.set_timestamp(now, std::sync::atomic::Ordering::Relaxed);
     }
 
     message.set_status(SendStatus::Sent, std::sync::atomic::Ordering::Relaxed);
@@ -515,4 +568,42 @@ fn send_ui_message(
     message: impl Into<ui::Message> + 'static + Send
 ) {
     tokio::spawn(async move { msg_send_channel.send(message.into()).await.unwrap() });
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::VecDeque, sync::Arc};
+
+    use crate::{message::Priority, ui::message_history::SendMessageInfo};
+    use super::{SignalMessage, enqueue};
+
+    fn send(text: &str, priority: Priority) -> SignalMessage {
+        let mut message = SendMessageInfo::new(text.to_owned(), None);
+        message.priority = priority;
+        SignalMessage::SendMessage(Arc::new(message), false)
+    }
+
+    #[test]
+    fn higher_priority_goes_first() {
+        let mut queue = VecDeque::new();
+        enqueue(&mut queue, false, send("низький", Priority::Low));
+        // The first message is being sent, so it stays in front of the urgent one
+        enqueue(&mut queue, true, send("перший", Priority::Normal));
+        enqueue(&mut queue, true, send("другий", Priority::Normal));
+        enqueue(&mut queue, true, send("терміновий", Priority::Urgent));
+        enqueue(&mut queue, true, send("третій", Priority::Normal));
+
+        let texts = queue.iter()
+            .map(|message| match message {
+                SignalMessage::SendMessage(message, _) => message.content.as_str(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(texts, ["низький", "терміновий", "перший", "другий", "третій"]);
+
+        let mut idle = VecDeque::new();
+        enqueue(&mut idle, false, send("звичайний", Priority::Normal));
+        enqueue(&mut idle, false, send("терміновий", Priority::Urgent));
+        assert!(matches!(&idle[0], SignalMessage::SendMessage(message, _) if message.priority == Priority::Urgent));
+    }
+}
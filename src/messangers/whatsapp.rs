@@ -1,42 +1,53 @@
-use std::sync::{Arc, LazyLock, OnceLock};
+use std::{path::PathBuf, sync::Arc, time::Instant};
 
-use futures::{SinkExt, channel::mpsc::UnboundedSender};
+use futures::channel::mpsc::UnboundedSender;
 use whatsapp_rust::{Client, bot::Bot, store::SqliteStore, transport::{TokioWebSocketTransportFactory, UreqHttpClient}, types::events::{Event, PinUpdate}};
+use wacore_binary::jid::Jid;
 use waproto::whatsapp::{self as wa, sync_action_value::PinAction};
 
-use crate::{message::{SendMode, parse_message_with_whatsapp_format}, messangers::Key, ui::{self, side_menu::LinkState, message_history::{SendMessageInfo, SendStatus}}};
+use crate::{crypto, message::{SendMode, WHATSAPP_MAX_LENGTH, markup_parts}, messangers::{AccountId, Key, rate_limit}, metrics::METRICS, paths, ui::{self, side_menu::LinkState, message_history::{GroupInfoWhatsapp, SendMessageInfo, SendStatus}}};
 
-pub static UI_MESSAGE_SENDER: OnceLock<UnboundedSender<ui::Message>> = OnceLock::new();
-static DB_STR: LazyLock<String> = LazyLock::new(|| {
-    let mut home = match std::env::home_dir() {
-        Some(v) => v,
-        None => return "sqlite:///whatsapp_data.db".to_owned(),
-    };
-    home.extend([".sender", "whatsapp_data.db"]);
-    
-    format!("sqlite://{}", home.to_string_lossy())
-});
+/// Store of the account. The first account keeps the store name used before multiple accounts were supported
+pub fn store_path(account: AccountId) -> PathBuf {
+    if account == 0 {
+        paths::profile_file("whatsapp_data.db")
+    }
+    else {
+        paths::profile_file(&format!("whatsapp_data_{account}.db"))
+    }
+}
 
-async fn send_ui_message(messsage: impl Into<ui::Message>) {
-    UI_MESSAGE_SENDER.get().unwrap().send(messsage.into()).await.unwrap()
+fn send_ui_message(channel: &UnboundedSender<ui::Message>, message: impl Into<ui::Message>) {
+    _ = channel.unbounded_send(message.into());
 }
 
-pub async fn start_whatsapp_task() {
-    match start_whatsapp_task_inner().await {
+/// Messages about account state are passed to `channel` and carry account id
+pub async fn start_whatsapp_task(account: AccountId, channel: UnboundedSender<ui::Message>) {
+    match start_whatsapp_task_inner(account, channel.clone()).await {
         Ok(_bot_handle) => {
             // TODO: Maybe use `bot_handle` for termination
         },
         Err(e) => {
-            UI_MESSAGE_SENDER.get().unwrap().send(ui::side_menu::Message::SetWhatsappState(LinkState::Unlinked).into()).await.unwrap();
-            UI_MESSAGE_SENDER.get().unwrap().send(ui::Message::Notification(format!("Error linking to Whatsapp: {e}"))).await.unwrap();
-            _ = UI_MESSAGE_SENDER.get().unwrap().send(ui::Message::UpdateGroupList).await;
+            send_ui_message(&channel, ui::side_menu::Message::SetWhatsappState(account, LinkState::Unlinked));
+            send_ui_message(&channel, ui::Message::Notification(format!("Error linking to Whatsapp: {e}")));
+            send_ui_message(&channel, ui::Message::UpdateGroupList);
         },
     }
 }
 
-async fn start_whatsapp_task_inner() -> anyhow::Result<tokio::task::JoinHandle<()>> {
-    _ = UI_MESSAGE_SENDER.get().unwrap().send(ui::side_menu::Message::SetWhatsappState(LinkState::Linking).into()).await;
-    let store = Arc::new(SqliteStore::new(&DB_STR).await?);
+async fn start_whatsapp_task_inner(account: AccountId, channel: UnboundedSender<ui::Message>) -> anyhow::Result<tokio::task::JoinHandle<()>> {
+    send_ui_message(&channel, ui::side_menu::Message::SetWhatsappState(account, LinkState::Linking));
+    let db = match crypto::store_key(&format!("whatsapp-{account}")) {
+        // SQLCipher takes raw key from `key` parameter of URI filename
+        Some(key) => {
+            let mut url = url::Url::from_file_path(std::path::absolute(store_path(account))?)
+                .map_err(|_| anyhow::anyhow!("Invalid store path"))?;
+            url.set_query(Some(&format!("key=x%27{key}%27")));
+            url.to_string()
+        },
+        None => format!("sqlite://{}", store_path(account).to_string_lossy()),
+    };
+    let store = Arc::new(SqliteStore::new(&db).await?);
     
     let transport = TokioWebSocketTransportFactory::new();
     let client = UreqHttpClient::new();
@@ -45,36 +56,40 @@ async fn start_whatsapp_task_inner() -> anyhow::Result<tokio::task::JoinHandle<(
     .with_backend(store)
     .with_transport_factory(transport)
     .with_http_client(client)
-    .on_event(|event, client| async {
-        match event {
-            // TODO: Maybe use timeout to communicate to UI
-            #[allow(unused_variables)]
-            Event::PairingQrCode { code, timeout } => {
-                _ = UI_MESSAGE_SENDER.get().unwrap().send(ui::main_screen::Message::SetWhatsappUrl(Some(code)).into()).await;
-            },
-            Event::Connected(_) => {
-                println!("Connected to whatsapp");
-                _ = UI_MESSAGE_SENDER.get().unwrap().send(ui::Message::SetWhatsappClient(Some(client))).await;
-            },
-            Event::Disconnected(_) => {
-                _ = UI_MESSAGE_SENDER.get().unwrap().send(ui::side_menu::Message::SetWhatsappState(LinkState::Disconnected).into()).await;
-            },
-            Event::LoggedOut(_) => {
-                _ = UI_MESSAGE_SENDER.get().unwrap().send(ui::side_menu::Message::SetWhatsappState(LinkState::Disconnected).into()).await;
-            },
-            Event::JoinedGroup(_) => {
-                _ = UI_MESSAGE_SENDER.get().unwrap().send(ui::Message::UpdateGroupList).await;
-            },
-            Event::GroupInfoUpdate { .. } => {
-                _ = UI_MESSAGE_SENDER.get().unwrap().send(ui::Message::UpdateGroupList).await;
-            },
-            Event::Notification(_) => {
-                _ = UI_MESSAGE_SENDER.get().unwrap().send(ui::Message::UpdateGroupList).await;
-            },
-            Event::PinUpdate(PinUpdate { action, .. }) if PinAction { pinned: Some(false) } == *action => {
-                _ = UI_MESSAGE_SENDER.get().unwrap().send(ui::Message::UpdateGroupList).await;
-            },
-            other_event => log::info!("Whatsapp event: {other_event:?}"),
+    .on_event(move |event, client| {
+        let channel = channel.clone();
+        async move {
+            match event {
+                // TODO: Maybe use timeout to communicate to UI
+                #[allow(unused_variables)]
+                Event::PairingQrCode { code, timeout } => {
+                    send_ui_message(&channel, ui::main_screen::Message::SetWhatsappUrl(Some(code)));
+                },
+                Event::Connected(_) => {
+                    println!("Connected to whatsapp");
+                    send_ui_message(&channel, ui::Message::SetWhatsappClient(account, Some(client)));
+                },
+                Event::Disconnected(_) => {
+                    METRICS.reconnect("Whatsapp");
+                    send_ui_message(&channel, ui::side_menu::Message::SetWhatsappState(account, LinkState::Disconnected));
+                },
+                Event::LoggedOut(_) => {
+                    send_ui_message(&channel, ui::side_menu::Message::SetWhatsappState(account, LinkState::Disconnected));
+                },
+                Event::JoinedGroup(_) => {
+                    send_ui_message(&channel, ui::Message::UpdateGroupList);
+                },
+                Event::GroupInfoUpdate { .. } => {
+                    send_ui_message(&channel, ui::Message::UpdateGroupList);
+                },
+                Event::Notification(_) => {
+                    send_ui_message(&channel, ui::Message::UpdateGroupList);
+                },
+                Event::PinUpdate(PinUpdate { action, .. }) if PinAction { pinned: Some(false) } == *action => {
+                    send_ui_message(&channel, ui::Message::UpdateGroupList);
+                },
+                other_event => log::info!("Whatsapp event: {other_event:?}"),
+            }
         }
     })
     .build()
@@ -83,124 +98,137 @@ async fn start_whatsapp_task_inner() -> anyhow::Result<tokio::task::JoinHandle<(
     bot.run().await
 }
 
-pub async fn get_groups(client: Arc<Client>) -> anyhow::Result<Vec<(Key, String)>> {
+pub async fn get_groups(account: AccountId, client: Arc<Client>) -> anyhow::Result<Vec<(Key, String)>> {
     Ok(
         client.groups().get_participating().await?.values()
-        .map(|meta| (meta.id.clone().into(), meta.subject.clone()))
+        .map(|meta| (Key::Whatsapp(account, meta.id.clone()), meta.subject.clone()))
         .collect()
     )
 }
 
-pub async fn send_message(client: Arc<Client>, message: Arc<SendMessageInfo>, markdown: bool) {
-    message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
-
-    let content = if markdown {
-        parse_message_with_whatsapp_format(&message.content).unwrap_or(message.content.clone())
-    }
-    else {
-        message.content.clone()
-    };
-    
-    for group in message.groups_whatsapp.iter() {
-        let wa_message = wa::Message {
-            conversation: Some(
-                if let SendMode::Frequency = group.send_mode && let Some(ref freq) = message.freq {
-                    format!("{}\n{}", freq, &content)
-                }
-                else {
-                    content.clone()
-                }
-            ),
+/// Parts message is sent to the group as, markdown is written as Whatsapp markup
+fn whatsapp_parts(message: &SendMessageInfo, key: &Key, send_mode: SendMode, markdown: bool) -> Vec<wa::Message> {
+    let freq = message.freq.as_deref().filter(|_| matches!(send_mode, SendMode::Frequency));
+    markup_parts(message.content_for(key), freq, markdown, WHATSAPP_MAX_LENGTH).into_iter()
+        .map(|part| wa::Message {
+            conversation: Some(part),
             ..Default::default()
-        };
-
-        let message_id = loop {
-            match client.send_message(
-                group.key.clone(),
-                wa_message.clone()
-            ).await {
-                Ok(msg) => {
-                    message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
-                    send_ui_message(ui::main_screen::Message::UpdateMessageHistory).await;
-                    break msg;
-                }
-                Err(e) => {
-                    message.set_status(SendStatus::Failed, std::sync::atomic::Ordering::Relaxed);
-                    send_ui_message(ui::Message::Notification(e.to_string())).await;
-                }
+        })
+        .collect()
+}
+
+/// Sends one part, retrying until it is sent. The group is marked failed while retrying
+async fn send_part(channel: &UnboundedSender<ui::Message>, client: &Client, message: &SendMessageInfo, group: &GroupInfoWhatsapp, wa_message: wa::Message) -> String {
+    let key = Key::Whatsapp(group.account, group.key.clone());
+    rate_limit::acquire(&key, message.priority).await;
+    loop {
+        let start = Instant::now();
+        match client.send_message(
+            group.key.clone(),
+            wa_message.clone()
+        ).await {
+            Ok(msg) => {
+                METRICS.sent(key.clone(), start.elapsed());
+                group.set_failed(false);
+                message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
+                send_ui_message(channel, ui::main_screen::Message::UpdateMessageHistory);
+                break msg;
             }
-        };
-        
-        group.set_id(message_id);
+            Err(e) => {
+                METRICS.failed(key.clone());
+                group.set_failed(true);
+                send_ui_message(channel, ui::Message::Notification(e.to_string()));
+            }
+        }
     }
+}
 
-    message.set_status(SendStatus::Sent, std::sync::atomic::Ordering::Relaxed);
+/// Deletes one part, returns whether it was deleted
+async fn revoke_part(channel: &UnboundedSender<ui::Message>, client: &Client, message: &SendMessageInfo, jid: &Jid, id: String) -> bool {
+    match client.revoke_message(jid.clone(), id, whatsapp_rust::RevokeType::Sender).await {
+        Ok(()) => {
+            message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
+            send_ui_message(channel, ui::main_screen::Message::UpdateMessageHistory);
+            true
+        },
+        Err(e) => {
+            log::warn!("Error deleting message: {e}");
+            message.set_status(SendStatus::Failed, std::sync::atomic::Ordering::Relaxed);
+            send_ui_message(channel, ui::Message::Notification(e.to_string()));
+            false
+        }
+    }
 }
 
-pub async fn edit_message(client: Arc<Client>, message: Arc<SendMessageInfo>, message_ids: Vec<String>, markdown: bool) {
+pub async fn send_message(channel: UnboundedSender<ui::Message>, account: AccountId, client: Arc<Client>, message: Arc<SendMessageInfo>, markdown: bool) {
     message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
-    let content = if markdown {
-        parse_message_with_whatsapp_format(&message.content).unwrap_or(message.content.clone())
+
+    for group in message.groups_whatsapp.iter().filter(|g| g.account == account) {
+        group.set_ids(Vec::new());
+        for wa_message in whatsapp_parts(&message, &Key::Whatsapp(account, group.key.clone()), group.send_mode, markdown) {
+            let message_id = send_part(&channel, &client, &message, group, wa_message).await;
+            group.push_id(message_id);
+        }
     }
-    else {
-        message.content.clone()
-    };
+
+    message.finish_job();
+    send_ui_message(&channel, ui::main_screen::Message::UpdateMessageHistory);
+}
+
+/// Edits sent parts in place. Parts the message didn't have before are sent, parts it doesn't have anymore are deleted
+pub async fn edit_message(channel: UnboundedSender<ui::Message>, account: AccountId, client: Arc<Client>, message: Arc<SendMessageInfo>, message_ids: Vec<Vec<String>>, markdown: bool) {
+    message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
     
-    for (group, message_id) in message.groups_whatsapp.iter().zip(message_ids.into_iter()) {
-        let wa_message = wa::Message {
-            conversation: Some(if let SendMode::Frequency = group.send_mode && let Some(ref freq) = message.freq {
-                    format!("{}\n{}", freq, &content)
-                }
-                else {
-                    content.clone()
-                }),
-            ..Default::default()
-        };
-        let message_id = loop {
-            match client.edit_message(
-                group.key.clone(),
-                message_id.clone(),
-                wa_message.clone()
-            ).await {
-                Ok(msg) => {
-                    message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
-                    send_ui_message(ui::main_screen::Message::UpdateMessageHistory).await;
-                    break msg;
-                }
-                Err(e) => {
-                    message.set_status(SendStatus::Failed, std::sync::atomic::Ordering::Relaxed);
-                    send_ui_message(ui::Message::Notification(e.to_string())).await;
+    // Ids are taken from groups of all accounts, so they are matched before filtering
+    for (group, message_ids) in message.groups_whatsapp.iter().zip(message_ids.into_iter()).filter(|(g, _)| g.account == account) {
+        let mut message_ids = message_ids.into_iter();
+        for wa_message in whatsapp_parts(&message, &Key::Whatsapp(account, group.key.clone()), group.send_mode, markdown) {
+            let Some(message_id) = message_ids.next() else {
+                let message_id = send_part(&channel, &client, &message, group, wa_message).await;
+                group.push_id(message_id);
+                continue
+            };
+            let message_id = loop {
+                match client.edit_message(
+                    group.key.clone(),
+                    message_id.clone(),
+                    wa_message.clone()
+                ).await {
+                    Ok(msg) => {
+                        message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
+                        send_ui_message(&channel, ui::main_screen::Message::UpdateMessageHistory);
+                        break msg;
+                    }
+                    Err(e) => {
+                        message.set_status(SendStatus::Failed, std::sync::atomic::Ordering::Relaxed);
+                        send_ui_message(&channel, ui::Message::Notification(e.to_string()));
+                    }
                 }
-            }
-        };
-
-        group.set_id(message_id);
+            };
+            group.push_id(message_id);
+        }
+        for id in message_ids {
+            revoke_part(&channel, &client, &message, &group.key, id).await;
+        }
     }
 
     message.set_status(SendStatus::Sent, std::sync::atomic::Ordering::Relaxed);
 }
 
-pub async fn delete_message(client: Arc<Client>, message: Arc<SendMessageInfo>) {
+pub async fn delete_message(channel: UnboundedSender<ui::Message>, account: AccountId, client: Arc<Client>, message: Arc<SendMessageInfo>) {
     message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
 
-    for group in message.groups_whatsapp.iter() {
-        if let Some(id) = group.message_id() {
-            match client.revoke_message(group.key.clone(), id, whatsapp_rust::RevokeType::Sender).await {
-                Ok(()) => {
-                    group.delete(std::sync::atomic::Ordering::Relaxed);
-                    message.set_status(SendStatus::Sending, std::sync::atomic::Ordering::Relaxed);
-                    send_ui_message(ui::main_screen::Message::UpdateMessageHistory).await;
-                },
-                Err(e) => {
-                    log::warn!("Error deleting message: {e}");
-                    message.set_status(SendStatus::Failed, std::sync::atomic::Ordering::Relaxed);
-                    send_ui_message(ui::Message::Notification(e.to_string())).await;
-                }
-                
+    for group in message.groups_whatsapp.iter().filter(|g| g.account == account) {
+        // Every part of split message is deleted, ones which failed stay sent
+        let mut failed = Vec::new();
+        for id in group.message_ids() {
+            if !revoke_part(&channel, &client, &message, &group.key, id.clone()).await {
+                failed.push(id);
             }
         }
+        group.set_ids(failed);
     }
 
     message.set_status(SendStatus::Deleted, std::sync::atomic::Ordering::Relaxed);
-    send_ui_message(ui::main_screen::Message::UpdateMessageHistory).await;
+    send_ui_message(&channel, ui::main_screen::Message::UpdateMessageHistory);
 }
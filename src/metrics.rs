@@ -0,0 +1,179 @@
+use std::{collections::HashMap, fmt::Write, sync::{LazyLock, Mutex, atomic::{AtomicU64, Ordering}}, time::Duration};
+
+use crate::messangers::Key;
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Upper bounds of send latency histogram buckets in seconds
+const LATENCY_BUCKETS: [f64; 9] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+const MESSENGERS: [&str; 2] = ["Signal", "Whatsapp"];
+
+#[derive(Debug, Default, Clone, Copy)]
+struct GroupCounters {
+    sent: u64,
+    failed: u64,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, value: Duration) {
+        let secs = value.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|bound| secs <= *bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(value.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Counters exposed in Prometheus text format on `/metrics`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    received: AtomicU64,
+    routed: AtomicU64,
+    queue_depth: AtomicU64,
+    reconnects: [AtomicU64; MESSENGERS.len()],
+    groups: Mutex<HashMap<Key, GroupCounters>>,
+    /// Titles of groups by key, published by the application
+    titles: Mutex<HashMap<String, String>>,
+    latency: Histogram,
+}
+
+impl Metrics {
+    pub fn message_received(&self, count: usize) {
+        self.received.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn message_routed(&self) {
+        self.routed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    pub fn reconnect(&self, messenger: &str) {
+        if let Some(i) = MESSENGERS.iter().position(|m| *m == messenger) {
+            self.reconnects[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn sent(&self, group: Key, latency: Duration) {
+        self.latency.observe(latency);
+        self.groups.lock().unwrap().entry(group).or_default().sent += 1;
+    }
+
+    pub fn failed(&self, group: Key) {
+        self.groups.lock().unwrap().entry(group).or_default().failed += 1;
+    }
+
+    pub fn set_titles(&self, titles: HashMap<String, String>) {
+        *self.titles.lock().unwrap() = titles;
+    }
+
+    /// Renders metrics, naming groups by their titles instead of keys, which are secret for Signal
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let titles = self.titles.lock().unwrap();
+        let groups = self.groups.lock().unwrap()
+            .iter()
+            .map(|(key, counters)| (key.messenger(), titles.get(&key.to_string()).map_or("unknown", String::as_str), *counters))
+            .collect::<Vec<_>>();
+
+        macro_rules! header {
+            ($name:literal, $kind:literal, $help:literal) => {
+                _ = writeln!(out, concat!("# HELP ", $name, " ", $help));
+                _ = writeln!(out, concat!("# TYPE ", $name, " ", $kind));
+            };
+        }
+
+        header!("sender_messages_received_total", "counter", "Messages received for sending");
+        _ = writeln!(out, "sender_messages_received_total {}", self.received.load(Ordering::Relaxed));
+
+        header!("sender_messages_routed_total", "counter", "Messages routed to at least one group");
+        _ = writeln!(out, "sender_messages_routed_total {}", self.routed.load(Ordering::Relaxed));
+
+        header!("sender_messenger_sent_total", "counter", "Messages sent to groups per messenger");
+        for messenger in MESSENGERS {
+            let sent: u64 = groups.iter().filter(|(m, _, _)| *m == messenger).map(|(_, _, c)| c.sent).sum();
+            _ = writeln!(out, "sender_messenger_sent_total{{messenger=\"{messenger}\"}} {sent}");
+        }
+
+        header!("sender_messenger_failed_total", "counter", "Failed attempts to send to groups per messenger");
+        for messenger in MESSENGERS {
+            let failed: u64 = groups.iter().filter(|(m, _, _)| *m == messenger).map(|(_, _, c)| c.failed).sum();
+            _ = writeln!(out, "sender_messenger_failed_total{{messenger=\"{messenger}\"}} {failed}");
+        }
+
+        header!("sender_group_sent_total", "counter", "Messages sent per group");
+        for (messenger, title, counters) in groups.iter() {
+            _ = writeln!(out, "sender_group_sent_total{{messenger=\"{messenger}\",group=\"{}\"}} {}", escape_label(title), counters.sent);
+        }
+
+        header!("sender_group_failed_total", "counter", "Failed attempts to send per group");
+        for (messenger, title, counters) in groups.iter() {
+            _ = writeln!(out, "sender_group_failed_total{{messenger=\"{messenger}\",group=\"{}\"}} {}", escape_label(title), counters.failed);
+        }
+
+        header!("sender_queue_depth", "gauge", "Messages waiting in Signal send queue");
+        _ = writeln!(out, "sender_queue_depth {}", self.queue_depth.load(Ordering::Relaxed));
+
+        header!("sender_send_latency_seconds", "histogram", "Time to send message to a single group");
+        let mut cumulative = 0;
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(self.latency.buckets.iter()) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            _ = writeln!(out, "sender_send_latency_seconds_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let count = self.latency.count.load(Ordering::Relaxed);
+        _ = writeln!(out, "sender_send_latency_seconds_bucket{{le=\"+Inf\"}} {count}");
+        _ = writeln!(out, "sender_send_latency_seconds_sum {}", self.latency.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+        _ = writeln!(out, "sender_send_latency_seconds_count {count}");
+
+        header!("sender_reconnects_total", "counter", "Reconnections to messengers");
+        for (messenger, reconnects) in MESSENGERS.iter().zip(self.reconnects.iter()) {
+            _ = writeln!(out, "sender_reconnects_total{{messenger=\"{messenger}\"}} {}", reconnects.load(Ordering::Relaxed));
+        }
+
+        out
+    }
+}
+
+fn escape_label(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::Metrics;
+    use crate::messangers::Key;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+        let key = Key::Signal(0, [1; 32]);
+        let titles = HashMap::from([(key.to_string(), "Група \"1\"".to_owned())]);
+
+        metrics.set_titles(titles);
+        metrics.message_received(2);
+        metrics.sent(key.clone(), Duration::from_millis(300));
+        metrics.sent(key.clone(), Duration::from_secs(100));
+        metrics.failed(key);
+
+        let out = metrics.render();
+        assert!(out.contains("sender_messages_received_total 2\n"));
+        assert!(out.contains("sender_messenger_sent_total{messenger=\"Signal\"} 2\n"));
+        assert!(out.contains("sender_group_failed_total{messenger=\"Signal\",group=\"Група \\\"1\\\"\"} 1\n"));
+        assert!(out.contains("sender_send_latency_seconds_bucket{le=\"0.25\"} 0\n"));
+        assert!(out.contains("sender_send_latency_seconds_bucket{le=\"0.5\"} 1\n"));
+        assert!(out.contains("sender_send_latency_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(!out.contains(&"01".repeat(32)));
+    }
+}
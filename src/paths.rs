@@ -0,0 +1,85 @@
+use std::{path::{Path, PathBuf}, sync::OnceLock};
+
+pub const DEFAULT_PROFILE: &str = "default";
+/// File in data directory storing the name of last used profile
+const PROFILE_FILE: &str = "profile";
+const PROFILES_DIR: &str = "profiles";
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Sets up data directory and active profile. Must be called before anything is loaded from disk.
+/// Without `data_dir` `~/.sender` is used, without `profile` the last used one is chosen
+pub fn init(data_dir: Option<PathBuf>, profile: Option<String>) -> std::io::Result<()> {
+    let data_dir = data_dir.unwrap_or_else(|| match std::env::home_dir() {
+        Some(home) => home.join(".sender"),
+        None => PathBuf::new(),
+    });
+    let profile = profile
+        .or_else(|| std::fs::read_to_string(data_dir.join(PROFILE_FILE)).ok())
+        .map(|p| p.trim().to_owned())
+        .filter(|p| valid_profile_name(p))
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_owned());
+
+    _ = DATA_DIR.set(data_dir);
+    _ = PROFILE.set(profile);
+    std::fs::create_dir_all(profile_dir())
+}
+
+pub fn data_dir() -> &'static Path {
+    DATA_DIR.get().map_or(Path::new(""), PathBuf::as_path)
+}
+
+pub fn active_profile() -> &'static str {
+    PROFILE.get().map_or(DEFAULT_PROFILE, String::as_str)
+}
+
+/// Directory with settings and messenger stores of active profile.
+/// Default profile lives in data directory itself, so older installs keep their data
+pub fn profile_dir() -> PathBuf {
+    dir_of(active_profile())
+}
+
+/// Path of file inside active profile directory
+pub fn profile_file(name: &str) -> PathBuf {
+    profile_dir().join(name)
+}
+
+fn dir_of(profile: &str) -> PathBuf {
+    if profile == DEFAULT_PROFILE {
+        data_dir().to_owned()
+    }
+    else {
+        data_dir().join(PROFILES_DIR).join(profile)
+    }
+}
+
+/// Names of all existing profiles, starting with the default one
+pub fn profiles() -> Vec<String> {
+    let mut profiles = std::fs::read_dir(data_dir().join(PROFILES_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name != DEFAULT_PROFILE && valid_profile_name(name))
+        .collect::<Vec<_>>();
+    profiles.sort();
+    profiles.insert(0, DEFAULT_PROFILE.to_owned());
+    profiles
+}
+
+/// Creates profile if needed and makes it used on the next start
+pub fn set_active_profile(profile: &str) -> std::io::Result<()> {
+    if !valid_profile_name(profile) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid profile name"));
+    }
+    std::fs::create_dir_all(dir_of(profile))?;
+    std::fs::write(data_dir().join(PROFILE_FILE), profile)
+}
+
+/// Profile name is used as directory name, so only letters, digits, spaces, `-` and `_` are allowed
+pub fn valid_profile_name(name: &str) -> bool {
+    !name.trim().is_empty()
+    && name.chars().all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+}
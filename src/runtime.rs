@@ -0,0 +1,650 @@
+use std::{collections::{BTreeMap, HashMap, HashSet, VecDeque}, sync::Arc};
+
+use futures::{FutureExt, channel::mpsc::UnboundedSender, future::BoxFuture};
+use jiff::Timestamp;
+
+use crate::{appdata::AppData, crypto, message::{OperatorMessage, Priority, SendMode}, message_server::{self, AccountStatusDto, StatusDto}, messangers::{AccountId, Key, Messenger, rate_limit, signal::{SignalMessage, SignalWorker}, whatsapp}, metrics::METRICS, notification, send_categories::{Destination, DigestBatch, NetworkInfo, QueuedMessage, RoutingInfo, route_groups}, ui::{self, Message, main_screen, message_history::{SendMessageInfo, SendStatus}, side_menu::LinkState}};
+
+mod api;
+
+/// Background work produced by the core. GUI runs it as `Task`, headless mode spawns it on runtime
+pub type Job = BoxFuture<'static, ()>;
+
+/// State and logic shared by GUI and headless mode: settings, messengers, routing and ingest server
+pub struct Core {
+    pub data: AppData,
+    signal: BTreeMap<AccountId, SignalConnection>,
+    whatsapp: BTreeMap<AccountId, WhatsappConnection>,
+    message_channel: Option<UnboundedSender<Message>>,
+}
+
+/// Worker of one Signal account and its state
+struct SignalConnection {
+    state: LinkState,
+    /// Account has received all queued messages after linking
+    synced: bool,
+    task_send: UnboundedSender<SignalMessage>,
+}
+
+#[derive(Default)]
+struct WhatsappConnection {
+    state: LinkState,
+    client: Option<Arc<whatsapp_rust::Client>>,
+}
+
+impl Core {
+    pub fn new(data: AppData) -> Self {
+        Self {
+            data,
+            signal: BTreeMap::new(),
+            whatsapp: BTreeMap::new(),
+            message_channel: None,
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        self.data.save()
+    }
+
+    /// Spawns Signal workers and connects to accounts user has logged in before.
+    /// Messages from messengers and ingest server are passed to `channel`
+    pub fn setup(&mut self, channel: UnboundedSender<Message>) -> Vec<Job> {
+        self.message_channel = Some(channel);
+        self.wipe_plain_stores();
+
+        let accounts = self.data.signal_accounts.iter().map(|a| (a.id, a.logged)).collect::<Vec<_>>();
+        for (account, logged) in accounts {
+            self.spawn_signal(account);
+            if logged {
+                self.signal(account, SignalMessage::LinkBegin);
+            }
+        }
+        let accounts = self.data.whatsapp_accounts.iter().map(|a| (a.id, a.logged)).collect::<Vec<_>>();
+        let mut jobs = Vec::new();
+        for (account, logged) in accounts {
+            self.whatsapp.insert(account, WhatsappConnection::default());
+            if logged {
+                jobs.push(self.link_whatsapp(account));
+            }
+        }
+        self.publish_status();
+        jobs
+    }
+
+    /// Stores created before encryption was enabled are wiped, their accounts have to be linked again
+    fn wipe_plain_stores(&mut self) {
+        if !crypto::unlocked() {
+            return
+        }
+        for messenger in [Messenger::Signal, Messenger::Whatsapp] {
+            let ids = self.data.accounts(messenger).iter().map(|a| a.id).collect::<Vec<_>>();
+            for id in ids {
+                let path = messenger.store_path(id);
+                if !crypto::is_plain_sqlite(&path) {
+                    continue
+                }
+                log::warn!("Wiping unencrypted store {}", path.display());
+                if let Err(e) = crypto::wipe_store(&path) {
+                    log::error!("Error wiping {}: {e}", path.display());
+                }
+                if let Some(account) = self.data.account_mut(messenger, id) {
+                    account.logged = false;
+                }
+            }
+        }
+    }
+
+    /// Ingest server future. Must be called after `setup`
+    pub fn server(&self) -> Job {
+        message_server::start_server(
+            self.data.recieve_address,
+            self.data.api_token.clone(),
+            self.message_channel.clone().unwrap(),
+        )
+        .map(|res| if let Err(e) = res {
+            log::error!("Server error: {e}");
+        })
+        .boxed()
+    }
+
+    fn spawn_signal(&mut self, account: AccountId) {
+        let Some(channel) = self.message_channel.clone() else {
+            return
+        };
+        let (task_tx, task_rx) = futures::channel::mpsc::unbounded();
+        SignalWorker::spawn_new(account, task_rx, channel, task_tx.clone());
+        self.signal.insert(account, SignalConnection { state: LinkState::Unlinked, synced: false, task_send: task_tx });
+    }
+
+    pub fn signal(&self, account: AccountId, message: SignalMessage) {
+        if let Some(connection) = self.signal.get(&account) {
+            _ = connection.task_send.unbounded_send(message);
+        }
+    }
+
+    pub fn link_state(&self, messenger: Messenger, account: AccountId) -> LinkState {
+        match messenger {
+            Messenger::Signal => self.signal.get(&account).map_or(LinkState::Unlinked, |c| c.state),
+            Messenger::Whatsapp => self.whatsapp.get(&account).map_or(LinkState::Unlinked, |c| c.state),
+        }
+    }
+
+    /// Starts linking of the account, QR code is passed to the message channel
+    pub fn link(&mut self, messenger: Messenger, account: AccountId) -> Vec<Job> {
+        match messenger {
+            Messenger::Signal => {
+                self.signal(account, SignalMessage::LinkBegin);
+                vec![]
+            },
+            Messenger::Whatsapp => vec![self.link_whatsapp(account)],
+        }
+    }
+
+    /// Adds account and starts its worker. Linking is started by user
+    pub fn add_account(&mut self, messenger: Messenger) -> Option<AccountId> {
+        let account = self.data.add_account(messenger)?;
+        match messenger {
+            Messenger::Signal => self.spawn_signal(account),
+            Messenger::Whatsapp => {
+                self.whatsapp.insert(account, WhatsappConnection::default());
+            },
+        }
+        self.publish_status();
+        Some(account)
+    }
+
+    /// Disconnects account. Accounts except the first one are removed together with their groups.
+    /// Store is removed by caller
+    pub fn remove_account(&mut self, messenger: Messenger, account: AccountId) {
+        match messenger {
+            Messenger::Signal => {
+                self.signal(account, SignalMessage::Disconnect);
+                if account != 0 {
+                    self.signal.remove(&account);
+                }
+            },
+            Messenger::Whatsapp => {
+                if account != 0 {
+                    self.whatsapp.remove(&account);
+                }
+                else {
+                    self.whatsapp.insert(account, WhatsappConnection::default());
+                }
+            },
+        }
+        self.data.remove_account(messenger, account);
+        self.publish_status();
+    }
+
+    /// Passes notification to the user. In headless mode it is only logged
+    pub fn notify(&self, text: impl ToString) {
+        if let Some(channel) = self.message_channel.as_ref() {
+            _ = channel.unbounded_send(notification!(text));
+        }
+    }
+
+    pub fn link_whatsapp(&mut self, account: AccountId) -> Job {
+        self.whatsapp.entry(account).or_default().state = LinkState::Linking;
+        self.publish_status();
+        let Some(channel) = self.message_channel.clone() else {
+            return futures::future::ready(()).boxed()
+        };
+        whatsapp::start_whatsapp_task(account, channel).boxed()
+    }
+
+    pub fn set_signal_state(&mut self, account: AccountId, state: LinkState) {
+        if let Some(connection) = self.signal.get_mut(&account) {
+            connection.state = state;
+        }
+        if state == LinkState::Linked && let Some(account) = self.data.account_mut(Messenger::Signal, account) {
+            account.logged = true
+        }
+        self.publish_status();
+    }
+
+    pub fn signal_synced(&mut self, account: AccountId) {
+        if let Some(connection) = self.signal.get_mut(&account) {
+            connection.synced = true;
+        }
+        self.publish_status();
+    }
+
+    pub fn set_whatsapp_state(&mut self, account: AccountId, state: LinkState) {
+        if let Some(connection) = self.whatsapp.get_mut(&account) {
+            connection.state = state;
+        }
+        if state == LinkState::Linked && let Some(account) = self.data.account_mut(Messenger::Whatsapp, account) {
+            account.logged = true
+        }
+        self.publish_status();
+    }
+
+    pub fn set_whatsapp_client(&mut self, account: AccountId, client: Option<Arc<whatsapp_rust::Client>>) -> LinkState {
+        let Some(connection) = self.whatsapp.get_mut(&account) else {
+            return LinkState::Unlinked
+        };
+        connection.client = client;
+        if connection.client.is_some() { LinkState::Linked } else { LinkState::Unlinked }
+    }
+
+    pub fn signal_disconnected(&mut self, account: AccountId) {
+        if let Some(connection) = self.signal.get_mut(&account) {
+            connection.state = LinkState::Disconnected;
+            connection.synced = false;
+        }
+        self.publish_status();
+        METRICS.reconnect("Signal");
+        self.signal(account, SignalMessage::Disconnect);
+        self.signal(account, SignalMessage::LinkBegin);
+    }
+
+    pub fn status(&self) -> StatusDto {
+        let accounts = |messenger: Messenger| self.data.accounts(messenger).iter()
+            .map(|account| {
+                let state = self.link_state(messenger, account.id);
+                AccountStatusDto {
+                    account: account.id,
+                    name: account.name.clone(),
+                    state,
+                    synced: match messenger {
+                        Messenger::Signal => self.signal.get(&account.id).is_some_and(|c| c.synced),
+                        Messenger::Whatsapp => state == LinkState::Linked,
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        StatusDto {
+            signal: accounts(Messenger::Signal),
+            whatsapp: accounts(Messenger::Whatsapp),
+        }
+    }
+
+    /// Updates snapshot of account status and group titles read by `/readyz` and `/metrics`,
+    /// so they answer without waiting for the application
+    pub fn publish_status(&self) {
+        *message_server::STATUS.write().unwrap() = Some(self.status());
+        METRICS.set_titles(self.data.groups.iter().map(|(key, group)| (key.to_string(), group.title.clone())).collect());
+    }
+
+    pub fn set_groups(&mut self, groups: Vec<(Key, String)>) {
+        self.data.set_groups(groups);
+        self.publish_status();
+    }
+
+    fn linked_signal(&self) -> impl Iterator<Item = AccountId> {
+        self.signal.iter()
+            .filter(|(_, c)| c.state == LinkState::Linked)
+            .map(|(account, _)| *account)
+    }
+
+    fn whatsapp_clients(&self) -> impl Iterator<Item = (AccountId, Arc<whatsapp_rust::Client>)> {
+        self.whatsapp.iter()
+            .filter_map(|(account, c)| c.client.clone().map(|client| (*account, client)))
+    }
+
+    pub fn update_groups(&self) -> Vec<Job> {
+        for account in self.linked_signal() {
+            self.signal(account, SignalMessage::GetGroups);
+        }
+        let Some(channel) = self.message_channel.as_ref() else {
+            return vec![]
+        };
+        self.whatsapp_clients()
+            .map(|(account, client)| {
+                let channel = channel.clone();
+                async move {
+                    let message: Message = whatsapp::get_groups(account, client).await.map(main_screen::Message::SetGroups).into();
+                    _ = channel.unbounded_send(message);
+                }.boxed()
+            })
+            .collect()
+    }
+
+    /// Sends message through linked accounts it has groups in. Groups of accounts which are not linked are marked failed
+    /// and user is asked to link them, message is dropped only when none of its groups can be reached
+    pub fn send_message(&self, message: Arc<SendMessageInfo>) -> Vec<Job> {
+        let Some(channel) = self.message_channel.as_ref() else {
+            return vec![]
+        };
+        self.set_rate_limits();
+        let signal_accounts = message.accounts(Messenger::Signal).into_iter()
+            .filter(|account| matches!(self.link_state(Messenger::Signal, *account), LinkState::Linked | LinkState::Disconnected))
+            .collect::<Vec<_>>();
+        let whatsapp_clients = message.accounts(Messenger::Whatsapp).into_iter()
+            .filter_map(|account| Some((account, self.whatsapp.get(&account)?.client.clone()?)))
+            .collect::<Vec<_>>();
+
+        let unlinked = self.data.signal_accounts.iter()
+            .filter(|account| !signal_accounts.contains(&account.id) && message.groups_signal.iter().any(|g| g.account == account.id))
+            .chain(self.data.whatsapp_accounts.iter()
+                .filter(|account| whatsapp_clients.iter().all(|(id, _)| *id != account.id) && message.groups_whatsapp.iter().any(|g| g.account == account.id)))
+            .collect::<Vec<_>>();
+        for account in unlinked.iter() {
+            self.notify(format!("Прив'яжіть, будь ласка, Modern Sender до {}", account.name));
+        }
+        if !unlinked.is_empty() && signal_accounts.is_empty() && whatsapp_clients.is_empty() {
+            message.set_status(SendStatus::Deleted, std::sync::atomic::Ordering::Relaxed);
+            return vec![]
+        }
+
+        for group in message.groups_signal.iter().filter(|g| !signal_accounts.contains(&g.account)) {
+            group.set_failed(true);
+        }
+        for group in message.groups_whatsapp.iter().filter(|g| whatsapp_clients.iter().all(|(id, _)| *id != g.account)) {
+            group.set_failed(true);
+        }
+        message.start_jobs(signal_accounts.len() + whatsapp_clients.len());
+        for account in signal_accounts {
+            self.signal(account, SignalMessage::SendMessage(message.clone(), self.data.markdown));
+        }
+        whatsapp_clients.into_iter()
+            .map(|(account, client)| whatsapp::send_message(channel.clone(), account, client, message.clone(), self.data.markdown).boxed())
+            .collect()
+    }
+
+    pub fn delete_message(&self, message: Arc<SendMessageInfo>) -> Vec<Job> {
+        message.set_status(SendStatus::Pending, std::sync::atomic::Ordering::Relaxed);
+        for account in self.linked_signal() {
+            self.signal(account, SignalMessage::DeleteMessage(message.clone()));
+        }
+        let Some(channel) = self.message_channel.as_ref() else {
+            return vec![]
+        };
+        self.whatsapp_clients()
+            .map(|(account, client)| whatsapp::delete_message(channel.clone(), account, client, message.clone()).boxed())
+            .collect()
+    }
+
+    pub fn edit_message(&self, message: Arc<SendMessageInfo>, timestamps: Vec<Vec<u64>>, whatsapp_ids: Vec<Vec<String>>) -> Vec<Job> {
+        self.set_rate_limits();
+        for account in self.linked_signal() {
+            self.signal(account, SignalMessage::EditMessage(message.clone(), timestamps.clone(), self.data.markdown));
+        }
+        let Some(channel) = self.message_channel.as_ref() else {
+            return vec![]
+        };
+        self.whatsapp_clients()
+            .map(|(account, client)| whatsapp::edit_message(channel.clone(), account, client, message.clone(), whatsapp_ids.clone(), self.data.markdown).boxed())
+            .collect()
+    }
+
+    /// Passes rate limits of groups to senders
+    fn set_rate_limits(&self) {
+        rate_limit::set_limits(self.data.groups.iter().filter_map(|(key, group)| Some((key.clone(), group.rate_limit?))));
+    }
+
+    pub fn cancel_message(&mut self, message: Arc<SendMessageInfo>) {
+        if let Some(channel) = self.message_channel.as_mut() {
+            message.cancel(channel);
+        }
+    }
+
+    /// Remembers sources and comments of received messages
+    pub fn accept_messages(&mut self, messages: &[OperatorMessage]) {
+        METRICS.message_received(messages.len());
+        for m in messages.iter() {
+            if !self.data.sources.contains(&m.source) {
+                self.data.sources.insert(m.source.clone());
+            }
+            if let Some(comment) = &m.comment && !self.data.comments.contains(comment) {
+                self.data.comments.insert(comment.clone());
+            }
+        }
+    }
+
+    /// Creates message with groups chosen by categories, text conditions are matched against `text`
+    pub fn route_message(&mut self, text: String, mut info: RoutingInfo) -> Arc<SendMessageInfo> {
+        info.text = Some(text.clone());
+        let groups = self.route_groups(&info, None);
+        let mut message = SendMessageInfo::new(text, info.freq.clone());
+        message.priority = self.priority(&info, groups.values());
+        if !groups.is_empty() {
+            METRICS.message_routed();
+        }
+        for (key, destination) in groups {
+            message.push(key, destination.mode);
+        }
+        Arc::new(message)
+    }
+
+    /// Routes operator message the same way as if user sent it without changes.
+    /// Groups with own template, included by category with one or of messenger with one get the message rendered with it
+    pub fn route_operator_message(&mut self, message: &OperatorMessage) -> Arc<SendMessageInfo> {
+        let text = message.format(self.data.formatting.as_ref());
+        let mut info = RoutingInfo::from(message);
+        info.text = Some(text.clone());
+        let groups = self.route_groups(&info, Some(message));
+        let mut send = SendMessageInfo::new(text, info.freq.clone());
+        if groups.is_empty() {
+            return Arc::new(send)
+        }
+
+        METRICS.message_routed();
+        send.priority = self.priority(&info, groups.values());
+        for (key, destination) in groups {
+            if let Some(text) = self.render(message, &key, destination.categories()).filter(|text| *text != send.content) {
+                send.overrides.insert(key.clone(), text);
+            }
+            send.push(key, destination.mode);
+        }
+        Arc::new(send)
+    }
+
+    /// The highest of priorities of the message and of categories it is routed by
+    fn priority<'a>(&self, info: &RoutingInfo, destinations: impl Iterator<Item = &'a Destination>) -> Priority {
+        let names = destinations.flat_map(|destination| destination.categories()).collect::<HashSet<_>>();
+        self.data.categories.iter()
+            .filter(|category| names.contains(category.name()))
+            .map(|category| category.priority)
+            .chain(info.priority)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Operator message rendered with template of the group, of the first category including it or of the messenger
+    fn render<'a>(&self, message: &OperatorMessage, key: &Key, mut categories: impl Iterator<Item = &'a str>) -> Option<String> {
+        let formatting = self.data.groups.get(key)
+            .and_then(|group| group.formatting.as_ref())
+            .or_else(|| categories.find_map(|name| self.data.categories.iter().find(|c| c.name() == name)?.formatting.as_ref()))
+            .or_else(|| self.data.messenger_formatting.get(&Messenger::from(key)))?;
+        Some(formatting.format_message(message))
+    }
+
+    /// Groups message is sent to now. Groups of categories off their schedule which queue messages
+    /// get it when the schedule starts, see `release_queued`
+    fn route_groups(&mut self, info: &RoutingInfo, message: Option<&OperatorMessage>) -> HashMap<Key, Destination> {
+        let routed = route_groups(&self.data.categories, &self.data.groups, &self.data.blocked_groups, info);
+        let text = info.text.clone().unwrap_or_default();
+        for (category, groups) in routed.queued {
+            let priority = self.category_priority(&category, info);
+            let overrides = groups.keys()
+                .filter_map(|key| {
+                    let rendered = self.render(message?, key, std::iter::once(category.as_str()))?;
+                    (rendered != text).then(|| (key.clone(), rendered))
+                })
+                .collect();
+            self.data.queued_messages.push(QueuedMessage {
+                category,
+                text: text.clone(),
+                overrides,
+                freq: info.freq.clone(),
+                groups,
+                received: info.time.unwrap_or_else(Timestamp::now),
+                priority,
+            });
+        }
+        for (category, groups) in routed.digests {
+            let priority = self.category_priority(&category, info);
+            let Some(digest) = self.data.categories.iter()
+                .find(|c| c.name() == category)
+                .and_then(|c| c.digest.as_ref()) else {
+                continue
+            };
+            let batch = match self.data.digests.iter().position(|batch| batch.category == category) {
+                Some(index) => &mut self.data.digests[index],
+                None => {
+                    self.data.digests.push(DigestBatch::new(category, info.time.unwrap_or_else(Timestamp::now)));
+                    self.data.digests.last_mut().unwrap()
+                },
+            };
+            batch.push(digest.template.item(message, &text), groups, priority);
+            if batch.is_due(digest, Timestamp::now()) {
+                self.check_schedules();
+            }
+        }
+        routed.groups
+    }
+
+    /// Makes the application release due messages right away instead of on the next periodic check
+    fn check_schedules(&self) {
+        if let Some(channel) = self.message_channel.as_ref() {
+            _ = channel.unbounded_send(Message::CheckSchedules);
+        }
+    }
+
+    /// The highest of priorities of the message and of the category
+    fn category_priority(&self, name: &str, info: &RoutingInfo) -> Priority {
+        self.data.categories.iter()
+            .find(|c| c.name() == name)
+            .map(|c| c.priority)
+            .into_iter()
+            .chain(info.priority)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Takes queued messages whose categories are live now. Messages of deleted categories are dropped
+    pub fn release_queued(&mut self) -> Vec<Arc<SendMessageInfo>> {
+        let now = Timestamp::now();
+        let mut ready = Vec::new();
+        for queued in std::mem::take(&mut self.data.queued_messages) {
+            match self.data.categories.iter().find(|c| c.name() == queued.category) {
+                Some(category) if category.is_live(now) => ready.push(queued),
+                Some(_) => self.data.queued_messages.push(queued),
+                None => log::warn!("Category {} of queued message is deleted, dropping message", queued.category),
+            }
+        }
+
+        ready.into_iter()
+            .map(|queued| {
+                log::info!("Schedule of category {} started, sending queued message", queued.category);
+                let mut message = SendMessageInfo::new(queued.text, queued.freq);
+                message.overrides = queued.overrides;
+                message.priority = queued.priority;
+                for (key, mode) in queued.groups.into_iter().filter(|(key, _)| !self.data.blocked_groups.contains(key)) {
+                    message.push(key, mode);
+                }
+                Arc::new(message)
+            })
+            .collect()
+    }
+
+    /// Takes digests which collected enough messages or waited their interval. Digests of deleted categories are dropped,
+    /// ones of categories which stopped collecting messages are sent right away
+    pub fn release_digests(&mut self) -> Vec<Arc<SendMessageInfo>> {
+        let now = Timestamp::now();
+        let mut ready = Vec::new();
+        for batch in std::mem::take(&mut self.data.digests) {
+            match self.data.categories.iter().find(|c| c.name() == batch.category) {
+                Some(category) => match &category.digest {
+                    Some(digest) if !batch.is_due(digest, now) => self.data.digests.push(batch),
+                    digest => ready.push((digest.as_ref().map(|d| d.template.clone()).unwrap_or_default(), batch)),
+                },
+                None => log::warn!("Category {} of digest is deleted, dropping {} messages", batch.category, batch.items.len()),
+            }
+        }
+
+        ready.into_iter()
+            .map(|(template, batch)| {
+                log::info!("Sending digest of category {} with {} messages", batch.category, batch.items.len());
+                let mut message = SendMessageInfo::new(template.render(&batch.items), None);
+                message.priority = batch.priority;
+                for (key, mode) in batch.groups.into_iter().filter(|(key, _)| !self.data.blocked_groups.contains(key)) {
+                    message.push(key, mode);
+                }
+                Arc::new(message)
+            })
+            .collect()
+    }
+
+    /// Replaces network pool, removing unknown networks from categories
+    pub fn set_networks(&mut self, networks: std::collections::HashMap<u64, NetworkInfo>) {
+        for cat in self.data.categories.iter_mut() {
+            cat.rule.retain_networks(&|id| networks.contains_key(&id));
+        }
+        self.data.networks = networks;
+    }
+
+    /// Messages which were not sent yet, to be restored on the next start
+    pub fn store_unsent(&mut self, history: &VecDeque<Arc<SendMessageInfo>>) {
+        self.data.saved_messages = history.iter()
+            .filter(|info| matches!(info.status(std::sync::atomic::Ordering::Relaxed), SendStatus::Pending | SendStatus::Sending | SendStatus::Failed))
+            .map(|info| ui::message_history::SaveMessageInfo::from(info.as_ref()))
+            .collect();
+    }
+}
+
+pub fn push_history(history: &mut VecDeque<Arc<SendMessageInfo>>, message: Arc<SendMessageInfo>, history_len: u32) {
+    if history.len() >= history_len as usize {
+        history.pop_back();
+    }
+    history.push_front(message);
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, atomic::Ordering};
+
+    use futures::channel::mpsc;
+
+    use crate::{appdata::AppData, message::SendMode, messangers::{Key, signal::SignalMessage}, send_categories::{Digest, RoutingInfo, Rule, SendCategory}, ui::{Message, message_history::{SendMessageInfo, SendStatus}, side_menu::LinkState}};
+    use super::{Core, SignalConnection};
+
+    #[test]
+    fn full_digest_is_released() {
+        let mut summary = SendCategory::new("Summary".to_owned());
+        summary.rule = Rule::All(Vec::new());
+        summary.groups.insert(Key::Signal(0, [1; 32]), SendMode::Normal);
+        summary.digest = Some(Digest { max_items: 2, ..Default::default() });
+        let mut data = AppData::default();
+        data.categories.push(summary);
+        let mut core = Core::new(data);
+        let (tx, mut rx) = mpsc::unbounded();
+        core.message_channel = Some(tx);
+
+        core.route_message("перше".to_owned(), RoutingInfo::default());
+        assert!(rx.try_next().is_err());
+        core.route_message("друге".to_owned(), RoutingInfo::default());
+        assert!(matches!(rx.try_next(), Ok(Some(Message::CheckSchedules))));
+        let released = core.release_digests();
+        assert_eq!(released.len(), 1);
+        assert!(core.data.digests.is_empty());
+    }
+
+    #[test]
+    fn message_fails_when_any_account_fails() {
+        let mut core = Core::new(AppData::default());
+        let (tx, _rx) = mpsc::unbounded();
+        core.message_channel = Some(tx);
+        let mut workers = Vec::new();
+        for account in [0, 1] {
+            let (task_send, task_recv) = mpsc::unbounded();
+            core.signal.insert(account, SignalConnection { state: LinkState::Linked, synced: true, task_send });
+            workers.push(task_recv);
+        }
+
+        // Account 1 has no groups in the message and account 2 is not linked
+        let mut message = SendMessageInfo::new("текст".to_owned(), None);
+        message.push(Key::Signal(0, [1; 32]), SendMode::Normal);
+        message.push(Key::Signal(2, [2; 32]), SendMode::Normal);
+        let message = Arc::new(message);
+        assert!(core.send_message(message.clone()).is_empty());
+        assert!(matches!(workers[0].try_next(), Ok(Some(SignalMessage::SendMessage(..)))));
+        assert!(workers[1].try_next().is_err());
+        assert_eq!(message.failed_groups(), [Key::Signal(2, [2; 32])]);
+
+        message.groups_signal[0].push_timestamp(1);
+        message.finish_job();
+        assert_eq!(message.status(Ordering::Relaxed), SendStatus::Failed);
+    }
+}
@@ -0,0 +1,240 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, sync::{Arc, atomic::Ordering}};
+
+use crate::{message::SendMode, message_server::{ApiError, ApiReply, ApiRequest, CategoryDto, GroupDto, MessageId, RouteDto, SendRequest, SendResponse}, messangers::Key, metrics::METRICS, send_categories::{SendCategory, route}, ui::message_history::{SendMessageInfo, SendStatus}};
+
+use super::{Core, Job, push_history};
+
+impl Core {
+    /// Handles HTTP API request. `history` is the list of sent messages, which can be edited or deleted
+    pub fn handle_api(&mut self, request: ApiRequest, history: &mut VecDeque<Arc<SendMessageInfo>>) -> Vec<Job> {
+        match request {
+            ApiRequest::Send(request, reply) => {
+                METRICS.message_received(1);
+                match self.direct_message(request) {
+                    Ok((Some(message), held)) => {
+                        _ = reply.send(Ok(SendResponse { id: Some(message.id), groups: message.len(), held }));
+                        push_history(history, message.clone(), self.data.history_len);
+                        self.notify("Початок відправки повідомлення");
+                        self.send_message(message)
+                    },
+                    // Queued messages and digests are kept in settings
+                    Ok((None, held)) => self.reply_and_save(reply, Ok(SendResponse { id: None, groups: 0, held })),
+                    Err(e) => {
+                        _ = reply.send(Err(e));
+                        vec![]
+                    }
+                }
+            },
+            ApiRequest::EditMessage(id, text, reply) => {
+                match edit_sent_message(history, id, text) {
+                    Ok((message, timestamps, whatsapp_ids)) => {
+                        _ = reply.send(Ok(MessageId { id }));
+                        self.edit_message(message, timestamps, whatsapp_ids)
+                    },
+                    Err(e) => {
+                        _ = reply.send(Err(e));
+                        vec![]
+                    }
+                }
+            },
+            ApiRequest::DeleteMessage(id, reply) => {
+                let Some(message) = history.iter().find(|m| m.id == id) else {
+                    _ = reply.send(Err(ApiError::NotFound));
+                    return vec![]
+                };
+                if message.sent_count() == 0 {
+                    _ = reply.send(Err(ApiError::Conflict("Message is not sent to any group".to_owned())));
+                    return vec![]
+                }
+
+                let message = message.clone();
+                _ = reply.send(Ok(MessageId { id }));
+                self.delete_message(message)
+            },
+            ApiRequest::GetCategories(reply) => {
+                _ = reply.send(Ok(self.data.categories.iter().map(CategoryDto::from).collect()));
+                vec![]
+            },
+            ApiRequest::CreateCategory(category, reply) => {
+                let res = SendCategory::try_from(category).and_then(|category| {
+                    if self.data.categories.iter().any(|c| c.name() == category.name()) {
+                        return Err(ApiError::Conflict(format!("Category {} already exists", category.name())));
+                    }
+                    let dto = CategoryDto::from(&category);
+                    self.data.categories.push(category);
+                    Ok(dto)
+                });
+                self.reply_and_save(reply, res)
+            },
+            ApiRequest::UpdateCategory(name, category, reply) => {
+                let res = SendCategory::try_from(category).and_then(|category| {
+                    if category.name() != name && self.data.categories.iter().any(|c| c.name() == category.name()) {
+                        return Err(ApiError::Conflict(format!("Category {} already exists", category.name())));
+                    }
+                    let existing = self.data.categories.iter_mut()
+                        .find(|c| c.name() == name)
+                        .ok_or(ApiError::NotFound)?;
+                    let dto = CategoryDto::from(&category);
+                    *existing = category;
+                    Ok(dto)
+                });
+                self.reply_and_save(reply, res)
+            },
+            ApiRequest::DeleteCategory(name, reply) => {
+                let res = match self.data.categories.iter().position(|c| c.name() == name) {
+                    Some(index) => {
+                        self.data.categories.remove(index);
+                        Ok(())
+                    },
+                    None => Err(ApiError::NotFound),
+                };
+                self.reply_and_save(reply, res)
+            },
+            ApiRequest::GetGroups(reply) => {
+                _ = reply.send(Ok(self.data.groups.iter().map(|(key, group)| GroupDto::new(key, group, &self.data.blocked_groups)).collect()));
+                vec![]
+            },
+            ApiRequest::GetNetworks(reply) => {
+                _ = reply.send(Ok(self.data.networks.values().cloned().collect()));
+                vec![]
+            },
+            ApiRequest::SetNetworks(networks, reply) => {
+                self.set_networks(networks.into_iter().map(|n| (n.id, n)).collect());
+                self.reply_and_save(reply, Ok(()))
+            },
+            ApiRequest::RoutePreview(request, reply) => {
+                _ = reply.send(self.route_preview(request));
+                vec![]
+            },
+        }
+    }
+
+    /// Sends reply and persists data, if the request changed it
+    fn reply_and_save<T>(&self, reply: ApiReply<T>, res: Result<T, ApiError>) -> Vec<Job> {
+        let changed = res.is_ok();
+        _ = reply.send(res);
+        if changed && let Err(e) = self.save() {
+            log::error!("Error saving data: {e}");
+            self.notify(format!("Error saving data: {e}"));
+        }
+        vec![]
+    }
+
+    /// Creates message for `SendRequest`, resolving named groups and categories, with the number of messages held for later.
+    /// Message is `None` when it is only held. Named categories are used regardless of their schedules and digests
+    pub fn direct_message(&mut self, mut request: SendRequest) -> Result<(Option<Arc<SendMessageInfo>>, usize), ApiError> {
+        request.info.text = Some(request.text.clone());
+        let held = self.held_messages();
+        let (groups, priority) = if request.groups.is_empty() && request.categories.is_empty() {
+            let routed = self.route_groups(&request.info, None);
+            let priority = self.priority(&request.info, routed.values());
+            (routed.into_iter().map(|(key, destination)| (key, destination.mode)).collect(), priority)
+        }
+        else {
+            let priority = self.data.categories.iter()
+                .filter(|c| request.categories.iter().any(|name| c.name() == name))
+                .map(|c| c.priority)
+                .chain(request.info.priority)
+                .max()
+                .unwrap_or_default();
+            (self.named_groups(&request.groups, &request.categories)?, priority)
+        };
+        let held = self.held_messages() - held;
+        if groups.is_empty() {
+            return match held {
+                0 => Err(ApiError::BadRequest("No groups to send message to".to_owned())),
+                held => Ok((None, held)),
+            }
+        }
+
+        let mut message = SendMessageInfo::new(request.text, request.info.freq);
+        message.priority = priority;
+        for (key, mode) in groups {
+            message.push(key, mode);
+        }
+        METRICS.message_routed();
+        Ok((Some(Arc::new(message)), held))
+    }
+
+    /// Messages kept to be sent later: queued until schedules start or collected for digests
+    fn held_messages(&self) -> usize {
+        self.data.queued_messages.len() + self.data.digests.iter().map(|batch| batch.items.len()).sum::<usize>()
+    }
+
+    /// Explains routing of `SendRequest` by categories, sorted by group title
+    fn route_preview(&self, mut request: SendRequest) -> Result<Vec<RouteDto>, ApiError> {
+        if !request.groups.is_empty() || !request.categories.is_empty() {
+            return Err(ApiError::BadRequest("Preview is available only for messages routed by categories".to_owned()));
+        }
+        request.info.text = Some(request.text);
+        let mut routes = route(&self.data.categories, &self.data.groups, &self.data.blocked_groups, &request.info)
+            .into_iter()
+            .map(|(key, destination)| {
+                let title = self.data.groups.get(&key).map(|g| g.title.clone()).unwrap_or_else(|| key.to_string());
+                RouteDto::new(&key, title, destination)
+            })
+            .collect::<Vec<_>>();
+        routes.sort_by(|a, b| a.title.cmp(&b.title));
+        Ok(routes)
+    }
+
+    /// Resolves groups by their titles and categories by their names.
+    /// Groups excluded by named categories and blocked groups are left out, as in `route`
+    fn named_groups(&self, group_names: &[String], category_names: &[String]) -> Result<HashMap<Key, SendMode>, ApiError> {
+        let mut groups: HashMap<Key, SendMode> = HashMap::new();
+        let mut excluded = HashSet::new();
+        let mut use_general = false;
+
+        for name in group_names {
+            let (key, group) = self.data.groups.iter()
+                .find(|(_, g)| &g.title == name)
+                .ok_or_else(|| ApiError::BadRequest(format!("Unknown group: {name}")))?;
+            let mode = if group.active() { group.send_mode } else { SendMode::Normal };
+            groups.entry(key.clone())
+            .and_modify(|m| m.update(mode))
+            .or_insert(mode);
+        }
+
+        for name in category_names {
+            let category = self.data.categories.iter()
+                .find(|c| c.name() == name)
+                .ok_or_else(|| ApiError::BadRequest(format!("Unknown category: {name}")))?;
+            for (key, mode) in category.groups.iter().filter(|(_, m)| m.active()) {
+                groups.entry(key.clone())
+                .and_modify(|m| m.update(*mode))
+                .or_insert(*mode);
+            }
+            excluded.extend(category.excluded.iter());
+            use_general |= category.use_general;
+        }
+
+        if use_general {
+            for (key, group) in self.data.groups.iter().filter(|(_, g)| g.active()) {
+                groups.entry(key.clone())
+                .and_modify(|m| m.update(group.send_mode))
+                .or_insert(group.send_mode);
+            }
+        }
+
+        groups.retain(|key, _| !excluded.contains(key) && !self.data.blocked_groups.contains(key));
+        Ok(groups)
+    }
+}
+
+/// Does the same as `main_screen::Message::ConfirmEdit`, but for message in history
+fn edit_sent_message(history: &mut VecDeque<Arc<SendMessageInfo>>, id: u64, text: String) -> Result<(Arc<SendMessageInfo>, Vec<Vec<u64>>, Vec<Vec<String>>), ApiError> {
+    let arc_message = history.iter_mut()
+        .find(|m| m.id == id)
+        .ok_or(ApiError::NotFound)?;
+    if arc_message.status(Ordering::Relaxed) != SendStatus::Sent {
+        return Err(ApiError::Conflict("Only sent messages can be edited".to_owned()));
+    }
+    let message = Arc::get_mut(arc_message)
+        .ok_or_else(|| ApiError::Conflict("Message is still being processed".to_owned()))?;
+
+    let (timestamps, whatsapp_ids) = message.take_sent_ids();
+    message.set_content(text);
+    message.set_status(SendStatus::Pending, Ordering::Relaxed);
+
+    Ok((arc_message.clone(), timestamps, whatsapp_ids))
+}
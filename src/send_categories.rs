@@ -1,15 +1,224 @@
 mod deserialize;
-use std::collections::{HashMap, HashSet};
+mod digest;
+mod rule;
+mod schedule;
+mod text;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 pub use deserialize::{NetworkInfo, parse_networks_data};
+pub use digest::{Digest, DigestBatch, DigestTemplate};
+pub use rule::{RoutingInfo, Rule, RuleKind, frequency_mhz};
+pub use schedule::{Day, QueuedMessage, Schedule, TimeRange};
+pub use text::{TextPattern, TextScope};
+use jiff::Timestamp;
 use serde::Serialize;
 use serde_versioning::Deserialize;
 
-use crate::{message::SendMode, messangers::Key};
+use crate::{message::{Formatting, Priority, SendMode}, messangers::Key, ui::main_screen::Group};
 
 pub type NetworksPool = HashMap<u64, NetworkInfo>;
 
-#[derive(Debug, Clone, Serialize, Deserialize, better_default::Default)]
+/// Why group receives message
+#[derive(Debug, Clone, PartialEq, Serialize, derive_more::Display)]
+pub enum RouteReason {
+    /// Category matched the message and sends to the group
+    #[display("категорія \"{_0}\"")]
+    Category(String),
+    /// Group is general, and a matched category asks for general groups or no category matched
+    #[display("загальні групи")]
+    General,
+    /// Category matched the message and never sends it to the group
+    #[display("виключено категорією \"{_0}\"")]
+    Excluded(String),
+    /// Group is in the global blocklist
+    #[display("чорний список")]
+    Blocked,
+    /// Category matched the message off its schedule and keeps it until the schedule starts
+    #[display("у черзі до розкладу \"{_0}\"")]
+    Queued(String),
+    /// Category matched the message and collects it into digest sent later
+    #[display("у зведенні \"{_0}\"")]
+    Digest(String),
+}
+
+/// Group message is routed to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Destination {
+    /// The highest send mode of all reasons, `Off` when group is excluded or blocked
+    pub mode: SendMode,
+    /// Everything that included or excluded the group, with send mode it asked for
+    pub reasons: Vec<(RouteReason, SendMode)>,
+}
+
+impl Destination {
+    fn add(&mut self, reason: RouteReason, mode: SendMode) {
+        self.mode.update(mode);
+        self.reasons.push((reason, mode));
+    }
+
+    fn exclude(&mut self, reason: RouteReason) {
+        self.mode = SendMode::Off;
+        self.reasons.push((reason, SendMode::Off));
+    }
+
+    /// Names of matched categories which include the group, in order of categories
+    pub fn categories(&self) -> impl Iterator<Item = &str> {
+        self.reasons.iter().filter_map(|(reason, _)| match reason {
+            RouteReason::Category(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Categories group is left for until their schedule starts, with send modes they ask for
+    pub fn queued(&self) -> impl Iterator<Item = (&str, SendMode)> {
+        self.held(|reason| match reason {
+            RouteReason::Queued(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Categories collecting the message into digest for the group, with send modes they ask for
+    pub fn digests(&self) -> impl Iterator<Item = (&str, SendMode)> {
+        self.held(|reason| match reason {
+            RouteReason::Digest(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Reasons to send the message later, if group neither gets it now nor is excluded
+    fn held<'a>(&'a self, name: impl Fn(&'a RouteReason) -> Option<&'a str>) -> impl Iterator<Item = (&'a str, SendMode)> {
+        let excluded = self.reasons.iter().any(|(reason, _)| matches!(reason, RouteReason::Excluded(_) | RouteReason::Blocked));
+        self.reasons.iter()
+            .filter(move |_| !excluded && !self.mode.active())
+            .filter_map(move |(reason, mode)| Some((name(reason)?, *mode)))
+    }
+}
+
+/// Explains where message goes according to active categories.
+///
+/// Precedence, from the strongest:
+/// 1. Groups in `blocked` never receive anything.
+/// 2. Groups excluded by any matched category don't receive the message,
+///    even if another category or general groups include them.
+/// 3. Groups of matched categories and general groups are joined, the highest send mode wins.
+///    General groups are used when a matched category asks for them or no category includes any group.
+///
+/// Categories off their schedule are inactive. Groups of ones which queue messages and don't receive it now
+/// are kept in result with `Off` mode and [`RouteReason::Queued`], exclusions apply to them too.
+/// Groups of live digest categories are kept the same way with [`RouteReason::Digest`]. Digest categories
+/// don't add general groups, but count as including groups when they have any.
+///
+/// Excluded groups are kept in result with `Off` mode only if something included them.
+pub fn route(
+    categories: &[SendCategory],
+    general: &HashMap<Key, Group>,
+    blocked: &HashSet<Key>,
+    info: &RoutingInfo,
+) -> HashMap<Key, Destination> {
+    let now = info.time.unwrap_or_else(Timestamp::now);
+    let mut groups: HashMap<Key, Destination> = HashMap::new();
+    let mut excluded = Vec::new();
+    let mut held = Vec::new();
+    let mut use_general = false;
+
+    for category in categories.iter().filter(|c| c.active) {
+        if !category.rule.matches(info) {
+            continue
+        }
+        if category.is_live(now) && category.digest.is_some() {
+            held.extend(category.groups.iter().filter(|(_, m)| m.active()).map(|(key, mode)| (key, RouteReason::Digest(category.name().to_owned()), *mode)));
+            excluded.extend(category.excluded.iter().map(|key| (key, RouteReason::Excluded(category.name().to_owned()))));
+        }
+        else if category.is_live(now) {
+            for (key, mode) in category.groups.iter().filter(|(_, m)| m.active()) {
+                groups.entry(key.clone())
+                .or_insert(Destination { mode: *mode, reasons: Vec::new() })
+                .add(RouteReason::Category(category.name().to_owned()), *mode);
+            }
+            excluded.extend(category.excluded.iter().map(|key| (key, RouteReason::Excluded(category.name().to_owned()))));
+            use_general |= category.use_general;
+        }
+        else if category.schedule.as_ref().is_some_and(|s| s.queue) {
+            held.extend(category.groups.iter().filter(|(_, m)| m.active()).map(|(key, mode)| (key, RouteReason::Queued(category.name().to_owned()), *mode)));
+        }
+    }
+
+    let digested = held.iter().any(|(_, reason, _)| matches!(reason, RouteReason::Digest(_)));
+    if use_general || (groups.is_empty() && !digested) {
+        for (key, group) in general.iter().filter(|(_, g)| g.active()) {
+            groups.entry(key.clone())
+            .or_insert(Destination { mode: group.send_mode, reasons: Vec::new() })
+            .add(RouteReason::General, group.send_mode);
+        }
+    }
+
+    for (key, reason, mode) in held {
+        let destination = groups.entry(key.clone()).or_insert(Destination { mode: SendMode::Off, reasons: Vec::new() });
+        if !destination.mode.active() {
+            destination.reasons.push((reason, mode));
+        }
+    }
+
+    for (key, reason) in excluded.into_iter().chain(blocked.iter().map(|key| (key, RouteReason::Blocked))) {
+        if let Some(destination) = groups.get_mut(key) {
+            destination.exclude(reason);
+        }
+    }
+
+    groups
+}
+
+/// Groups message is sent to now and ones it is queued for, see [`route`]
+#[derive(Debug, Default)]
+pub struct Routed {
+    /// Groups with active send mode
+    pub groups: HashMap<Key, Destination>,
+    /// Groups of categories off their schedule, by category name
+    pub queued: BTreeMap<String, HashMap<Key, SendMode>>,
+    /// Groups of digest categories, by category name
+    pub digests: BTreeMap<String, HashMap<Key, SendMode>>,
+}
+
+/// Collects groups message should be sent to with their send modes, see [`route`]
+pub fn route_groups(
+    categories: &[SendCategory],
+    general: &HashMap<Key, Group>,
+    blocked: &HashSet<Key>,
+    info: &RoutingInfo,
+) -> Routed {
+    let groups = route(categories, general, blocked, info);
+    let mut reasons = groups.values()
+        .flat_map(|destination| destination.reasons.iter().map(|(reason, _)| reason))
+        .collect::<Vec<_>>();
+    reasons.sort_by_key(|reason| reason.to_string());
+    reasons.dedup();
+    for reason in reasons {
+        match reason {
+            RouteReason::Category(name) => log::info!("Message matches category {name}"),
+            RouteReason::General => log::info!("Getting general"),
+            RouteReason::Excluded(name) => log::info!("Category {name} excludes groups"),
+            RouteReason::Blocked => log::info!("Skipping blocked groups"),
+            RouteReason::Queued(name) => log::info!("Category {name} is off schedule, queueing message"),
+            RouteReason::Digest(name) => log::info!("Category {name} collects message into digest"),
+        }
+    }
+
+    let mut routed = Routed::default();
+    for (key, destination) in groups {
+        for (name, mode) in destination.queued() {
+            routed.queued.entry(name.to_owned()).or_default().insert(key.clone(), mode);
+        }
+        for (name, mode) in destination.digests() {
+            routed.digests.entry(name.to_owned()).or_default().insert(key.clone(), mode);
+        }
+        if destination.mode.active() {
+            routed.groups.insert(key, destination);
+        }
+    }
+    routed
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, better_default::Default, PartialEq)]
 #[versioning(previous_version = "SendCategoryOld")]
 #[serde(default)]
 pub struct SendCategory {
@@ -18,8 +227,30 @@ pub struct SendCategory {
     pub active: bool,
     #[default(true)]
     pub use_general: bool,
-    pub parameters: Parameters,
+    /// Messages the category sends
+    pub rule: Rule,
     pub groups: HashMap<Key, SendMode>,
+    /// Groups never sent messages the category matches
+    pub excluded: HashSet<Key>,
+    /// Hours category is active, always when `None`
+    pub schedule: Option<Schedule>,
+    /// Template operator messages are rendered with for groups of the category, the global one is used when `None`
+    pub formatting: Option<Formatting>,
+    /// Priority of messages the category matches
+    pub priority: Priority,
+    /// Matched messages are collected and sent as one message, sent one by one when `None`
+    pub digest: Option<Digest>,
+    /// Parameters of categories saved before rules were added, moved to `rule` by settings migration
+    #[serde(rename = "parameters", skip_serializing, deserialize_with = "deserialize_some")]
+    legacy_parameters: Option<Parameters>,
+    /// Networks of categories saved before parameters were added, moved to `rule` by settings migration
+    #[serde(rename = "networks", skip_serializing)]
+    legacy_networks: Vec<u64>,
+}
+
+/// Present field is `Some` without being written as `Some(..)`
+fn deserialize_some<'de, D: serde::Deserializer<'de>, T: serde::Deserialize<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,9 +266,16 @@ impl From<SendCategoryOld> for SendCategory {
         Self {
             name: value.name,
             use_general: value.use_general,
-            parameters: Parameters::Networks(value.networks),
+            rule: Rule::default(),
             groups: value.groups,
+            excluded: HashSet::new(),
+            schedule: None,
+            formatting: None,
+            priority: Priority::default(),
+            digest: None,
             active: true,
+            legacy_parameters: Some(Parameters::Networks(value.networks)),
+            legacy_networks: Vec::new(),
         }
     }
 }
@@ -50,42 +288,169 @@ impl SendCategory {
         }
     }
 
-    pub fn name(&self) -> &str {
-        &self.name
+    /// Moves networks and parameters of old format category to rule
+    pub fn migrate_rule(&mut self) {
+        let networks = std::mem::take(&mut self.legacy_networks);
+        let parameters = match self.legacy_parameters.take() {
+            Some(Parameters::Networks(n)) if n.is_empty() && !networks.is_empty() => Parameters::Networks(networks),
+            Some(parameters) => parameters,
+            None if !networks.is_empty() => Parameters::Networks(networks),
+            None => return,
+        };
+        self.rule = parameters.into();
     }
 
-    pub fn contains_network(&self, id: &u64) -> bool {
-        if let Parameters::Networks(networks) = &self.parameters {
-            networks.contains(id)
-        }
-        else {
-            false
-        }
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    pub fn contains_source(&self, source: &String) -> bool {
-        if let Parameters::Sources(sources) = &self.parameters {
-            sources.contains(source)
-        }
-        else {
-            false
-        }
+    /// Category is active and within its schedule
+    pub fn is_live(&self, now: Timestamp) -> bool {
+        self.active && self.schedule.as_ref().is_none_or(|s| s.is_live(now))
     }
 
-    pub fn contains_comment(&self, comment: &String) -> bool {
-        if let Parameters::Comments(comments) = &self.parameters {
-            comments.contains(comment)
-        }
-        else {
-            false
-        }
+    pub fn contains_network(&self, id: &u64) -> bool {
+        self.rule.networks().contains(id)
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, better_default::Default)]
+/// Matching parameters of categories saved before rules were added
+#[derive(Debug, Clone, Serialize, Deserialize, better_default::Default, PartialEq)]
 pub enum Parameters {
     #[default]
     Networks(Vec<u64>),
     Sources(HashSet<String>),
     Comments(HashSet<String>),
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::{message::SendMode, messangers::Key, ui::main_screen::Group};
+
+    use jiff::civil::time;
+
+    use super::{Digest, RouteReason, Routed, RoutingInfo, Rule, Schedule, SendCategory, TimeRange, route, route_groups};
+
+    fn modes(routed: Routed) -> HashMap<Key, SendMode> {
+        routed.groups.into_iter().map(|(key, destination)| (key, destination.mode)).collect()
+    }
+
+    fn network(id: u64) -> RoutingInfo {
+        RoutingInfo { network_id: Some(id), ..Default::default() }
+    }
+
+    #[test]
+    fn route_reasons() {
+        let general = HashMap::from([
+            (Key::Signal(0, [1; 32]), Group::new("A".to_owned(), SendMode::Normal)),
+            (Key::Signal(0, [2; 32]), Group::new("B".to_owned(), SendMode::Off)),
+        ]);
+        let mut north = SendCategory::new("North".to_owned());
+        north.rule = Rule::Network(1);
+        north.use_general = false;
+        north.groups.insert(Key::Signal(0, [1; 32]), SendMode::Frequency);
+        let categories = vec![north];
+
+        let routes = route(&categories, &general, &HashSet::new(), &network(1));
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[&Key::Signal(0, [1; 32])].mode, SendMode::Frequency);
+        assert_eq!(routes[&Key::Signal(0, [1; 32])].reasons, vec![(RouteReason::Category("North".to_owned()), SendMode::Frequency)]);
+
+        let routes = route(&categories, &general, &HashSet::new(), &network(2));
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[&Key::Signal(0, [1; 32])].reasons, vec![(RouteReason::General, SendMode::Normal)]);
+    }
+
+    #[test]
+    fn exclusion_precedence() {
+        let (a, b, c) = (Key::Signal(0, [1; 32]), Key::Signal(0, [2; 32]), Key::Signal(0, [3; 32]));
+        let general = HashMap::from([
+            (a.clone(), Group::new("A".to_owned(), SendMode::Normal)),
+            (b.clone(), Group::new("B".to_owned(), SendMode::Normal)),
+            (c.clone(), Group::new("C".to_owned(), SendMode::Off)),
+        ]);
+        let mut north = SendCategory::new("North".to_owned());
+        north.rule = Rule::Network(1);
+        north.groups.insert(c.clone(), SendMode::Frequency);
+        let mut quiet = SendCategory::new("Quiet".to_owned());
+        quiet.rule = Rule::Network(1);
+        quiet.excluded.insert(a.clone());
+        quiet.excluded.insert(c.clone());
+        let categories = vec![north, quiet];
+
+        // Exclusion of matched category beats both other categories and general groups
+        let groups = modes(route_groups(&categories, &general, &HashSet::new(), &network(1)));
+        assert_eq!(groups, HashMap::from([(b.clone(), SendMode::Normal)]));
+        let routes = route(&categories, &general, &HashSet::new(), &network(1));
+        assert_eq!(routes[&c].mode, SendMode::Off);
+        assert_eq!(routes[&c].reasons.last(), Some(&(RouteReason::Excluded("Quiet".to_owned()), SendMode::Off)));
+
+        // Exclusion applies only to messages category matches
+        let groups = modes(route_groups(&categories, &general, &HashSet::new(), &network(2)));
+        assert_eq!(groups, HashMap::from([(a.clone(), SendMode::Normal), (b.clone(), SendMode::Normal)]));
+
+        // Blocklist applies whatever matches
+        let blocked = HashSet::from([b.clone()]);
+        assert!(route_groups(&categories, &general, &blocked, &network(1)).groups.is_empty());
+        assert_eq!(modes(route_groups(&categories, &general, &blocked, &network(2))), HashMap::from([(a, SendMode::Normal)]));
+    }
+
+    #[test]
+    fn off_schedule_queue() {
+        let (a, b) = (Key::Signal(0, [1; 32]), Key::Signal(0, [2; 32]));
+        let general = HashMap::from([
+            (a.clone(), Group::new("A".to_owned(), SendMode::Normal)),
+        ]);
+        let mut night = SendCategory::new("Night".to_owned());
+        night.rule = Rule::All(Vec::new());
+        night.groups.insert(a.clone(), SendMode::Normal);
+        night.groups.insert(b.clone(), SendMode::Frequency);
+        night.schedule = Some(Schedule {
+            ranges: vec![TimeRange { start: time(22, 0, 0, 0), end: time(6, 0, 0, 0) }],
+            timezone: "UTC".to_owned(),
+            queue: true,
+            ..Default::default()
+        });
+        let categories = vec![night];
+        let at = |time: &str| RoutingInfo { time: Some(time.parse().unwrap()), ..Default::default() };
+
+        let routed = route_groups(&categories, &general, &HashSet::new(), &at("2026-10-16T23:00:00Z"));
+        assert!(routed.queued.is_empty());
+        assert_eq!(modes(routed), HashMap::from([(a.clone(), SendMode::Normal), (b.clone(), SendMode::Frequency)]));
+
+        // Off schedule category is inactive, general groups get the message now and the rest waits
+        let routed = route_groups(&categories, &general, &HashSet::new(), &at("2026-10-16T12:00:00Z"));
+        assert_eq!(routed.queued["Night"], HashMap::from([(b.clone(), SendMode::Frequency)]));
+        assert_eq!(modes(routed), HashMap::from([(a.clone(), SendMode::Normal)]));
+
+        let routed = route_groups(&categories, &general, &HashSet::from([b]), &at("2026-10-16T12:00:00Z"));
+        assert!(routed.queued.is_empty());
+    }
+
+    #[test]
+    fn digest_holds_groups() {
+        let (a, b) = (Key::Signal(0, [1; 32]), Key::Signal(0, [2; 32]));
+        let general = HashMap::from([
+            (a.clone(), Group::new("A".to_owned(), SendMode::Normal)),
+        ]);
+        let mut summary = SendCategory::new("Summary".to_owned());
+        summary.rule = Rule::All(Vec::new());
+        summary.groups.insert(b.clone(), SendMode::Normal);
+        summary.digest = Some(Digest::default());
+        let mut north = SendCategory::new("North".to_owned());
+        north.rule = Rule::Network(1);
+        north.groups.insert(b.clone(), SendMode::Frequency);
+
+        // Digest category doesn't fall back to general groups
+        let routed = route_groups(&[summary.clone(), north.clone()], &general, &HashSet::new(), &network(2));
+        assert_eq!(routed.digests["Summary"], HashMap::from([(b.clone(), SendMode::Normal)]));
+        assert!(routed.groups.is_empty());
+
+        // Group getting the message now isn't sent it in digest again
+        let routed = route_groups(&[summary, north], &general, &HashSet::new(), &network(1));
+        assert!(routed.digests.is_empty());
+        assert_eq!(modes(routed), HashMap::from([(a, SendMode::Normal), (b, SendMode::Frequency)]));
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, str::FromStr};
+
+use derive_more::Display;
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::{message::{Formatting, OperatorMessage, Priority, SendMode}, messangers::Key};
+
+/// Start of digest template part repeated for every message
+const ITEMS_START: &str = "%кожне%";
+/// End of digest template part repeated for every message
+const ITEMS_END: &str = "%/кожне%";
+/// Number of messages in digest, allowed outside of the repeated part
+const COUNT: &str = "%кількість%";
+
+const DEFAULT_TEMPLATE: &str = "Зведення, повідомлень: %кількість%\n%кожне%\n%час% %частота%\n%текст%\n%/кожне%";
+
+#[derive(Debug, Display)]
+pub enum DigestTemplateError {
+    #[display("Шаблон зведення має містити %кожне% і %/кожне%")]
+    NoItems,
+    #[display("Помилка в шаблоні повідомлення зведення: {_0}")]
+    Item(String),
+}
+
+/// Template of combined message, the part between `%кожне%` and `%/кожне%` is repeated for every message.
+/// Written like "Зведення (%кількість%)%кожне%\n%час% %текст%%/кожне%"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DigestTemplate {
+    header: String,
+    item: Formatting,
+    footer: String,
+}
+
+impl Default for DigestTemplate {
+    fn default() -> Self {
+        DEFAULT_TEMPLATE.parse().unwrap()
+    }
+}
+
+impl FromStr for DigestTemplate {
+    type Err = DigestTemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (header, rest) = s.split_once(ITEMS_START).ok_or(DigestTemplateError::NoItems)?;
+        let (item, footer) = rest.split_once(ITEMS_END).ok_or(DigestTemplateError::NoItems)?;
+        Ok(Self {
+            header: header.to_owned(),
+            item: item.parse::<Formatting>().map_err(|e| DigestTemplateError::Item(e.to_string()))?,
+            footer: footer.to_owned(),
+        })
+    }
+}
+
+impl TryFrom<String> for DigestTemplate {
+    type Error = DigestTemplateError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for DigestTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{ITEMS_START}{}{ITEMS_END}{}", self.header, self.item, self.footer)
+    }
+}
+
+impl From<DigestTemplate> for String {
+    fn from(value: DigestTemplate) -> Self {
+        value.to_string()
+    }
+}
+
+impl DigestTemplate {
+    /// Part of digest for one message. Message typed by hand has only `%текст%` filled
+    pub fn item(&self, message: Option<&OperatorMessage>, text: &str) -> String {
+        match message {
+            Some(message) => self.item.format_message(message),
+            None => self.item.format_text(text),
+        }
+    }
+
+    /// Combined message of items rendered with `item`
+    pub fn render(&self, items: &[String]) -> String {
+        let count = items.len().to_string();
+        format!("{}{}{}", self.header.replace(COUNT, &count), items.concat(), self.footer.replace(COUNT, &count))
+            .trim()
+            .to_owned()
+    }
+}
+
+/// Category collects matched messages and sends them as one message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, better_default::Default)]
+#[serde(default)]
+pub struct Digest {
+    /// Minutes since the first collected message after which the digest is sent
+    #[default(15)]
+    pub interval: u32,
+    /// Digest is sent as soon as it has this many messages, no limit when 0
+    pub max_items: u32,
+    pub template: DigestTemplate,
+}
+
+/// Messages collected for digest of a category
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DigestBatch {
+    pub category: String,
+    /// When the first message was collected
+    pub started: Timestamp,
+    /// Messages rendered with item part of the template
+    pub items: Vec<String>,
+    /// Groups of all collected messages, the highest send mode wins
+    pub groups: HashMap<Key, SendMode>,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+impl DigestBatch {
+    pub fn new(category: String, started: Timestamp) -> Self {
+        Self {
+            category,
+            started,
+            items: Vec::new(),
+            groups: HashMap::new(),
+            priority: Priority::Low,
+        }
+    }
+
+    pub fn push(&mut self, item: String, groups: HashMap<Key, SendMode>, priority: Priority) {
+        self.items.push(item);
+        for (key, mode) in groups {
+            self.groups.entry(key).or_default().update(mode);
+        }
+        self.priority = self.priority.max(priority);
+    }
+
+    /// Interval passed since the first message or the batch is full
+    pub fn is_due(&self, digest: &Digest, now: Timestamp) -> bool {
+        (digest.max_items > 0 && self.items.len() >= digest.max_items as usize)
+            || now.duration_since(self.started) >= SignedDuration::from_mins(digest.interval as i64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Digest, DigestBatch, DigestTemplate};
+
+    #[test]
+    fn render_digest() {
+        let template = "Зведення (%кількість%)%кожне%\n- %текст%%/кожне%\nКінець".parse::<DigestTemplate>().unwrap();
+        let items = ["перше", "друге"].map(|text| template.item(None, text));
+        assert_eq!(template.render(&items), "Зведення (2)\n- перше\n- друге\nКінець");
+        assert_eq!(template.to_string().parse::<DigestTemplate>().unwrap(), template);
+        assert!("%текст%".parse::<DigestTemplate>().is_err());
+    }
+
+    #[test]
+    fn batch_due() {
+        let digest = Digest { interval: 15, max_items: 2, ..Default::default() };
+        let start = "2026-10-16T19:30:00Z".parse().unwrap();
+        let mut batch = DigestBatch::new("North".to_owned(), start);
+        batch.push("a".to_owned(), Default::default(), Default::default());
+        assert!(!batch.is_due(&digest, "2026-10-16T19:44:00Z".parse().unwrap()));
+        assert!(batch.is_due(&digest, "2026-10-16T19:45:00Z".parse().unwrap()));
+        batch.push("b".to_owned(), Default::default(), Default::default());
+        assert!(batch.is_due(&digest, start));
+    }
+}
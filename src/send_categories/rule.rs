@@ -0,0 +1,379 @@
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+use crate::message::{OperatorMessage, Priority};
+use super::{Parameters, TextPattern, TextScope, text::contains_keyword};
+
+/// Message properties categories are matched against
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingInfo {
+    pub network_id: Option<u64>,
+    pub source: Option<String>,
+    pub comment: Option<String>,
+    pub freq: Option<String>,
+    pub title: Option<String>,
+    pub location: Option<String>,
+    pub sender: Option<String>,
+    pub reciever: Option<String>,
+    /// Text being sent, filled when message is routed
+    #[serde(skip)]
+    pub text: Option<String>,
+    /// Individual intercepted messages of operator message
+    pub lines: Vec<String>,
+    /// Time schedules of categories are checked at, current time when not set
+    #[serde(skip)]
+    pub time: Option<jiff::Timestamp>,
+    /// Priority the message came with, the highest of it and priorities of matched categories is used
+    pub priority: Option<Priority>,
+}
+
+impl From<&OperatorMessage> for RoutingInfo {
+    fn from(value: &OperatorMessage) -> Self {
+        Self {
+            network_id: value.network_id,
+            source: Some(value.source.clone()),
+            comment: value.comment.clone(),
+            freq: Some(value.frequency.clone()),
+            title: Some(value.title.clone()),
+            location: Some(value.location.clone()),
+            sender: Some(value.sender.to_string()),
+            reciever: Some(value.reciever.to_string()),
+            text: None,
+            lines: value.message.lines().map(str::to_owned).collect(),
+            time: None,
+            priority: value.priority,
+        }
+    }
+}
+
+/// Condition on message properties. Text conditions except source and comment match case-insensitive substrings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Rule {
+    /// Every rule matches, empty list always matches
+    All(Vec<Rule>),
+    /// At least one rule matches, empty list never matches
+    Any(Vec<Rule>),
+    Not(Box<Rule>),
+    Network(u64),
+    Source(String),
+    Comment(String),
+    /// Frequency in MHz within inclusive range
+    Frequency(f64, f64),
+    Title(String),
+    Location(String),
+    Sender(String),
+    Reciever(String),
+    /// Some keyword starts a word, case and Cyrillic look-alike letters are ignored
+    Keywords(Vec<String>, TextScope),
+    /// Case-insensitive regular expression
+    Regex(TextPattern, TextScope),
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self::Any(Vec::new())
+    }
+}
+
+impl From<Parameters> for Rule {
+    fn from(value: Parameters) -> Self {
+        match value {
+            Parameters::Networks(networks) => Self::Any(networks.into_iter().map(Self::Network).collect()),
+            Parameters::Sources(sources) => {
+                let mut sources = sources.into_iter().collect::<Vec<_>>();
+                sources.sort();
+                Self::Any(sources.into_iter().map(Self::Source).collect())
+            },
+            Parameters::Comments(comments) => {
+                let mut comments = comments.into_iter().collect::<Vec<_>>();
+                comments.sort();
+                Self::Any(comments.into_iter().map(Self::Comment).collect())
+            },
+        }
+    }
+}
+
+fn contains(value: Option<&str>, pattern: &str) -> bool {
+    value.is_some_and(|value| value.to_lowercase().contains(&pattern.to_lowercase()))
+}
+
+/// Text or any line of message matches
+fn text_matches(info: &RoutingInfo, scope: TextScope, matches: impl Fn(&str) -> bool) -> bool {
+    match scope {
+        TextScope::Text => info.text.as_deref().is_some_and(matches),
+        TextScope::Lines => info.lines.iter().any(|line| matches(line)),
+    }
+}
+
+/// Parses frequency like "123.456 МГц" or "450,5", megahertz are assumed without unit
+pub fn frequency_mhz(freq: &str) -> Option<f64> {
+    let freq = freq.trim();
+    let end = freq.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ',')).unwrap_or(freq.len());
+    let value = freq[.. end].replace(',', ".").parse::<f64>().ok()?;
+    let unit = freq[end ..].trim().to_lowercase();
+    Some(match unit.as_str() {
+        "кгц" | "khz" => value / 1000.0,
+        "ггц" | "ghz" => value * 1000.0,
+        _ => value,
+    })
+}
+
+impl Rule {
+    pub fn matches(&self, info: &RoutingInfo) -> bool {
+        match self {
+            Self::All(rules) => rules.iter().all(|rule| rule.matches(info)),
+            Self::Any(rules) => rules.iter().any(|rule| rule.matches(info)),
+            Self::Not(rule) => !rule.matches(info),
+            Self::Network(id) => info.network_id == Some(*id),
+            Self::Source(source) => info.source.as_ref() == Some(source),
+            Self::Comment(comment) => info.comment.as_ref() == Some(comment),
+            Self::Frequency(min, max) => info.freq.as_deref()
+                .and_then(frequency_mhz)
+                .is_some_and(|freq| (*min ..= *max).contains(&freq)),
+            Self::Title(title) => contains(info.title.as_deref(), title),
+            Self::Location(location) => contains(info.location.as_deref(), location),
+            Self::Sender(sender) => contains(info.sender.as_deref(), sender),
+            Self::Reciever(reciever) => contains(info.reciever.as_deref(), reciever),
+            Self::Keywords(keywords, scope) => text_matches(info, *scope, |text| contains_keyword(text, keywords)),
+            Self::Regex(pattern, scope) => text_matches(info, *scope, |text| pattern.is_match(text)),
+        }
+    }
+
+    /// Regular expressions which can't be compiled, they never match
+    pub fn invalid_patterns(&self) -> Vec<&str> {
+        match self {
+            Self::All(rules) | Self::Any(rules) => rules.iter().flat_map(Rule::invalid_patterns).collect(),
+            Self::Not(rule) => rule.invalid_patterns(),
+            Self::Regex(pattern, _) if pattern.regex().is_none() => vec![pattern.as_str()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Networks the rule refers to
+    pub fn networks(&self) -> Vec<u64> {
+        match self {
+            Self::All(rules) | Self::Any(rules) => rules.iter().flat_map(Rule::networks).collect(),
+            Self::Not(rule) => rule.networks(),
+            Self::Network(id) => vec![*id],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Removes conditions on networks `keep` returns false for. Such condition never matches,
+    /// so it is dropped from `Any` and replaced with the never matching default elsewhere
+    pub fn retain_networks(&mut self, keep: &impl Fn(u64) -> bool) {
+        match self {
+            Self::Network(id) if !keep(*id) => *self = Self::default(),
+            Self::Any(rules) => {
+                rules.retain(|rule| !matches!(rule, Self::Network(id) if !keep(*id)));
+                for rule in rules.iter_mut() {
+                    rule.retain_networks(keep);
+                }
+            },
+            Self::All(rules) => {
+                for rule in rules.iter_mut() {
+                    rule.retain_networks(keep);
+                }
+            },
+            Self::Not(rule) => rule.retain_networks(keep),
+            _ => (),
+        }
+    }
+
+    /// Rule at `path` of child indices, the root for empty path
+    pub fn get_mut(&mut self, path: &[usize]) -> Option<&mut Rule> {
+        let Some((first, rest)) = path.split_first() else {
+            return Some(self)
+        };
+        match self {
+            Self::All(rules) | Self::Any(rules) => rules.get_mut(*first)?.get_mut(rest),
+            Self::Not(rule) if *first == 0 => rule.get_mut(rest),
+            _ => None,
+        }
+    }
+
+    /// Child rules of `All`, `Any` or `Not`
+    pub fn children(&self) -> &[Rule] {
+        match self {
+            Self::All(rules) | Self::Any(rules) => rules,
+            Self::Not(rule) => std::slice::from_ref(rule),
+            _ => &[],
+        }
+    }
+}
+
+/// Kind of rule condition, used to choose new condition in the editor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum RuleKind {
+    #[display("Усі умови (І)")]
+    All,
+    #[display("Будь-яка умова (АБО)")]
+    Any,
+    #[display("Заперечення (НЕ)")]
+    Not,
+    #[display("Мережа")]
+    Network,
+    #[display("Джерело")]
+    Source,
+    #[display("Коментар")]
+    Comment,
+    #[display("Частота, МГц")]
+    Frequency,
+    #[display("Назва містить")]
+    Title,
+    #[display("Місце містить")]
+    Location,
+    #[display("Відправник містить")]
+    Sender,
+    #[display("Отримувач містить")]
+    Reciever,
+    #[display("Ключові слова")]
+    Keywords,
+    #[display("Регулярний вираз")]
+    Regex,
+}
+
+impl RuleKind {
+    pub const ALL: [RuleKind; 13] = [
+        Self::All, Self::Any, Self::Not, Self::Network, Self::Source, Self::Comment,
+        Self::Frequency, Self::Title, Self::Location, Self::Sender, Self::Reciever,
+        Self::Keywords, Self::Regex,
+    ];
+
+    /// Condition needs a value typed by user
+    pub fn has_value(self) -> bool {
+        !matches!(self, Self::All | Self::Any | Self::Not)
+    }
+
+    /// Condition matches message text
+    pub fn has_scope(self) -> bool {
+        matches!(self, Self::Keywords | Self::Regex)
+    }
+
+    /// Builds rule from value typed by user, `None` when the value is invalid.
+    /// Keywords are separated with commas, `scope` is used by text conditions
+    pub fn build(self, value: &str, scope: TextScope) -> Option<Rule> {
+        let value = value.trim();
+        let text = || (!value.is_empty()).then(|| value.to_owned());
+        Some(match self {
+            Self::All => Rule::All(Vec::new()),
+            Self::Any => Rule::Any(Vec::new()),
+            Self::Not => Rule::Not(Box::new(Rule::Any(Vec::new()))),
+            Self::Network => Rule::Network(value.parse().ok()?),
+            Self::Source => Rule::Source(text()?),
+            Self::Comment => Rule::Comment(text()?),
+            Self::Frequency => {
+                let (min, max) = value.split_once('-').unwrap_or((value, value));
+                let (min, max) = (frequency_mhz(min)?, frequency_mhz(max)?);
+                if min > max {
+                    return None
+                }
+                Rule::Frequency(min, max)
+            },
+            Self::Title => Rule::Title(text()?),
+            Self::Location => Rule::Location(text()?),
+            Self::Sender => Rule::Sender(text()?),
+            Self::Reciever => Rule::Reciever(text()?),
+            Self::Keywords => {
+                let keywords = value.split(',')
+                    .map(str::trim)
+                    .filter(|keyword| !keyword.is_empty())
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>();
+                if keywords.is_empty() {
+                    return None
+                }
+                Rule::Keywords(keywords, scope)
+            },
+            Self::Regex => {
+                let pattern = TextPattern::from(text()?);
+                pattern.regex()?;
+                Rule::Regex(pattern, scope)
+            },
+        })
+    }
+}
+
+impl From<&Rule> for RuleKind {
+    fn from(value: &Rule) -> Self {
+        match value {
+            Rule::All(_) => Self::All,
+            Rule::Any(_) => Self::Any,
+            Rule::Not(_) => Self::Not,
+            Rule::Network(_) => Self::Network,
+            Rule::Source(_) => Self::Source,
+            Rule::Comment(_) => Self::Comment,
+            Rule::Frequency(..) => Self::Frequency,
+            Rule::Title(_) => Self::Title,
+            Rule::Location(_) => Self::Location,
+            Rule::Sender(_) => Self::Sender,
+            Rule::Reciever(_) => Self::Reciever,
+            Rule::Keywords(..) => Self::Keywords,
+            Rule::Regex(..) => Self::Regex,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rule, RoutingInfo, TextScope, frequency_mhz};
+
+    #[test]
+    fn composite_rules() {
+        let info = RoutingInfo {
+            network_id: Some(7),
+            freq: Some("145,500 МГц".to_owned()),
+            title: Some("УКХ р/м управління".to_owned()),
+            sender: Some("Сокіл".to_owned()),
+            ..Default::default()
+        };
+        let rule = Rule::All(vec![
+            Rule::Any(vec![Rule::Network(3), Rule::Network(7)]),
+            Rule::Frequency(144.0, 146.0),
+            Rule::Title("укх".to_owned()),
+            Rule::Not(Box::new(Rule::Sender("сокіл".to_owned()))),
+        ]);
+        assert!(!rule.matches(&info));
+        assert!(rule.matches(&RoutingInfo { sender: Some("Беркут".to_owned()), ..info.clone() }));
+        assert!(Rule::All(Vec::new()).matches(&info));
+        assert!(!Rule::default().matches(&info));
+        assert_eq!(rule.networks(), [3, 7]);
+    }
+
+    #[test]
+    fn removed_network_never_matches() {
+        let info = RoutingInfo { network_id: Some(7), title: Some("УКХ".to_owned()), ..Default::default() };
+        let mut all = Rule::All(vec![Rule::Network(5), Rule::Title("укх".to_owned())]);
+        all.retain_networks(&|id| id != 5);
+        assert_eq!(all, Rule::All(vec![Rule::default(), Rule::Title("укх".to_owned())]));
+        assert!(!all.matches(&info));
+
+        let mut any = Rule::Any(vec![Rule::Network(5), Rule::Network(7)]);
+        any.retain_networks(&|id| id != 5);
+        assert_eq!(any, Rule::Any(vec![Rule::Network(7)]));
+        assert!(any.matches(&info));
+    }
+
+    #[test]
+    fn text_rules() {
+        let info = RoutingInfo {
+            text: Some("Зведення\nСОКІЛ вийшов на зв'язок".to_owned()),
+            lines: vec!["Беркут, прийом".to_owned()],
+            ..Default::default()
+        };
+        assert!(Rule::Keywords(vec!["сокіл".to_owned()], TextScope::Text).matches(&info));
+        assert!(!Rule::Keywords(vec!["сокіл".to_owned()], TextScope::Lines).matches(&info));
+        assert!(Rule::Regex("^беркут".to_owned().into(), TextScope::Lines).matches(&info));
+        assert!(!Rule::Regex("^беркут".to_owned().into(), TextScope::Text).matches(&info));
+        assert_eq!(Rule::Any(vec![Rule::Regex("(".to_owned().into(), TextScope::Text)]).invalid_patterns(), ["("]);
+    }
+
+    #[test]
+    fn frequency() {
+        assert_eq!(frequency_mhz("123.456 МГц"), Some(123.456));
+        assert_eq!(frequency_mhz("450,5"), Some(450.5));
+        assert_eq!(frequency_mhz("27125 кГц"), Some(27.125));
+        assert_eq!(frequency_mhz("Н/Д"), None);
+    }
+}
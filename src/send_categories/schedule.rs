@@ -0,0 +1,151 @@
+use std::collections::{BTreeSet, HashMap};
+
+use derive_more::Display;
+use jiff::{Timestamp, civil::{Time, Weekday}, tz::TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::{message::{Priority, SendMode}, messangers::Key};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Display)]
+pub enum Day {
+    #[display("Пн")]
+    Monday,
+    #[display("Вт")]
+    Tuesday,
+    #[display("Ср")]
+    Wednesday,
+    #[display("Чт")]
+    Thursday,
+    #[display("Пт")]
+    Friday,
+    #[display("Сб")]
+    Saturday,
+    #[display("Нд")]
+    Sunday,
+}
+
+impl Day {
+    pub const ALL: [Day; 7] = [Day::Monday, Day::Tuesday, Day::Wednesday, Day::Thursday, Day::Friday, Day::Saturday, Day::Sunday];
+}
+
+impl From<Weekday> for Day {
+    fn from(value: Weekday) -> Self {
+        Self::ALL[value.to_monday_zero_offset() as usize]
+    }
+}
+
+/// Hours of the day, range with end before start crosses midnight and range with equal ends is the whole day
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TimeRange {
+    pub start: Time,
+    pub end: Time,
+}
+
+impl TimeRange {
+    /// Parses range like "08:00-20:00"
+    pub fn parse(value: &str) -> Option<Self> {
+        let (start, end) = value.split_once(['-', '–'])?;
+        Some(Self { start: parse_time(start)?, end: parse_time(end)? })
+    }
+}
+
+impl std::fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}–{}", self.start.strftime("%H:%M"), self.end.strftime("%H:%M"))
+    }
+}
+
+/// Parses time like "8:30" or "08:30"
+fn parse_time(value: &str) -> Option<Time> {
+    let (hour, minute) = value.trim().split_once(':')?;
+    Time::new(hour.parse().ok()?, minute.parse().ok()?, 0, 0).ok()
+}
+
+/// Duty shift of category, outside of it category behaves as inactive
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Schedule {
+    /// Days the shift starts on, empty means every day
+    pub days: BTreeSet<Day>,
+    /// Hours of the shift, empty means the whole day
+    pub ranges: Vec<TimeRange>,
+    /// IANA time zone like "Europe/Kyiv", system one when empty
+    pub timezone: String,
+    /// Messages arriving off shift are kept and sent when the shift starts
+    pub queue: bool,
+}
+
+impl Schedule {
+    /// Time zone schedule is set in, `None` when it is unknown
+    pub fn time_zone(&self) -> Option<TimeZone> {
+        match self.timezone.trim() {
+            "" => Some(TimeZone::system()),
+            name => TimeZone::get(name).ok(),
+        }
+    }
+
+    pub fn is_live(&self, now: Timestamp) -> bool {
+        let zoned = now.to_zoned(self.time_zone().unwrap_or_else(TimeZone::system));
+        let day = Day::from(zoned.weekday());
+        let yesterday = Day::from(zoned.weekday().predecessor());
+        let time = zoned.time();
+        let on = |day| self.days.is_empty() || self.days.contains(&day);
+
+        if self.ranges.is_empty() {
+            return on(day)
+        }
+        self.ranges.iter().any(|range| {
+            if range.start < range.end {
+                on(day) && range.start <= time && time < range.end
+            }
+            else {
+                (on(day) && time >= range.start) || (on(yesterday) && time < range.end)
+            }
+        })
+    }
+}
+
+/// Message kept until schedule of its category starts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedMessage {
+    pub category: String,
+    pub text: String,
+    /// Text for groups rendered with their own template instead of `text`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub overrides: HashMap<Key, String>,
+    pub freq: Option<String>,
+    pub groups: HashMap<Key, SendMode>,
+    pub received: Timestamp,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+#[cfg(test)]
+mod test {
+    use jiff::civil::time;
+
+    use super::{Day, Schedule, TimeRange};
+
+    #[test]
+    fn night_shift() {
+        let schedule = Schedule {
+            days: [Day::Friday].into(),
+            ranges: vec![TimeRange { start: time(22, 0, 0, 0), end: time(6, 0, 0, 0) }],
+            timezone: "Europe/Kyiv".to_owned(),
+            queue: false,
+        };
+        // 2026-10-16 is Friday, Kyiv is UTC+3 until the end of October
+        assert!(schedule.is_live("2026-10-16T19:30:00Z".parse().unwrap()));
+        assert!(schedule.is_live("2026-10-17T02:59:00Z".parse().unwrap()));
+        assert!(!schedule.is_live("2026-10-17T03:00:00Z".parse().unwrap()));
+        assert!(!schedule.is_live("2026-10-17T19:30:00Z".parse().unwrap()));
+        assert!(!schedule.is_live("2026-10-16T18:59:00Z".parse().unwrap()));
+    }
+
+    #[test]
+    fn parse_range() {
+        assert_eq!(TimeRange::parse("8:00 - 20:30").unwrap().to_string(), "08:00–20:30");
+        assert!(TimeRange::parse("25:00-26:00").is_none());
+        assert!(TimeRange::parse("08:00").is_none());
+    }
+}
@@ -0,0 +1,202 @@
+use std::sync::OnceLock;
+
+use derive_more::Display;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// Part of message text patterns are matched against
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Display)]
+pub enum TextScope {
+    /// Whole text being sent, after formatting and operator edits
+    #[default]
+    #[display("у тексті")]
+    Text,
+    /// Each intercepted message of operator message separately
+    #[display("у рядку")]
+    Lines,
+}
+
+impl TextScope {
+    pub const ALL: [TextScope; 2] = [TextScope::Text, TextScope::Lines];
+}
+
+/// Regular expression kept as written by user, compiled on first use
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct TextPattern {
+    source: String,
+    compiled: OnceLock<Option<Regex>>,
+}
+
+impl From<String> for TextPattern {
+    fn from(source: String) -> Self {
+        Self { source, compiled: OnceLock::new() }
+    }
+}
+
+impl From<TextPattern> for String {
+    fn from(value: TextPattern) -> Self {
+        value.source
+    }
+}
+
+impl PartialEq for TextPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl TextPattern {
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Compiled case-insensitive expression, `None` when it is invalid
+    pub fn regex(&self) -> Option<&Regex> {
+        self.compiled.get_or_init(|| {
+            RegexBuilder::new(&normalize_pattern(&self.source))
+                .case_insensitive(true)
+                .build()
+                .inspect_err(|e| log::warn!("Invalid routing pattern {}: {e}", self.source))
+                .ok()
+        })
+        .as_ref()
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex().is_some_and(|regex| regex.is_match(&normalize(text)))
+    }
+}
+
+/// Latin letters looking the same as Cyrillic ones, often mixed into Cyrillic words by typos or keyboard layouts
+const HOMOGLYPHS: [(char, char); 13] = [
+    ('a', 'а'), ('b', 'в'), ('c', 'с'), ('e', 'е'), ('h', 'н'), ('i', 'і'), ('k', 'к'),
+    ('m', 'м'), ('o', 'о'), ('p', 'р'), ('t', 'т'), ('x', 'х'), ('y', 'у'),
+];
+
+/// Unifies characters written differently but meaning the same: apostrophes, `ё` and stress marks
+fn normalize_chars(text: &str) -> String {
+    text.chars()
+        .filter(|c| *c != '\u{301}')
+        .map(|c| match c {
+            'ʼ' | '’' | '‘' | '`' => '\'',
+            'ё' => 'е',
+            'Ё' => 'Е',
+            c => c,
+        })
+        .collect()
+}
+
+/// Lowercases text with [`normalize_chars`] and replaces Latin look-alikes inside Cyrillic words with Cyrillic letters,
+/// so "Cокіл" typed with Latin "C" is the same as "Сокіл"
+pub fn normalize(text: &str) -> String {
+    let text = normalize_chars(&text.to_lowercase());
+    let mut normalized = String::with_capacity(text.len());
+    for word in text.split_inclusive(|c: char| !c.is_alphanumeric()) {
+        push_word(&mut normalized, word);
+    }
+    normalized
+}
+
+/// Normalizes literals of regular expression the same way as [`normalize`] does with text, escape sequences are kept as they are
+fn normalize_pattern(pattern: &str) -> String {
+    let pattern = normalize_chars(pattern);
+    let mut normalized = String::with_capacity(pattern.len());
+    let mut word = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() {
+            word.push(c);
+            continue
+        }
+        push_word(&mut normalized, &word);
+        word.clear();
+        normalized.push(c);
+        if c != '\\' {
+            continue
+        }
+        let Some(escaped) = chars.next() else {
+            break
+        };
+        normalized.push(escaped);
+        // Class names and codes of escapes like `\p{Lu}` and `\x41` aren't literals
+        let len = match escaped {
+            'p' | 'P' => 1,
+            'x' => 2,
+            'u' => 4,
+            'U' => 8,
+            _ => 0,
+        };
+        if len > 0 && chars.peek() == Some(&'{') {
+            while let Some(c) = chars.next_if(|c| *c != '}') {
+                normalized.push(c);
+            }
+        }
+        else {
+            normalized.extend(chars.by_ref().take(len));
+        }
+    }
+    push_word(&mut normalized, &word);
+    normalized
+}
+
+/// Replaces Latin look-alikes in the word if it is Cyrillic
+fn push_word(normalized: &mut String, word: &str) {
+    if word.chars().any(is_cyrillic) {
+        normalized.extend(word.chars().map(|c| HOMOGLYPHS.iter().find(|(l, _)| *l == c.to_ascii_lowercase()).map_or(c, |(_, cyr)| *cyr)));
+    }
+    else {
+        normalized.push_str(word);
+    }
+}
+
+fn is_cyrillic(c: char) -> bool {
+    matches!(c, '\u{400}' ..= '\u{4ff}')
+}
+
+/// Some keyword starts a word in text, so inflected forms match too
+pub fn contains_keyword(text: &str, keywords: &[String]) -> bool {
+    let text = normalize(text);
+    keywords.iter()
+        .map(|keyword| normalize(keyword.trim()))
+        .filter(|keyword| !keyword.is_empty())
+        .any(|keyword| text.match_indices(&keyword)
+            .any(|(i, _)| text[.. i].chars().next_back().is_none_or(|c| !c.is_alphanumeric()))
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TextPattern, contains_keyword, normalize};
+
+    #[test]
+    fn normalization() {
+        assert_eq!(normalize("Cокіл"), "сокіл");
+        assert_eq!(normalize("Alpha Сокiл"), "alpha сокіл");
+        assert_eq!(normalize("Вʼюн ЇЖАК Ёж"), "в'юн їжак еж");
+    }
+
+    #[test]
+    fn keywords() {
+        let keywords = vec!["сокіл".to_owned(), "Бер кут".to_owned()];
+        assert!(contains_keyword("Передав СОКІЛ, прийом", &keywords));
+        assert!(contains_keyword("до Cокола", &["сокол".to_owned()]));
+        assert!(contains_keyword("бер кут на зв'язку", &keywords));
+        assert!(!contains_keyword("Ясокіл", &keywords));
+        assert!(!contains_keyword("будь-що", &[" ".to_owned()]));
+    }
+
+    #[test]
+    fn patterns() {
+        let pattern = TextPattern::from(r"\bсок[іо]л\w*".to_owned());
+        assert!(pattern.is_match("Викликаю Сокола"));
+        assert!(!pattern.is_match("Беркут"));
+        assert!(TextPattern::from("(".to_owned()).regex().is_none());
+
+        // Look-alikes in patterns are replaced as in text, escapes stay
+        assert!(TextPattern::from("Cокіл".to_owned()).is_match("Передав Cокіл"));
+        assert!(TextPattern::from("Cокіл".to_owned()).is_match("Передав Сокіл"));
+        assert!(TextPattern::from(r"\bсокiл\p{L}*".to_owned()).is_match("СОКІЛОВІ прийом"));
+        assert!(!TextPattern::from(r"\bсокiл".to_owned()).is_match("Ясокіл"));
+    }
+}
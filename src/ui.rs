@@ -3,9 +3,9 @@ use std::{
 };
 use futures::{SinkExt, Stream, StreamExt, channel::{mpsc::UnboundedSender}};
 use iced::{Alignment, Animation, Border, Color, Element, Length, Padding, Shadow, Subscription, Task, animation::Easing, keyboard, widget::{Row, Stack, container, text}};
-use crate::{appdata::AppData, message::OperatorMessage, message_server, messangers::whatsapp, send_categories::{NetworkInfo, Parameters}, ui::{category_screen::CategoryScreen, formatting_screen::FormattingScreen, message_history::{SaveMessageInfo, SendStatus}, side_menu::{LinkState, SideMenu}, theme::Theme}};
+use crate::{appdata::{AppData, ExternalChange}, crypto, message::OperatorMessage, message_server::ApiRequest, paths, runtime::{Core, Job}, send_categories::NetworkInfo, ui::{category_screen::CategoryScreen, formatting_screen::FormattingScreen, lock_screen::LockScreen, side_menu::SideMenu, theme::Theme}};
 
-use crate::{messangers::signal::{SignalMessage, SignalWorker}, ui::{ext::ColorExt, main_screen::MainScreen, message_history::SendMessageInfo, settings_screen::SettingsScreen}};
+use crate::{messangers::{AccountId, Messenger, signal::SignalMessage}, ui::{ext::ColorExt, main_screen::MainScreen, message_history::SendMessageInfo, settings_screen::SettingsScreen}};
 
 pub mod main_screen;
 pub mod settings_screen;
@@ -14,10 +14,15 @@ pub mod category_screen;
 pub mod side_menu;
 pub mod icons;
 pub mod formatting_screen;
+pub mod lock_screen;
 mod ext;
 pub mod theme;
 
 const NOTIFICATION_SHOW_TIME: u64 = 6000;
+/// How often settings file is checked for external changes
+const SETTINGS_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How often queued messages are checked for schedules of their categories
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Screen {
@@ -33,13 +38,21 @@ pub enum Message {
     CategoriesScrMessage(category_screen::Message),
     SideMenuMessage(side_menu::Message),
     FormattingScrMessage(formatting_screen::Message),
-    SignalMessage(SignalMessage),
-    SignalDisconnected,
-    SetWhatsappClient(Option<Arc<whatsapp_rust::Client>>),
+    LockScrMessage(lock_screen::Message),
+    Unlocked,
+    EnableEncryption(String),
+    EncryptionEnabled,
+    CheckSettingsFile,
+    ResolveSettingsChange(SettingsChoice, Box<ExternalChange>),
+    SignalMessage(AccountId, SignalMessage),
+    SignalDisconnected(AccountId),
+    AddAccount(Messenger),
+    RemoveAccount(Messenger, AccountId),
+    SetWhatsappClient(AccountId, Option<Arc<whatsapp_rust::Client>>),
     SetupSignalWorker(UnboundedSender<Message>),
     SendMessage(Arc<SendMessageInfo>),
     DeleteMessage(Arc<SendMessageInfo>),
-    EditMessage(Arc<SendMessageInfo>, Vec<u64>, Vec<String>),
+    EditMessage(Arc<SendMessageInfo>, Vec<Vec<u64>>, Vec<Vec<String>>),
     CancelMessage(Arc<SendMessageInfo>),
     LoadMessages(Vec<Arc<SendMessageInfo>>),
     SetScreen(Screen),
@@ -47,13 +60,17 @@ pub enum Message {
     ThemeChange(Theme),
     OnClose,
     UpdateGroupList,
-    Synced,
+    /// Sends queued messages whose category schedules have started
+    CheckSchedules,
+    Synced(AccountId),
     Notification(String),
     NotificationClose,
     RecivedNetworks(HashMap<u64, NetworkInfo>),
     Keyboard(keyboard::Event),
     StartServer,
     UpdateFormatting,
+    SwitchProfile(String),
+    Api(ApiRequest),
     None,
 }
 
@@ -73,28 +90,30 @@ impl Debug for Message {
     }
 }
 
-impl From<SignalMessage> for Message {
-    fn from(value: SignalMessage) -> Self {
-        Self::SignalMessage(value)
-    }
+/// User decision about settings file changed externally
+#[derive(Debug, Clone, Copy)]
+pub enum SettingsChoice {
+    Merge,
+    Reload,
+    KeepMine,
+    Later,
 }
 
 pub struct App {
-    data: AppData,
+    core: Core,
     cur_screen: Screen,
-    whatsapp_client: Option<Arc<whatsapp_rust::Client>>,
     main_scr: MainScreen,
     sett_scr: SettingsScreen,
     category_scr: CategoryScreen,
     format_scr: FormattingScreen,
-    signal_task_send: Option<UnboundedSender<SignalMessage>>,
     now: Instant,
     notification: Notification,
-    signal_logged: bool,
-    whatsapp_logged: bool,
     side_menu: SideMenu,
     server_abort: Option<iced::task::Handle>,
-    ui_message_channel: Option<UnboundedSender<Message>>,
+    /// Shown instead of everything else until encrypted profile is unlocked
+    lock: Option<LockScreen>,
+    /// Dialog about external settings change is open
+    settings_prompt: bool,
 }
 
 impl<M: Into<Message>> From<anyhow::Result<M>> for Message {
@@ -108,7 +127,15 @@ impl<M: Into<Message>> From<anyhow::Result<M>> for Message {
 
 impl App {
     pub fn new() -> (Self, Task<Message>) {
-        let mut data = AppData::new();
+        if crypto::enabled() && !crypto::unlocked() {
+            let (mut app, _) = Self::with_data(AppData::default());
+            app.lock = Some(LockScreen::default());
+            return (app, Task::none())
+        }
+        Self::with_data(AppData::open().unwrap_or_else(recover_data))
+    }
+
+    fn with_data(mut data: AppData) -> (Self, Task<Message>) {
         let saved_messages = std::mem::take(&mut data.saved_messages).into_iter()
         .map(|msg_info| Arc::new(SendMessageInfo::from(msg_info)))
         .collect();
@@ -131,51 +158,158 @@ impl App {
                 sett_scr: SettingsScreen::new(&data),
                 category_scr: CategoryScreen::new(),
                 format_scr: FormattingScreen::new(data.formatting.as_ref()),
-                signal_logged: data.signal_logged,
-                whatsapp_logged: data.whatsapp_logged,
-                data,
-                whatsapp_client: None,
+                core: Core::new(data),
                 cur_screen: Screen::Main,
-                signal_task_send: None,
                 now: Instant::now(),
                 notification: Notification::new(),
                 side_menu: SideMenu::new(),
                 server_abort: None,
-                ui_message_channel: None,
+                lock: None,
+                settings_prompt: false,
             },
             start_task
         )
     }
 
     fn save(&self) -> anyhow::Result<()> {
-        self.data.save()
+        if self.lock.is_some() {
+            // Nothing is loaded yet
+            return Ok(())
+        }
+        self.core.save()
+    }
+
+    /// Applies settings chosen by user, conflicting values are never dropped without asking
+    fn resolve_settings_change(&mut self, choice: SettingsChoice, change: ExternalChange) -> Task<Message> {
+        if let SettingsChoice::Later = choice {
+            return Task::none()
+        }
+        change.accept();
+        match choice {
+            SettingsChoice::Merge => self.core.data = change.merge.data,
+            SettingsChoice::Reload => self.core.data = change.theirs,
+            SettingsChoice::KeepMine | SettingsChoice::Later => (),
+        }
+        self.core.publish_status();
+        self.sett_scr = SettingsScreen::new(&self.core.data);
+        if let Err(e) = self.save() {
+            log::error!("Error saving data: {e}");
+            return Task::done(notification!("Помилка збереження налаштувань: {}", e))
+        }
+        Task::done(notification!("Налаштування оновлено"))
     }
 
     fn is_tutorial(&self) -> bool {
-        !self.data.signal_logged
-        && !self.data.whatsapp_logged
+        !self.core.data.any_logged(Messenger::Signal)
+        && !self.core.data.any_logged(Messenger::Whatsapp)
         && self.main_scr.message_history.is_empty()
     }
 
     pub fn update(&mut self, message: Message, now: Instant) -> Task<Message> {
         self.now = now;
 
+        if let Some(lock) = &mut self.lock {
+            return match message {
+                Message::LockScrMessage(m) => lock.update(m),
+                Message::Unlocked => {
+                    let (app, task) = Self::with_data(AppData::open().unwrap_or_else(recover_data));
+                    *self = app;
+                    task
+                },
+                Message::OnClose => iced::exit(),
+                _ => Task::none(),
+            }
+        }
+
         match message {
-            Message::MainScrMessage(m) => self.main_scr.update(m, now, &mut self.data),
-            Message::SettingsScrMessage(m) => self.sett_scr.update(m, &mut self.data),
-            Message::CategoriesScrMessage(m) => self.category_scr.update(m, &mut self.data),
-            Message::SideMenuMessage(m) => self.side_menu.update(m, now, &mut self.data),
-            Message::FormattingScrMessage(m) => self.format_scr.update(m, &mut self.data),
-            Message::SignalMessage(m) => {
-                if let Some(channel) = self.signal_task_send.as_ref() {
-                    let mut channel = channel.clone();
-                    Task::future(
-                        async move { channel.send(m).await }
-                    ).discard()
+            Message::MainScrMessage(m) => self.main_scr.update(m, now, &mut self.core),
+            Message::SettingsScrMessage(m) => self.sett_scr.update(m, &mut self.core.data),
+            Message::CategoriesScrMessage(m) => self.category_scr.update(m, &mut self.core.data),
+            Message::SideMenuMessage(m) => self.side_menu.update(m, now, &mut self.core),
+            Message::FormattingScrMessage(m) => self.format_scr.update(m, &mut self.core.data),
+            Message::LockScrMessage(_) | Message::Unlocked => Task::none(),
+            Message::EnableEncryption(passphrase) => {
+                Task::perform(
+                    tokio::task::spawn_blocking(move || crypto::enable(&passphrase)),
+                    |res| match res {
+                        Ok(Ok(())) => Message::EncryptionEnabled,
+                        Ok(Err(e)) => notification!("Не вдалося увімкнути шифрування: {}", e),
+                        Err(e) => notification!("Не вдалося увімкнути шифрування: {}", e),
+                    }
+                )
+            },
+            Message::CheckSettingsFile if !self.settings_prompt => {
+                match self.core.data.external_change() {
+                    Ok(None) => Task::none(),
+                    Ok(Some(change)) if !change.merge.local_changes => {
+                        log::warn!("Settings file was changed externally, reloading");
+                        self.resolve_settings_change(SettingsChoice::Reload, change)
+                    },
+                    Ok(Some(change)) => {
+                        self.settings_prompt = true;
+                        let mut description = "Файл налаштувань змінено ззовні, а налаштування в застосунку теж змінено.\n\n\
+                            Обʼєднати — взяти зміни з файлу, залишивши ваші значення в конфліктах.\n\
+                            Завантажити — замінити налаштування застосунку файлом.\n\
+                            Залишити мої — перезаписати файл налаштуваннями застосунку.".to_owned();
+                        if !change.merge.conflicts.is_empty() {
+                            description.push_str("\n\nКонфлікти:");
+                            for conflict in change.merge.conflicts.iter() {
+                                description.push_str(&format!("\n- {conflict}"));
+                            }
+                        }
+                        let dialog = rfd::AsyncMessageDialog::new()
+                            .set_buttons(rfd::MessageButtons::YesNoCancelCustom("Обʼєднати".to_owned(), "Завантажити".to_owned(), "Залишити мої".to_owned()))
+                            .set_title("Налаштування змінено")
+                            .set_level(rfd::MessageLevel::Warning)
+                            .set_description(description);
+                        Task::perform(dialog.show(), move |res| {
+                            let choice = match res {
+                                rfd::MessageDialogResult::Custom(label) if label == "Обʼєднати" => SettingsChoice::Merge,
+                                rfd::MessageDialogResult::Custom(label) if label == "Завантажити" => SettingsChoice::Reload,
+                                rfd::MessageDialogResult::Custom(label) if label == "Залишити мої" => SettingsChoice::KeepMine,
+                                rfd::MessageDialogResult::Yes => SettingsChoice::Merge,
+                                rfd::MessageDialogResult::No => SettingsChoice::Reload,
+                                _ => SettingsChoice::Later,
+                            };
+                            Message::ResolveSettingsChange(choice, Box::new(change))
+                        })
+                    },
+                    Err(e) => {
+                        log::error!("Settings file was changed externally and can't be loaded: {e}");
+                        Task::none()
+                    },
                 }
-                else {
-                    Task::none()
+            },
+            Message::CheckSettingsFile => Task::none(),
+            Message::ResolveSettingsChange(choice, change) => {
+                self.settings_prompt = false;
+                self.resolve_settings_change(choice, *change)
+            },
+            Message::EncryptionEnabled => {
+                log::warn!("Encryption enabled, saving data and restarting...");
+                self.core.store_unsent(&self.main_scr.message_history);
+                if let Err(e) = self.core.data.save_encrypted() {
+                    log::error!("Failed to save encrypted data: {e}");
+                    return Task::done(notification!("Помилка збереження зашифрованих налаштувань: {}", e))
                 }
+                match restart() {
+                    Ok(()) => iced::exit(),
+                    Err(e) => Task::done(notification!("Не вдалося перезапустити застосунок: {}", e)),
+                }
+            },
+            Message::SignalMessage(account, m) => {
+                self.core.signal(account, m);
+                Task::none()
+            },
+            Message::AddAccount(messenger) => {
+                if self.core.add_account(messenger).is_none() {
+                    return Task::done(notification!("Досягнуто максимальної кількості облікових записів {}", messenger))
+                }
+                Task::none()
+            },
+            Message::RemoveAccount(messenger, account) => {
+                self.core.remove_account(messenger, account);
+                Task::none()
             },
             Message::LoadMessages(messages) => {
                 let task = Task::batch(
@@ -183,7 +317,7 @@ impl App {
                     .map(|info| Task::done(Message::SendMessage(info.clone())))
                 );
 
-                for m in messages.into_iter().take(self.data.history_len as usize) {
+                for m in messages.into_iter().take(self.core.data.history_len as usize) {
                     self.main_scr.message_history.push_back(m);
                 }
 
@@ -191,7 +325,7 @@ impl App {
             },
             Message::SetScreen(screen) => {
                 if self.cur_screen == Screen::Formatting {
-                    self.data.formatting = Some(self.format_scr.formatting())
+                    self.format_scr.store(&mut self.core.data)
                 }
                 self.cur_screen = screen;
                 self.side_menu.open.go_mut(false, now);
@@ -215,15 +349,15 @@ impl App {
                         keyboard::Key::Named(named) => {
                             match named {
                                 keyboard::key::Named::Escape => match self.cur_screen {
-                                    Screen::Categories => return self.category_scr.update(category_screen::Message::Empty, &mut self.data),
+                                    Screen::Categories => return self.category_scr.update(category_screen::Message::Empty, &mut self.core.data),
                                     _ => ()
                                 }
                                 keyboard::key::Named::Enter if modifiers.command() => {
                                     if self.main_scr.edit.is_some() {
-                                        return self.main_scr.update(main_screen::Message::ConfirmEdit, now, &mut self.data)
+                                        return self.main_scr.update(main_screen::Message::ConfirmEdit, now, &mut self.core)
                                     }
                                     else {
-                                        return self.main_scr.update(main_screen::Message::SendMessagePressed, now, &mut self.data)
+                                        return self.main_scr.update(main_screen::Message::SendMessagePressed, now, &mut self.core)
                                     }
                                 },
                                 _ => ()
@@ -235,134 +369,70 @@ impl App {
 
                 Task::none()
             },
-            Message::SetWhatsappClient(maybe_client) => {
-                match maybe_client {
-                    Some(client) => {
-                        self.whatsapp_client = Some(client);
-                        self.whatsapp_logged = true;
-                        Task::done(side_menu::Message::SetWhatsappState(LinkState::Linked).into())
-                    },
-                    None => {
-                        self.whatsapp_client = None;
-                        Task::done(side_menu::Message::SetWhatsappState(LinkState::Unlinked).into())
-                    }
-                }
+            Message::SetWhatsappClient(account, maybe_client) => {
+                let state = self.core.set_whatsapp_client(account, maybe_client);
+                Task::done(side_menu::Message::SetWhatsappState(account, state).into())
             },
-            Message::Synced => {
+            Message::Synced(account) => {
+                self.core.signal_synced(account);
                 Task::done(Message::UpdateGroupList)
             },
             Message::StartServer => {
                 let (task, handle) = Task::abortable(
-                    Task::future(message_server::start_server(self.data.recieve_address, self.ui_message_channel.clone().unwrap())).discard()
+                    Task::future(self.core.server()).discard()
                 );
                 let handle = handle.abort_on_drop();
                 self.server_abort = Some(handle);
                 task
             },
             Message::SetupSignalWorker(tx) => {
-                let (task_tx, task_rx) = futures::channel::mpsc::unbounded();
-                SignalWorker::spawn_new(task_rx, tx.clone(), task_tx.clone());
-                self.signal_task_send = Some(task_tx);
-                whatsapp::UI_MESSAGE_SENDER.set(tx.clone()).unwrap();
-                self.ui_message_channel = Some(tx);
-
+                let jobs = self.core.setup(tx);
                 Task::batch([
-                    if self.signal_logged { Task::done(SignalMessage::LinkBegin.into()) } else { Task::none() },
-                    if self.whatsapp_logged { Task::future(whatsapp::start_whatsapp_task()).discard() } else { Task::none() },
+                    run_jobs(jobs),
                     Task::done(Message::StartServer)
                 ])
             },
-            Message::SignalDisconnected => {
-                self.side_menu.signal_state = LinkState::Disconnected;
-                Task::batch([
-                    Task::done(SignalMessage::Disconnect.into()),
-                    Task::done(SignalMessage::LinkBegin.into())
-                ])
-            },
-            Message::UpdateGroupList => {
-                let mut task_list = Vec::with_capacity(2);
-                if self.side_menu.signal_state == LinkState::Linked {
-                    task_list.push(
-                        Task::done(SignalMessage::GetGroups.into())
-                    );
-                }
-                if let Some(client) = self.whatsapp_client.as_ref() {
-                    task_list.push(
-                        Task::perform(whatsapp::get_groups(client.clone()), |v| v.map(main_screen::Message::SetGroups).into())
-                    );
-                }
-                Task::batch(task_list)
-            },
-            Message::SendMessage(message) => {
-                let mut task_list = Vec::with_capacity(2);
-                if self.side_menu.signal_state == LinkState::Linked || self.side_menu.signal_state == LinkState::Disconnected {
-                    task_list.push(
-                        Task::done(SignalMessage::SendMessage(message.clone(), self.data.markdown).into())
-                    );
-                }
-                else if !message.groups_signal.is_empty() {
-                    message.set_status(message_history::SendStatus::Deleted, std::sync::atomic::Ordering::Relaxed);
-                    return Task::done(notification!("Прив'яжіть, будь ласка, Modern Sender до Signal"));
-                }
-                if let Some(client) = self.whatsapp_client.as_ref() {
-                    task_list.push(
-                        Task::future(whatsapp::send_message(client.clone(), message, self.data.markdown)).discard()
-                    );
-                }
-                else if !message.groups_whatsapp.is_empty() {
-                    message.set_status(message_history::SendStatus::Deleted, std::sync::atomic::Ordering::Relaxed);
-                    return Task::done(notification!("Прив'яжіть, будь ласка, Modern Sender до Whatsapp"));
-                }
-                Task::batch(task_list)
-            },
-            Message::DeleteMessage(message) => {
-                message.set_status(message_history::SendStatus::Pending, std::sync::atomic::Ordering::Relaxed);
-                let mut task_list = Vec::with_capacity(2);
-                if self.side_menu.signal_state == LinkState::Linked  {
-                    task_list.push(
-                        Task::done(SignalMessage::DeleteMessage(message.clone()).into())
-                    );
-                }
-                if let Some(client) = self.whatsapp_client.as_ref() {
-                    task_list.push(
-                        Task::future(whatsapp::delete_message(client.clone(), message)).discard()
-                    );
-                }
-                Task::batch(task_list)
+            Message::SignalDisconnected(account) => {
+                self.core.signal_disconnected(account);
+                Task::none()
             },
-            Message::EditMessage(message, timestamps, whatsapp_ids) => {
-                let mut task_list = Vec::with_capacity(2);
-                if self.side_menu.signal_state == LinkState::Linked  {
-                    task_list.push(
-                        Task::done(SignalMessage::EditMessage(message.clone(), timestamps, self.data.markdown).into())
-                    );
+            Message::UpdateGroupList => run_jobs(self.core.update_groups()),
+            Message::CheckSchedules => {
+                let queued = self.core.release_queued();
+                let digests = self.core.release_digests();
+                if queued.is_empty() && digests.is_empty() {
+                    return Task::none()
                 }
-                if let Some(client) = self.whatsapp_client.as_ref() {
-                    task_list.push(
-                        Task::future(whatsapp::edit_message(client.clone(), message, whatsapp_ids, self.data.markdown)).discard()
-                    );
+                self.save().unwrap_or_else(|e| log::error!("Failed to save data: {e}"));
+                let notifications = [
+                    (!queued.is_empty()).then(|| Task::done(notification!("Розклад категорії почався, надсилаю повідомлення з черги"))),
+                    (!digests.is_empty()).then(|| Task::done(notification!("Надсилаю зведення категорії"))),
+                ];
+                let messages = queued.into_iter().chain(digests).collect::<Vec<_>>();
+                for message in messages.iter() {
+                    self.main_scr.push_history(message.clone(), self.core.data.history_len);
                 }
-                Task::batch(task_list)
+                Task::batch(
+                    messages.into_iter()
+                    .map(|message| Task::done(Message::SendMessage(message)))
+                    .chain(notifications.into_iter().flatten())
+                )
             },
+            Message::SendMessage(message) => run_jobs(self.core.send_message(message)),
+            Message::DeleteMessage(message) => run_jobs(self.core.delete_message(message)),
+            Message::EditMessage(message, timestamps, whatsapp_ids) => run_jobs(self.core.edit_message(message, timestamps, whatsapp_ids)),
             Message::CancelMessage(message) => {
-                message.cancel(self.ui_message_channel.as_mut().unwrap());
+                self.core.cancel_message(message);
                 Task::none()
             },
             Message::AcceptMessage(messages) => {
-                for m in messages.iter() {
-                    if  !self.data.sources.contains(&m.source) {
-                        self.data.sources.insert(m.source.clone());
-                    }
-                    if let Some(comment) = &m.comment && !self.data.comments.contains(comment) {
-                        self.data.comments.insert(comment.clone());
-                    }
-                }
+                self.core.accept_messages(&messages);
 
-                if self.data.autosend {
+                if self.core.data.autosend {
                     Task::batch(
                         messages
                         .into_iter()
-                        .map(|msg| Task::done(main_screen::Message::SendMessage(msg.format(self.data.formatting.as_ref()), Some(msg.frequency.clone()), msg.network_id, Some(msg.source.clone()), msg.comment.clone()).into()))
+                        .map(|msg| Task::done(main_screen::Message::SendOperatorMessage(Arc::new(msg)).into()))
                     )
                 }
                 else {
@@ -376,21 +446,14 @@ impl App {
                 }
             },
             Message::UpdateFormatting => {
-                if let Some(format) = &self.data.formatting {
-                    let content = iced::widget::text_editor::Content::with_text(&format.to_string());
-                    self.format_scr.editor = content;
-                }
+                self.format_scr.load(&self.core.data);
 
                 Task::none()
             },
             Message::OnClose => {
                 log::warn!("Closing application, saving data...");
 
-                let save_messages = self.main_scr.message_history.iter()
-                .filter(|info| matches!(info.status(std::sync::atomic::Ordering::Relaxed), SendStatus::Pending | SendStatus::Sending | SendStatus::Failed))
-                .map(|info |SaveMessageInfo::from(info.as_ref()))
-                .collect();
-                self.data.saved_messages = save_messages;
+                self.core.store_unsent(&self.main_scr.message_history);
 
                 self.save().unwrap_or_else(|e| log::error!("Failed to save data: {e}"));
                 iced::exit()
@@ -413,16 +476,16 @@ impl App {
                     Theme::System => {
                         iced::system::theme().map(|mode| Message::ThemeChange(mode.into()))
                     },
-                    Theme::Light if self.data.theme.is_system() => {
-                        self.data.theme = Theme::Light;
+                    Theme::Light if self.core.data.theme.is_system() => {
+                        self.core.data.theme = Theme::Light;
                         Task::none()
                     }
-                    Theme::Dark if self.data.theme.is_system() => {
-                        self.data.theme = Theme::Dark;
+                    Theme::Dark if self.core.data.theme.is_system() => {
+                        self.core.data.theme = Theme::Dark;
                         Task::none()
                     }
                     Theme::Selected(theme) => {
-                        self.data.theme = Theme::Selected(theme);
+                        self.core.data.theme = Theme::Selected(theme);
                         Task::none()
                     }
                     _ => {
@@ -431,14 +494,28 @@ impl App {
                 }
             },
             Message::RecivedNetworks(networks) => {
-                for cat in self.data.categories.iter_mut() {
-                    if let Parameters::Networks(cat_networks) = &mut cat.parameters {
-                        cat_networks.retain(|id| networks.contains_key(id));
-                    }
-                }
-                self.data.networks = networks;
+                self.core.set_networks(networks);
                 Task::done(Message::Notification("Нові мережі додані!".to_owned()))
             },
+            Message::SwitchProfile(profile) => {
+                if let Err(e) = paths::set_active_profile(&profile) {
+                    log::error!("Error switching profile: {e}");
+                    return Task::done(notification!("Помилка зміни профілю: {}", e))
+                }
+                log::warn!("Switching to profile {profile}, saving data...");
+                self.core.store_unsent(&self.main_scr.message_history);
+                self.save().unwrap_or_else(|e| log::error!("Failed to save data: {e}"));
+                match restart() {
+                    Ok(()) => iced::exit(),
+                    Err(e) => Task::done(notification!("Не вдалося перезапустити застосунок: {}", e)),
+                }
+            },
+            Message::Api(request) => {
+                if let ApiRequest::DeleteCategory(..) = request {
+                    self.category_scr.selected_category = None;
+                }
+                run_jobs(self.core.handle_api(request, &mut self.main_scr.message_history))
+            },
             Message::None => Task::batch([
                 Task::done(main_screen::Message::UpdateMessageHistory.into()),
                 Task::done(side_menu::Message::Animate.into())
@@ -447,25 +524,29 @@ impl App {
     }
 
     pub fn view(&self) -> Element<'_, Message> {
+        if let Some(lock) = &self.lock {
+            return lock.view().map(Into::into)
+        }
+
         Stack::new()
         .width(Length::Fill)
         .height(Length::Fill)
         .push(
             Row::new()
             .push(
-                self.side_menu.minimized(self.cur_screen, &self.data).map(Into::into)
+                self.side_menu.minimized(self.cur_screen, &self.core).map(Into::into)
             )
             .push(
                 match self.cur_screen {
-                    Screen::Main => self.main_scr.view(self.is_tutorial(), &self.data).map(Into::into),
-                    Screen::Settings => self.sett_scr.view(&self.data).map(Into::into),
-                    Screen::Categories => self.category_scr.view(&self.data).map(Into::into),
-                    Screen::Formatting => self.format_scr.view().map(Into::into),
+                    Screen::Main => self.main_scr.view(self.is_tutorial(), &self.core.data).map(Into::into),
+                    Screen::Settings => self.sett_scr.view(&self.core.data).map(Into::into),
+                    Screen::Categories => self.category_scr.view(&self.core.data).map(Into::into),
+                    Screen::Formatting => self.format_scr.view(&self.core.data).map(Into::into),
                 }
             )
         )
         .push(
-            self.side_menu.view(self.cur_screen, &self.data).map(Into::into)
+            self.side_menu.view(self.cur_screen, &self.core).map(Into::into)
         )
         .push(
             self.notification.view(self.now)
@@ -491,10 +572,16 @@ impl App {
     }
 
     pub fn subscription(&self) -> Subscription<Message> {
+        if self.lock.is_some() {
+            return iced::window::close_requests().map(|_| Message::OnClose)
+        }
+
         Subscription::batch([
             iced::system::theme_changes().map(|mode| Message::ThemeChange(mode.into())),
             Subscription::run(Self::setup_subscription),
             iced::time::every(std::time::Duration::from_secs(180)).map(|_| Message::UpdateGroupList),
+            iced::time::every(SETTINGS_CHECK_INTERVAL).map(|_| Message::CheckSettingsFile),
+            iced::time::every(SCHEDULE_CHECK_INTERVAL).map(|_| Message::CheckSchedules),
             iced::window::close_requests().map(|_| Message::OnClose),
             if self.is_animating() { iced::window::frames().map(|_| Message::None) } else { Subscription::none() },
             iced::keyboard::listen().map(Message::Keyboard),
@@ -519,8 +606,69 @@ impl App {
     }
 
     pub fn theme(&self) -> iced::Theme {
-        self.data.theme.as_theme().clone()
+        self.core.data.theme.as_theme().clone()
+    }
+}
+
+/// Starts new instance of the application with the same arguments, so it picks up newly selected profile or encryption
+fn restart() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut kept = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--profile" {
+            _ = args.next();
+        }
+        else if !arg.starts_with("--profile=") {
+            kept.push(arg);
+        }
+    }
+    std::process::Command::new(std::env::current_exe()?)
+        .args(kept)
+        .env_remove("SENDER_PROFILE")
+        .spawn()?;
+    Ok(())
+}
+
+/// Settings file is unreadable. Instead of silently starting with defaults user is offered to restore a backup
+fn recover_data(error: anyhow::Error) -> AppData {
+    log::error!("{error}");
+    let mut description = error.to_string();
+    if !AppData::backups().is_empty() {
+        let restore = rfd::MessageDialog::new()
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .set_title("Помилка завантаження налаштувань")
+            .set_level(rfd::MessageLevel::Error)
+            .set_description(format!("{description}\n\nВідновити налаштування з останньої резервної копії? Інакше будуть використані налаштування за замовчуванням, а пошкоджений файл буде збережено як data.ron.broken"))
+            .show();
+        if restore != rfd::MessageDialogResult::Yes {
+            description.clear();
+        }
+        else {
+            match AppData::restore_backup() {
+                Ok((data, _)) => return data,
+                Err(e) => {
+                    log::error!("Error restoring settings: {e}");
+                    description = format!("Не вдалося відновити налаштування: {e}");
+                },
+            }
+        }
+    }
+
+    let start = description.is_empty() || rfd::MessageDialog::new()
+        .set_buttons(rfd::MessageButtons::OkCancel)
+        .set_title("Помилка завантаження налаштувань")
+        .set_level(rfd::MessageLevel::Error)
+        .set_description(format!("{description}\n\nПродовжити з налаштуваннями за замовчуванням? Пошкоджений файл буде збережено як data.ron.broken"))
+        .show() == rfd::MessageDialogResult::Ok;
+    if !start {
+        std::process::exit(1);
     }
+    AppData::reset().unwrap_or_else(|e| panic!("Failed to set aside broken settings: {e}"))
+}
+
+/// Runs background work of the core as tasks
+fn run_jobs(jobs: Vec<Job>) -> Task<Message> {
+    Task::batch(jobs.into_iter().map(|job| Task::future(job).discard()))
 }
 
 #[derive(Debug)]
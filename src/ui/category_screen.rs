@@ -5,13 +5,15 @@ use derive_more::Display;
 use iced::border::Radius;
 use iced::{Alignment, Border, Color, Length, Padding, Shadow, Vector};
 use iced::{Element, Task};
-use iced::widget::{Column, Row, button, checkbox, container, mouse_area, pick_list, scrollable, space, text, text_input, tooltip as tooltip_widget};
+use iced::widget::{Column, Row, button, checkbox, container, mouse_area, pick_list, scrollable, space, text, text_editor, text_input, tooltip as tooltip_widget};
 
+use crate::appdata::category_file::CategoryFile;
+use crate::appdata::transfer::{Section, SettingsPart, Strategy};
 use crate::icon;
-use crate::message::SendMode;
+use crate::message::{Priority, SendMode};
 use crate::messangers::Key;
-use crate::send_categories::{NetworkInfo, Parameters, SendCategory};
-use crate::ui::{AppData, Message as MainMessage};
+use crate::send_categories::{Day, Digest, DigestTemplate, NetworksPool, Rule, RuleKind, Schedule, SendCategory, TextScope, TimeRange};
+use crate::ui::{AppData, Message as MainMessage, ext::PushMaybe};
 use crate::ui::main_screen::Group;
 
 
@@ -19,12 +21,21 @@ pub struct CategoryScreen {
     pub new_category_name: Option<String>,
     pub selected_category: Option<usize>,
     edit_new_name_id: iced::widget::Id,
-    parameter_search: String,
     group_search: String,
-    selected_parameter: ParameterChoose,
-    cur_networks: Vec<u64>,
-    cur_sources: HashSet<String>,
-    cur_comments: HashSet<String>,
+    /// Kind of condition to add
+    rule_kind: RuleKind,
+    /// Value of condition to add
+    rule_value: String,
+    /// Where text condition to add is matched
+    rule_scope: TextScope,
+    /// Path of `All` or `Any` rule new conditions are added to
+    rule_target: Vec<usize>,
+    /// Time range to add to schedule, like "08:00-20:00"
+    schedule_range: String,
+    /// Digest template of the selected category, saved to it when valid
+    digest_template: text_editor::Content,
+    /// Why edited digest template is not saved
+    digest_error: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -35,15 +46,40 @@ pub enum Message {
     CategoryVisibility(usize),
     CategoryDelete(usize),
     ToggleGroup(usize, Key, SendMode),
-    ToggleNetwork(usize, u64, bool),
-    ToggleSource(usize, String, bool),
-    ToggleComment(usize, String, bool),
     ToggleGeneralGroup(Key, SendMode),
+    /// Adds or removes group from groups category never sends to
+    ToggleExcluded(usize, Key),
+    /// Adds or removes group from the global blocklist
+    ToggleBlocked(Key),
     ToggleUseGeneral(usize, bool),
-    NetworkSerch(String),
+    SetPriority(usize, Priority),
+    /// Messages per minute, empty clears the limit
+    GroupRateLimit(Key, String),
     GroupSearch(String),
-    #[expect(private_interfaces)]
-    ParameterChoose(usize, ParameterChoose),
+    RuleKindSelected(RuleKind),
+    RuleValueEdit(String),
+    RuleScopeSelected(TextScope),
+    RuleTarget(Vec<usize>),
+    AddRule(usize),
+    RemoveRule(usize, Vec<usize>),
+    SetCombinator(usize, Vec<usize>, RuleKind),
+    ToggleSchedule(usize, bool),
+    ToggleDay(usize, Day),
+    ScheduleRangeEdit(String),
+    AddRange(usize),
+    RemoveRange(usize, usize),
+    TimezoneEdit(usize, String),
+    ToggleQueue(usize, bool),
+    ToggleDigest(usize, bool),
+    DigestInterval(usize, String),
+    DigestMaxItems(usize, String),
+    DigestTemplateEdit(usize, text_editor::Action),
+    /// Exports the selected category, or all of them when none is selected
+    ExportCategories,
+    ExportPath(Option<rfd::FileHandle>),
+    ImportCategories,
+    ImportPath(Option<rfd::FileHandle>),
+    CategoriesLoaded(Box<CategoryFile>),
     Empty,
 }
 
@@ -59,12 +95,14 @@ impl CategoryScreen {
             new_category_name: None,
             selected_category: None,
             edit_new_name_id: iced::widget::Id::unique(),
-            parameter_search: String::new(),
             group_search: String::new(),
-            selected_parameter: ParameterChoose::Network,
-            cur_comments: Default::default(),
-            cur_networks: Default::default(),
-            cur_sources: Default::default(),
+            rule_kind: RuleKind::Network,
+            rule_value: String::new(),
+            rule_scope: TextScope::default(),
+            rule_target: Vec::new(),
+            schedule_range: String::new(),
+            digest_template: text_editor::Content::new(),
+            digest_error: None,
         }
     }
 
@@ -98,16 +136,11 @@ impl CategoryScreen {
                 }
                 else {
                     self.selected_category = Some(index);
-                    self.selected_parameter = match &data.categories[index].parameters {
-                        Parameters::Networks(_) => ParameterChoose::Network,
-                        Parameters::Sources(_) => ParameterChoose::Source,
-                        Parameters::Comments(_) => ParameterChoose::Comment,
-                    };
                 }
                 self.new_category_name = None;
-                self.cur_comments = Default::default();
-                self.cur_sources = Default::default();
-                self.cur_networks = Default::default();
+                self.rule_value.clear();
+                self.rule_target.clear();
+                self.load_digest_template(data.categories[index].digest.as_ref());
             },
             Message::CategoryDelete(index) => {
                 if self.selected_category.is_some() {
@@ -120,47 +153,41 @@ impl CategoryScreen {
             },
             Message::ToggleGroup(index, key, send_mode) => {
                 let category = &mut data.categories[index];
+                if send_mode.active() {
+                    category.excluded.remove(&key);
+                }
                 category.groups.insert(key, send_mode);
             },
-            Message::ToggleNetwork(index, network, state) => {
+            Message::ToggleExcluded(index, key) => {
                 let category = &mut data.categories[index];
-                if let Parameters::Networks(networks) = &mut category.parameters {
-                    if state {
-                        networks.push(network);
-                    }
-                    else {
-                        networks.retain(|v| *v != network);
-                    }
+                if !category.excluded.remove(&key) {
+                    category.groups.remove(&key);
+                    category.excluded.insert(key);
                 }
             },
-            Message::ToggleSource(index, source, state) => {
-                let category = &mut data.categories[index];
-                if let Parameters::Sources(sources) = &mut category.parameters {
-                    if state {
-                        sources.insert(source);
-                    }
-                    else {
-                        sources.remove(&source);
-                    }
-                }
-            },
-            Message::ToggleComment(index, comment, state) => {
-                let category = &mut data.categories[index];
-                if let Parameters::Comments(comments) = &mut category.parameters {
-                    if state {
-                        comments.insert(comment);
-                    }
-                    else {
-                        comments.remove(&comment);
-                    }
+            Message::ToggleBlocked(key) => {
+                if !data.blocked_groups.remove(&key) {
+                    data.blocked_groups.insert(key);
                 }
             },
             Message::ToggleUseGeneral(index, state) => {
                 let cat = &mut data.categories[index];
                 cat.use_general = state;
             },
-            Message::NetworkSerch(s) => {
-                self.parameter_search = s
+            Message::SetPriority(index, priority) => {
+                data.categories[index].priority = priority;
+            },
+            Message::GroupRateLimit(key, limit) => {
+                let limit = match limit.trim() {
+                    "" => None,
+                    limit => match limit.parse::<u32>() {
+                        Ok(limit) => Some(limit).filter(|l| *l > 0),
+                        Err(_) => return Task::none(),
+                    },
+                };
+                if let Some(group) = data.groups.get_mut(&key) {
+                    group.rate_limit = limit;
+                }
             },
             Message::GroupSearch(s) => {
                 self.group_search = s
@@ -169,20 +196,193 @@ impl CategoryScreen {
                 self.new_category_name = None;
                 self.selected_category = None;
             },
-            Message::ParameterChoose(index, param) => {
-                self.selected_parameter = param;
-                
+            Message::RuleKindSelected(kind) => {
+                self.rule_kind = kind;
+                self.rule_value.clear();
+            },
+            Message::RuleValueEdit(value) => {
+                self.rule_value = value;
+            },
+            Message::RuleScopeSelected(scope) => {
+                self.rule_scope = scope;
+            },
+            Message::RuleTarget(path) => {
+                self.rule_target = path;
+            },
+            Message::AddRule(index) => {
+                let Some(new_rule) = self.rule_kind.build(&self.rule_value, self.rule_scope) else {
+                    return Task::none()
+                };
+                let rule = &mut data.categories[index].rule;
+                // Single condition saved via API becomes a part of group, so more conditions can be added
+                if self.rule_target.is_empty() && !matches!(rule, Rule::All(_) | Rule::Any(_)) {
+                    *rule = Rule::All(vec![std::mem::take(rule)]);
+                }
+                if let Some(Rule::All(rules) | Rule::Any(rules)) = rule.get_mut(&self.rule_target) {
+                    rules.push(new_rule);
+                    self.rule_value.clear();
+                }
+            },
+            Message::RemoveRule(index, path) => {
+                let Some((last, parent)) = path.split_last() else {
+                    return Task::none()
+                };
+                match data.categories[index].rule.get_mut(parent) {
+                    Some(Rule::All(rules) | Rule::Any(rules)) if *last < rules.len() => {
+                        rules.remove(*last);
+                    },
+                    Some(Rule::Not(rule)) => **rule = Rule::default(),
+                    _ => (),
+                }
+                if self.rule_target.starts_with(&path) {
+                    self.rule_target.clear();
+                }
+            },
+            Message::SetCombinator(index, path, kind) => {
+                if let Some(rule) = data.categories[index].rule.get_mut(&path)
+                    && let Rule::All(rules) | Rule::Any(rules) = rule {
+                    let rules = std::mem::take(rules);
+                    *rule = if kind == RuleKind::All { Rule::All(rules) } else { Rule::Any(rules) };
+                }
+            },
+            Message::ToggleSchedule(index, state) => {
+                data.categories[index].schedule = state.then(Schedule::default);
+            },
+            Message::ToggleDay(index, day) => {
+                if let Some(schedule) = &mut data.categories[index].schedule && !schedule.days.remove(&day) {
+                    schedule.days.insert(day);
+                }
+            },
+            Message::ScheduleRangeEdit(value) => {
+                self.schedule_range = value;
+            },
+            Message::AddRange(index) => {
+                if let Some(schedule) = &mut data.categories[index].schedule
+                    && let Some(range) = TimeRange::parse(&self.schedule_range) {
+                    schedule.ranges.push(range);
+                    self.schedule_range.clear();
+                }
+            },
+            Message::RemoveRange(index, range) => {
+                if let Some(schedule) = &mut data.categories[index].schedule && range < schedule.ranges.len() {
+                    schedule.ranges.remove(range);
+                }
+            },
+            Message::TimezoneEdit(index, timezone) => {
+                if let Some(schedule) = &mut data.categories[index].schedule {
+                    schedule.timezone = timezone;
+                }
+            },
+            Message::ToggleQueue(index, state) => {
+                if let Some(schedule) = &mut data.categories[index].schedule {
+                    schedule.queue = state;
+                }
+            },
+            Message::ToggleDigest(index, state) => {
                 let category = &mut data.categories[index];
-                let param = match param {
-                    ParameterChoose::Network => std::mem::replace(&mut category.parameters, Parameters::Networks(self.cur_networks.clone())),
-                    ParameterChoose::Source => std::mem::replace(&mut category.parameters, Parameters::Sources(self.cur_sources.clone())),
-                    ParameterChoose::Comment => std::mem::replace(&mut category.parameters, Parameters::Comments(self.cur_comments.clone())),
+                category.digest = state.then(Digest::default);
+                self.load_digest_template(category.digest.as_ref());
+            },
+            Message::DigestInterval(index, value) => {
+                if let Some(digest) = &mut data.categories[index].digest
+                    && let Ok(interval) = value.trim().parse::<u32>() {
+                    digest.interval = interval.max(1);
+                }
+            },
+            Message::DigestMaxItems(index, value) => {
+                if let Some(digest) = &mut data.categories[index].digest {
+                    match value.trim() {
+                        "" => digest.max_items = 0,
+                        value => if let Ok(max_items) = value.parse() {
+                            digest.max_items = max_items;
+                        },
+                    }
+                }
+            },
+            Message::ExportCategories => {
+                let mut dialog = rfd::AsyncFileDialog::new()
+                .set_title("Зберегти категорії")
+                .add_filter("RON", &["ron"])
+                .add_filter("JSON", &["json"])
+                .set_file_name(match self.selected_category {
+                    Some(index) => format!("{}.ron", data.categories[index].name()),
+                    None => "categories.ron".to_owned(),
+                });
+                if let Some(home) = std::env::home_dir() {
+                    dialog = dialog.set_directory(home);
+                }
+                return Task::perform(dialog.save_file(), |p| Message::ExportPath(p).into());
+            },
+            Message::ExportPath(path) => {
+                if let Some(path) = path {
+                    let file = match self.selected_category {
+                        Some(index) => CategoryFile::export([&data.categories[index]], &data.groups),
+                        None => CategoryFile::export(&data.categories, &data.groups),
+                    };
+                    let count = file.categories.len();
+                    return Task::future(async move {
+                        match file.save_to(path.path()).await {
+                            Ok(_) => MainMessage::Notification(format!("Категорій збережено: {count}")),
+                            Err(e) => {
+                                log::error!("Error saving categories: {e}");
+                                MainMessage::Notification(format!("Помилка збереження категорій: {e}"))
+                            },
+                        }
+                    })
+                }
+            },
+            Message::ImportCategories => {
+                let mut dialog = rfd::AsyncFileDialog::new()
+                .set_title("Завантажити категорії")
+                .add_filter("Категорії", &["ron", "json"]);
+                if let Some(home) = std::env::home_dir() {
+                    dialog = dialog.set_directory(home);
+                }
+                return Task::perform(dialog.pick_file(), |p| Message::ImportPath(p).into());
+            },
+            Message::ImportPath(path) => {
+                if let Some(path) = path {
+                    return Task::future(async move {
+                        match CategoryFile::load_from(path.path()).await {
+                            Ok(file) => Message::CategoriesLoaded(Box::new(file)).into(),
+                            Err(e) => {
+                                log::error!("Error loading categories: {e}");
+                                MainMessage::Notification(format!("Помилка завантаження категорій: {e}"))
+                            },
+                        }
+                    })
+                }
+            },
+            Message::CategoriesLoaded(file) => {
+                let (categories, unresolved) = match file.resolve(&data.groups) {
+                    Ok(resolved) => resolved,
+                    Err(e) => return Task::done(MainMessage::Notification(format!("Помилка завантаження категорій: {e}"))),
                 };
+                let count = categories.len();
+                let part = SettingsPart { categories: Some(categories), ..Default::default() };
+                part.apply(data, &[Section::Categories], Strategy::Merge);
+                self.selected_category = None;
 
-                match param {
-                    Parameters::Comments(v) => self.cur_comments = v,
-                    Parameters::Sources(v) => self.cur_sources = v,
-                    Parameters::Networks(v) => self.cur_networks = v,
+                let mut notification = format!("Категорій завантажено: {count}");
+                if !unresolved.is_empty() {
+                    for group in unresolved.iter() {
+                        log::warn!("Group {} of imported category {} is not found", group.group, group.category);
+                    }
+                    let groups = unresolved.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+                    notification.push_str(&format!(". Не знайдено груп: {groups}"));
+                }
+                return Task::done(MainMessage::Notification(notification));
+            },
+            Message::DigestTemplateEdit(index, action) => {
+                self.digest_template.perform(action);
+                if let Some(digest) = &mut data.categories[index].digest {
+                    match self.digest_template.text().parse::<DigestTemplate>() {
+                        Ok(template) => {
+                            digest.template = template;
+                            self.digest_error = None;
+                        },
+                        Err(e) => self.digest_error = Some(e.to_string()),
+                    }
                 }
             },
         }
@@ -190,7 +390,13 @@ impl CategoryScreen {
         Task::none()
     }
 
+    fn load_digest_template(&mut self, digest: Option<&Digest>) {
+        self.digest_template = text_editor::Content::with_text(&digest.map(|d| d.template.to_string()).unwrap_or_default());
+        self.digest_error = None;
+    }
+
     fn category_list<'a>(&'a self, data: &'a AppData) -> Element<'a, Message> {
+        let now = jiff::Timestamp::now();
         let col = Column::new()
         .spacing(5)
         .push(
@@ -230,6 +436,26 @@ impl CategoryScreen {
                 .into()
             )
         )
+        .push(
+            Row::new()
+            .spacing(5)
+            .push(
+                tooltip(
+                    my_button("Експорт", Some(icon!(file_download).size(22)), false)
+                    .width(Length::Fill)
+                    .on_press(Message::ExportCategories),
+                    "Зберегти вибрану категорію або всі, якщо жодна не вибрана, у файл RON чи JSON",
+                )
+            )
+            .push(
+                tooltip(
+                    my_button("Імпорт", Some(icon!(upload_file).size(22)), false)
+                    .width(Length::Fill)
+                    .on_press(Message::ImportCategories),
+                    "Групи, яких немає у списку, шукаються за назвою",
+                )
+            )
+        )
         .push(
             space()
             .height(15)
@@ -259,6 +485,19 @@ impl CategoryScreen {
                                     .width(Length::Fill)
                                     .center()
                                 )
+                                .push_maybe(
+                                    category.schedule.as_ref().map(|_| {
+                                        let live = category.is_live(now);
+                                        tooltip(
+                                            icon!(schedule)
+                                            .size(24)
+                                            .style(move |theme: &iced::Theme| text::Style {
+                                                color: Some(if live { theme.extended_palette().success.base.color } else { theme.extended_palette().secondary.weak.color }),
+                                            }),
+                                            if live { "Зараз на зміні" } else { "Поза розкладом" },
+                                        )
+                                    })
+                                )
                                 .push(
                                     tooltip(
                                         button(
@@ -317,7 +556,7 @@ impl CategoryScreen {
         .into()
     }
 
-    fn general_groups<'a>(&'a self, groups: &'a HashMap<Key, Group>) -> Element<'a, Message> {
+    fn general_groups<'a>(&'a self, groups: &'a HashMap<Key, Group>, blocked_groups: &'a HashSet<Key>) -> Element<'a, Message> {
         fn sort((k1, g1): &(&Key, &Group), (k2, g2): &(&Key, &Group)) -> std::cmp::Ordering {
             let v = k1.cmp(k2);
             if v == std::cmp::Ordering::Equal {
@@ -340,9 +579,13 @@ impl CategoryScreen {
         
         let mut added = Vec::new();
         let mut other = Vec::new();
+        let mut blocked = Vec::new();
 
         for (k, g) in groups.iter().filter(|(_, g)| g.title.contains(&self.group_search)) {
-            if g.active() {
+            if blocked_groups.contains(k) {
+                blocked.push((k, g));
+            }
+            else if g.active() {
                 added.push((k, g));
             }
             else {
@@ -351,6 +594,7 @@ impl CategoryScreen {
         }
         added.sort_unstable_by(sort);
         other.sort_unstable_by(sort);
+        blocked.sort_unstable_by(sort);
 
         vec_to_col!(added, |key: &'a Key, group: &'a Group| -> container::Container<'a, Message> {
             container(
@@ -365,9 +609,12 @@ impl CategoryScreen {
                     .width(Length::Shrink)
                 )
                 .push(
-                    text(&group.title)
+                    group_title(key, group)
                     .width(Length::Fill)
                 )
+                .push(
+                    rate_limit_input(key, group)
+                )
                 .push(
                     tooltip(
                         button(
@@ -422,9 +669,12 @@ impl CategoryScreen {
                     .width(Length::Shrink)
                 )
                 .push(
-                    text(&group.title)
+                    group_title(key, group)
                     .width(Length::Fill)
                 )
+                .push(
+                    rate_limit_input(key, group)
+                )
                 .push(
                     tooltip(
                         button(
@@ -437,10 +687,26 @@ impl CategoryScreen {
                         "Додати, щоб надсилати"
                     )
                 )
+                .push(
+                    tooltip(
+                        button(
+                            icon!(block)
+                            .size(22)
+                        )
+                        .padding(0)
+                        .style(button::text)
+                        .on_press(Message::ToggleBlocked(key.clone())),
+                        "Ніколи не надсилати, навіть за категоріями"
+                    )
+                )
             )
             .style(entry_style)
         });
 
+        vec_to_col!(blocked, |key: &'a Key, group: &'a Group| -> container::Container<'a, Message> {
+            excluded_entry(key, group, Message::ToggleBlocked(key.clone()), "Прибрати з чорного списку")
+        });
+
         container(
             Column::new()
             .spacing(7)
@@ -471,6 +737,8 @@ impl CategoryScreen {
                     .push(added)
                     .push("Не відправляти")
                     .push(other)
+                    .push("Чорний список")
+                    .push(blocked)
                 )
             )
             .width(Length::Fill)
@@ -508,10 +776,14 @@ impl CategoryScreen {
         
         let mut added = Vec::new();
         let mut other = Vec::new();
+        let mut excluded = Vec::new();
         let category = &data.categories[index];
 
         for (k, g) in data.groups.iter().filter(|(_, g)| g.title.contains(&self.group_search)) {
-            if let Some(mode) = category.groups.get(k) && mode.active() {
+            if category.excluded.contains(k) {
+                excluded.push((k, g));
+            }
+            else if let Some(mode) = category.groups.get(k) && mode.active() {
                 added.push((k, g));
             }
             else {
@@ -520,6 +792,7 @@ impl CategoryScreen {
         }
         added.sort_unstable_by(sort);
         other.sort_unstable_by(sort);
+        excluded.sort_unstable_by(sort);
 
         vec_to_col!(added, |key: &'a Key, group: &'a Group| -> container::Container<'a, Message> {
             let mode = category.groups.get(key).map_or_else(Default::default, Clone::clone);
@@ -535,7 +808,7 @@ impl CategoryScreen {
                     .width(Length::Shrink)
                 )
                 .push(
-                    text(&group.title)
+                    group_title(key, group)
                     .width(Length::Fill)
                 )
                 .push(
@@ -590,7 +863,7 @@ impl CategoryScreen {
                     .width(Length::Shrink)
                 )
                 .push(
-                    text(&group.title)
+                    group_title(key, group)
                     .width(Length::Fill)
                 )
                 .push(
@@ -605,10 +878,26 @@ impl CategoryScreen {
                         "Додати, щоб надсилати"
                     )
                 )
+                .push(
+                    tooltip(
+                        button(
+                            icon!(block)
+                            .size(22)
+                        )
+                        .padding(0)
+                        .style(button::text)
+                        .on_press(Message::ToggleExcluded(index, key.clone())),
+                        "Ніколи не надсилати повідомлення цієї категорії, навіть за іншими категоріями"
+                    )
+                )
             )
             .style(entry_style)
         });
 
+        vec_to_col!(excluded, |key: &'a Key, group: &'a Group| -> container::Container<'a, Message> {
+            excluded_entry(key, group, Message::ToggleExcluded(index, key.clone()), "Дозволити надсилати")
+        });
+
         container(
             Column::new()
             .spacing(7)
@@ -639,6 +928,8 @@ impl CategoryScreen {
                     .push(added)
                     .push("Не відправляти")
                     .push(other)
+                    .push("Ніколи не відправляти")
+                    .push(excluded)
                 )
             )
             .width(Length::Fill)
@@ -653,272 +944,214 @@ impl CategoryScreen {
         .into()
     }
 
-    fn category_parameters<'a>(&'a self, index: usize, data: &'a AppData) -> Element<'a, Message> {
-        macro_rules! vec_to_col {
-            ($name:ident, $map:expr) => {
-                let $name = $name.into_iter().fold(
-                    Column::new()
-                    .spacing(5),
-                    |col, val| col.push(($map)(val))
-                );
-            };
-        }
-
-        let category = &data.categories[index];
-        let (active, other) = match category.parameters {
-            Parameters::Networks(_) => {
-                let mut active = Vec::new();
-                let mut other = Vec::new();
-
-                for (id, network) in data.networks.iter().filter(|(_, net)| net.name.contains(&self.parameter_search)) {
-                    if category.contains_network(id) {
-                        active.push((id, network))
-                    }
-                    else {
-                        other.push((id, network))
-                    }
-                }
-
-                macro_rules! vec_to_col {
-                    ($name:ident, $map:expr) => {
-                        let $name = $name.into_iter().fold(
-                            Column::new()
-                            .spacing(5),
-                            |col, (key, group)| col.push(($map)(key, group))
-                        );
-                    };
-                }
-
-                vec_to_col!(active, |id: &'a u64, network: &'a NetworkInfo| -> container::Container<'a, _> {
-                    container(
-                        Row::new()
-                        .width(Length::Fill)
-                        .padding(5)
-                        .spacing(5)
-                        .align_y(Alignment::Center)
-                        .push(
-                            text(&network.name)
-                            .width(Length::Fill)
-                        )
-                        .push(
-                            tooltip(
-                                button(
-                                    icon!(remove)
-                                    .size(22)
-                                )
-                                .padding(0)
-                                .style(button::text)
-                                .on_press(Message::ToggleNetwork(index, *id, false)), 
-                                "Не надсилати",
-                            )
-                        )
-                    )
-                    .style(entry_style)
-                });
-
-                vec_to_col!(other, |id: &'a u64, network: &'a NetworkInfo| -> container::Container<'a, _> {
-                    container(
-                        Row::new()
-                        .width(Length::Fill)
-                        .padding(5)
-                        .spacing(5)
-                        .align_y(Alignment::Center)
-                        .push(
-                            text(&network.name)
-                            .width(Length::Fill)
-                        )
-                        .push(
-                            tooltip(
-                                button(
-                                    icon!(add)
-                                    .size(22)
-                                )
-                                .padding(0)
-                                .style(button::text)
-                                .on_press(Message::ToggleNetwork(index, *id, true)), 
-                                "Додати, щоб надсилати"
-                            )
-                        )
+    fn category_rule<'a>(&'a self, index: usize, data: &'a AppData) -> Element<'a, Message> {
+        container(
+            Column::new()
+            .spacing(7)
+            .padding(10)
+            .push(
+                text("Умови надсилання")
+                .center()
+                .width(Length::Fill)
+            )
+            .push(
+                Row::new()
+                .spacing(5)
+                .align_y(Alignment::Center)
+                .push(
+                    pick_list(RuleKind::ALL, Some(self.rule_kind), Message::RuleKindSelected)
+                )
+                .push(
+                    self.rule_value_input(index, data)
+                )
+                .push_maybe(
+                    self.rule_kind.has_scope().then(|| pick_list(TextScope::ALL, Some(self.rule_scope), Message::RuleScopeSelected))
+                )
+                .push(
+                    tooltip(
+                        button(icon!(add).size(22))
+                        .padding(0)
+                        .style(button::text)
+                        .on_press_maybe(self.rule_kind.build(&self.rule_value, self.rule_scope).map(|_| Message::AddRule(index))),
+                        "Додати умову до обраної групи",
                     )
-                    .style(entry_style)
-                });
-
-                (active, other)
-            },
-            Parameters::Sources(_) => {
-                let mut active = Vec::new();
-                let mut other = Vec::new();
+                )
+            )
+            .push(
+                space()
+                .height(15)
+            )
+            .push(
+                scrollable(
+                    container(self.rule_view(index, &data.categories[index].rule, Vec::new(), &data.networks))
+                    .padding(Padding::default().horizontal(15))
+                    .width(Length::Fill)
+                )
+            )
+            .width(Length::Fill)
+            .height(Length::Fill)
+        )
+        .style(|theme: &iced::Theme| container::Style {
+                background: Some(theme.extended_palette().background.weaker.color.into()),
+                border: Border::default().rounded(20),
+                shadow: Shadow { color: Color::BLACK.scale_alpha(0.2), blur_radius: 4.0, offset: Vector::new(0.0, 3.0) },
+                ..Default::default()
+        })
+        .into()
+    }
 
-                for source in data.sources.iter().filter(|source| source.contains(&self.parameter_search)) {
-                    if category.contains_source(source) {
-                        active.push(source);
-                    }
-                    else {
-                        other.push(source)
-                    }
-                }
+    /// Days, hours and time zone category is active in, category without schedule is always active
+    fn category_schedule<'a>(&'a self, index: usize, data: &'a AppData) -> Element<'a, Message> {
+        let schedule = data.categories[index].schedule.as_ref();
+        let mut col = Column::new()
+        .spacing(7)
+        .padding(10)
+        .push(
+            checkbox(schedule.is_some())
+            .label("Надсилати лише за розкладом")
+            .on_toggle(move |state| Message::ToggleSchedule(index, state))
+        );
 
-                vec_to_col!(active, |source: &'a String| -> container::Container<'a, _> {
-                    container(
-                        Row::new()
-                        .width(Length::Fill)
-                        .padding(5)
-                        .spacing(5)
-                        .align_y(Alignment::Center)
-                        .push(
-                            text(source)
-                            .width(Length::Fill)
-                        )
-                        .push(
-                            tooltip(
-                                button(
-                                    icon!(remove)
-                                    .size(22)
-                                )
-                                .padding(0)
-                                .style(button::text)
-                                .on_press(Message::ToggleSource(index, source.clone(), false)), 
-                                "Не надсилати",
-                            )
-                        )
+        if let Some(schedule) = schedule {
+            col = col
+            .push(
+                Day::ALL.into_iter().fold(
+                    Row::new()
+                    .spacing(5)
+                    .align_y(Alignment::Center)
+                    .push(text("Дні:")),
+                    |row, day| row.push(
+                        my_button(text(day.to_string()), None::<Element<'_, Message>>, schedule.days.contains(&day))
+                        .on_press(Message::ToggleDay(index, day))
                     )
-                    .style(entry_style)
-                });
-
-                vec_to_col!(other, |source: &'a String| -> container::Container<'a, _> {
-                    container(
-                        Row::new()
-                        .width(Length::Fill)
-                        .padding(5)
-                        .spacing(5)
-                        .align_y(Alignment::Center)
-                        .push(
-                            text(source)
-                            .width(Length::Fill)
-                        )
-                        .push(
-                            tooltip(
-                                button(
-                                    icon!(add)
-                                    .size(22)
-                                )
+                )
+            )
+            .push(
+                schedule.ranges.iter().enumerate().fold(
+                    Row::new()
+                    .spacing(5)
+                    .align_y(Alignment::Center)
+                    .push(text("Години:"))
+                    .push_maybe(schedule.ranges.is_empty().then(|| text("цілодобово"))),
+                    |row, (range_index, range)| row.push(
+                        container(
+                            Row::new()
+                            .spacing(3)
+                            .align_y(Alignment::Center)
+                            .push(text(range.to_string()))
+                            .push(
+                                button(icon!(close).size(18))
                                 .padding(0)
                                 .style(button::text)
-                                .on_press(Message::ToggleSource(index, source.clone(), true)), 
-                                "Додати, щоб надсилати"
+                                .on_press(Message::RemoveRange(index, range_index))
                             )
                         )
+                        .padding(Padding::default().horizontal(5))
+                        .style(entry_style)
                     )
-                    .style(entry_style)
-                });
-
-                (active, other)
-            },
-            Parameters::Comments(_) => {
-                let mut active = Vec::new();
-                let mut other = Vec::new();
-
-                for comment in data.comments.iter().filter(|comment| comment.contains(&self.parameter_search)) {
-                    if category.contains_comment(comment) {
-                        active.push(comment);
-                    }
-                    else {
-                        other.push(comment)
-                    }
-                }
-
-                vec_to_col!(active, |comment: &'a String| -> container::Container<'a, _> {
-                    container(
-                        Row::new()
-                        .width(Length::Fill)
-                        .padding(5)
-                        .spacing(5)
-                        .align_y(Alignment::Center)
-                        .push(
-                            text(comment)
-                            .width(Length::Fill)
-                        )
-                        .push(
-                            tooltip(
-                                button(
-                                    icon!(remove)
-                                    .size(22)
-                                )
-                                .padding(0)
-                                .style(button::text)
-                                .on_press(Message::ToggleComment(index, comment.clone(), false)), 
-                                "Не надсилати",
-                            )
-                        )
+                )
+                .push(
+                    text_input("22:00-06:00", &self.schedule_range)
+                    .style(text_input_style)
+                    .on_input(Message::ScheduleRangeEdit)
+                    .on_submit(Message::AddRange(index))
+                    .width(120)
+                )
+                .push(
+                    tooltip(
+                        button(icon!(add).size(22))
+                        .padding(0)
+                        .style(button::text)
+                        .on_press_maybe(TimeRange::parse(&self.schedule_range).map(|_| Message::AddRange(index))),
+                        "Додати проміжок, що закінчується раніше початку, триває до наступного дня",
                     )
-                    .style(entry_style)
-                });
+                )
+            )
+            .push(
+                Row::new()
+                .spacing(5)
+                .align_y(Alignment::Center)
+                .push(text("Часовий пояс:"))
+                .push(
+                    text_input("Системний, наприклад Europe/Kyiv", &schedule.timezone)
+                    .style(text_input_style)
+                    .on_input(move |timezone| Message::TimezoneEdit(index, timezone))
+                    .width(250)
+                )
+                .push_maybe(
+                    schedule.time_zone().is_none().then(|| text("Невідомий часовий пояс").style(text::danger))
+                )
+            )
+            .push(
+                checkbox(schedule.queue)
+                .label("Надсилати повідомлення, що надійшли поза розкладом, коли він почнеться")
+                .on_toggle(move |state| Message::ToggleQueue(index, state))
+            );
+        }
 
-                vec_to_col!(other, |comment: &'a String| -> container::Container<'a, _> {
-                    container(
-                        Row::new()
-                        .width(Length::Fill)
-                        .padding(5)
-                        .spacing(5)
-                        .align_y(Alignment::Center)
-                        .push(
-                            text(comment)
-                            .width(Length::Fill)
-                        )
-                        .push(
-                            tooltip(
-                                button(
-                                    icon!(add)
-                                    .size(22)
-                                )
-                                .padding(0)
-                                .style(button::text)
-                                .on_press(Message::ToggleComment(index, comment.clone(), true)), 
-                                "Додати, щоб надсилати"
-                            )
-                        )
-                    )
-                    .style(entry_style)
-                });
+        container(col)
+        .width(Length::Fill)
+        .style(|theme: &iced::Theme| container::Style {
+                background: Some(theme.extended_palette().background.weaker.color.into()),
+                border: Border::default().rounded(20),
+                shadow: Shadow { color: Color::BLACK.scale_alpha(0.2), blur_radius: 4.0, offset: Vector::new(0.0, 3.0) },
+                ..Default::default()
+        })
+        .into()
+    }
 
-                (active, other)
-            },
-        };
+    fn category_digest<'a>(&'a self, index: usize, data: &'a AppData) -> Element<'a, Message> {
+        let digest = data.categories[index].digest.as_ref();
+        let mut col = Column::new()
+        .spacing(7)
+        .padding(10)
+        .push(
+            checkbox(digest.is_some())
+            .label("Надсилати зведенням")
+            .on_toggle(move |state| Message::ToggleDigest(index, state))
+        );
 
-        container(
-            Column::new()
-            .spacing(7)
-            .align_x(Alignment::Center)
-            .padding(10)
+        if let Some(digest) = digest {
+            let max_items = match digest.max_items {
+                0 => String::new(),
+                max_items => max_items.to_string(),
+            };
+            col = col
             .push(
-                pick_list(
-                    ParameterChoose::ALL,
-                    Some(&self.selected_parameter),
-                    move |param| Message::ParameterChoose(index, param)
+                Row::new()
+                .spacing(5)
+                .align_y(Alignment::Center)
+                .push(text("Кожні"))
+                .push(
+                    text_input("15", &digest.interval.to_string())
+                    .style(text_input_style)
+                    .on_input(move |value| Message::DigestInterval(index, value))
+                    .width(60)
                 )
+                .push(text("хв або коли зібрано"))
+                .push(
+                    text_input("∞", &max_items)
+                    .style(text_input_style)
+                    .on_input(move |value| Message::DigestMaxItems(index, value))
+                    .width(60)
+                )
+                .push(text("повідомлень"))
             )
             .push(
-                text_input("Пошук", &self.parameter_search)
-                .style(text_input_style)
-                .on_input(Message::NetworkSerch)
-                .width(Length::Fill)
-            )
-            .push(
-                scrollable(
-                    Column::new()
-                    .padding(Padding::default().horizontal(15))
-                    .spacing(15)
-                    .align_x(Alignment::Center)
-                    .width(Length::Fill)
-                    .push("Відправляти")
-                    .push(active)
-                    .push("Не відправляти")
-                    .push(other)
+                tooltip(
+                    text_editor(&self.digest_template)
+                    .height(120)
+                    .on_action(move |action| Message::DigestTemplateEdit(index, action)),
+                    "Частина між %кожне% і %/кожне% повторюється для кожного повідомлення, %кількість% — кількість повідомлень",
                 )
             )
-            .width(Length::Fill)
-            .height(Length::Fill)
-        )
+            .push_maybe(
+                self.digest_error.as_ref().map(|e| text(e).style(text::danger))
+            );
+        }
+
+        container(col)
+        .width(Length::Fill)
         .style(|theme: &iced::Theme| container::Style {
                 background: Some(theme.extended_palette().background.weaker.color.into()),
                 border: Border::default().rounded(20),
@@ -928,6 +1161,107 @@ impl CategoryScreen {
         .into()
     }
 
+    /// Networks, sources and comments are chosen from known ones, other conditions are typed
+    fn rule_value_input<'a>(&'a self, index: usize, data: &'a AppData) -> Element<'a, Message> {
+        let known = |values: &HashSet<String>| -> Element<'a, Message> {
+            let mut values = values.iter().cloned().collect::<Vec<_>>();
+            values.sort();
+            let selected = values.contains(&self.rule_value).then(|| self.rule_value.clone());
+            pick_list(values, selected, Message::RuleValueEdit)
+            .width(Length::Fill)
+            .into()
+        };
+        match self.rule_kind {
+            RuleKind::Network => {
+                let mut networks = data.networks.values()
+                    .map(|network| NetworkChoice(network.id, network.name.clone()))
+                    .collect::<Vec<_>>();
+                networks.sort_by(|a, b| a.1.cmp(&b.1));
+                let selected = networks.iter().find(|n| n.0.to_string() == self.rule_value).cloned();
+                pick_list(networks, selected, |network| Message::RuleValueEdit(network.0.to_string()))
+                .placeholder("Мережа")
+                .width(Length::Fill)
+                .into()
+            },
+            RuleKind::Source => known(&data.sources),
+            RuleKind::Comment => known(&data.comments),
+            kind if kind.has_value() => {
+                let placeholder = match kind {
+                    RuleKind::Frequency => "145.5 або 144-146",
+                    RuleKind::Keywords => "Слова через кому",
+                    RuleKind::Regex => "Вираз, напр. сок[іо]л\\w*",
+                    _ => "Текст",
+                };
+                text_input(placeholder, &self.rule_value)
+                .style(text_input_style)
+                .on_input(Message::RuleValueEdit)
+                .on_submit(Message::AddRule(index))
+                .width(Length::Fill)
+                .into()
+            },
+            _ => space().width(Length::Fill).into(),
+        }
+    }
+
+    fn rule_view<'a>(&'a self, index: usize, rule: &'a Rule, path: Vec<usize>, networks: &'a NetworksPool) -> Element<'a, Message> {
+        let remove = (!path.is_empty()).then(|| tooltip(
+            button(icon!(remove).size(22))
+            .padding(0)
+            .style(button::text)
+            .on_press(Message::RemoveRule(index, path.clone())),
+            "Видалити умову",
+        ));
+
+        let header = match rule {
+            Rule::All(_) | Rule::Any(_) => {
+                let target = self.rule_target == path;
+                let combinator_path = path.clone();
+                Row::new()
+                .spacing(5)
+                .align_y(Alignment::Center)
+                .push(
+                    pick_list(
+                        [RuleKind::All, RuleKind::Any],
+                        Some(RuleKind::from(rule)),
+                        move |kind| Message::SetCombinator(index, combinator_path.clone(), kind),
+                    )
+                    .width(Length::Fill)
+                )
+                .push(
+                    tooltip(
+                        button(icon!(playlist_add).size(22))
+                        .padding(0)
+                        .style(if target { button::primary } else { button::text })
+                        .on_press(Message::RuleTarget(path.clone())),
+                        "Додавати умови сюди",
+                    )
+                )
+            },
+            Rule::Not(_) => Row::new().push(text(RuleKind::Not.to_string()).width(Length::Fill)),
+            rule => Row::new().push(text(rule_label(rule, networks)).width(Length::Fill)),
+        }
+        .push_maybe(remove);
+
+        let children = rule.children().iter()
+            .enumerate()
+            .fold(Column::new().spacing(5), |col, (i, child)| {
+                let mut child_path = path.clone();
+                child_path.push(i);
+                col.push(self.rule_view(index, child, child_path, networks))
+            });
+
+        Column::new()
+        .spacing(5)
+        .push(
+            container(header.padding(5))
+            .style(entry_style)
+        )
+        .push_maybe(
+            (!rule.children().is_empty()).then(|| container(children).padding(Padding::default().left(20)))
+        )
+        .into()
+    }
+
     pub fn view<'a>(&'a self, data: &'a AppData) -> Element<'a, Message> {
         let mut main_row = Row::new()
         .padding(20)
@@ -957,14 +1291,31 @@ impl CategoryScreen {
                         self.category_groups(index, data)
                     )
                     .push(
-                        self.category_parameters(index, data)
+                        self.category_rule(index, data)
                     )
                 )
+                .push(
+                    self.category_schedule(index, data)
+                )
+                .push(
+                    self.category_digest(index, data)
+                )
                 .push(
                     container(
-                        checkbox(data.categories[index].use_general)
-                        .label("Відправляти у загальні групи")
-                        .on_toggle(move |state| Message::ToggleUseGeneral(index, state))
+                        Row::new()
+                        .spacing(20)
+                        .align_y(Alignment::Center)
+                        .push(
+                            checkbox(data.categories[index].use_general)
+                            .label("Відправляти у загальні групи")
+                            .on_toggle(move |state| Message::ToggleUseGeneral(index, state))
+                        )
+                        .push(
+                            tooltip(
+                                pick_list(Priority::ALL, Some(data.categories[index].priority), move |priority| Message::SetPriority(index, priority)),
+                                "Пріоритет повідомлень категорії",
+                            )
+                        )
                     )
                     .center_x(Length::Fill)
                 )
@@ -981,7 +1332,7 @@ impl CategoryScreen {
                     .center()
                 )
                 .push(
-                    self.general_groups(&data.groups)
+                    self.general_groups(&data.groups, &data.blocked_groups)
                 )
                 .width(Length::FillPortion(3))
             )
@@ -1071,20 +1422,74 @@ fn tooltip<'a, Message: 'a>(content: impl Into<Element<'a, Message>>, tooltip_te
     .style(tooltip_style)
 }
 
-#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
-enum ParameterChoose {
-    #[display("Мережі")]
-    Network,
-    #[display("Джерела")]
-    Source,
-    #[display("Коментарі")]
-    Comment,
+/// Network offered in condition editor, shown by name
+#[derive(Debug, Display, Clone, PartialEq)]
+#[display("{_1}")]
+struct NetworkChoice(u64, String);
+
+/// Condition description for the editor
+fn rule_label(rule: &Rule, networks: &NetworksPool) -> String {
+    let value = match rule {
+        Rule::Network(id) => networks.get(id).map_or_else(|| id.to_string(), |network| network.name.clone()),
+        Rule::Frequency(min, max) if min == max => min.to_string(),
+        Rule::Frequency(min, max) => format!("{min}–{max}"),
+        Rule::Source(value) | Rule::Comment(value) | Rule::Title(value) | Rule::Location(value) | Rule::Sender(value) | Rule::Reciever(value) => value.clone(),
+        Rule::Keywords(keywords, scope) => format!("{} ({scope})", keywords.join(", ")),
+        Rule::Regex(pattern, scope) => format!("{} ({scope})", pattern.as_str()),
+        Rule::All(_) | Rule::Any(_) | Rule::Not(_) => return RuleKind::from(rule).to_string(),
+    };
+    format!("{}: {value}", RuleKind::from(rule))
 }
 
-impl ParameterChoose {
-    const ALL: &[Self] = &[
-        Self::Network,
-        Self::Source,
-        Self::Comment,
-    ];
+/// Entry of group which never receives messages, with button returning it to other groups
+fn excluded_entry<'a>(key: &'a Key, group: &'a Group, on_press: Message, hint: &'a str) -> container::Container<'a, Message> {
+    container(
+        Row::new()
+        .width(Length::Fill)
+        .padding(5)
+        .spacing(5)
+        .align_y(Alignment::Center)
+        .push(
+            key.icon()
+            .height(22)
+            .width(Length::Shrink)
+        )
+        .push(
+            group_title(key, group)
+            .width(Length::Fill)
+        )
+        .push(
+            tooltip(
+                button(
+                    icon!(lock_open)
+                    .size(22)
+                )
+                .padding(0)
+                .style(button::text)
+                .on_press(on_press),
+                hint
+            )
+        )
+    )
+    .style(entry_style)
+}
+
+/// Groups of additional accounts are marked with account number
+/// Limit of messages per minute sent to the group
+fn rate_limit_input<'a>(key: &'a Key, group: &'a Group) -> tooltip_widget::Tooltip<'a, Message> {
+    let limit = group.rate_limit.map(|l| l.to_string()).unwrap_or_default();
+    tooltip(
+        text_input("∞", &limit)
+        .width(50)
+        .style(text_input_style)
+        .on_input(|limit| Message::GroupRateLimit(key.clone(), limit)),
+        "Найбільше повідомлень за хвилину",
+    )
+}
+
+fn group_title<'a>(key: &Key, group: &'a Group) -> text::Text<'a> {
+    match key.account() {
+        (_, 0) => text(&group.title),
+        (_, account) => text(format!("{} [{}]", group.title, account as u32 + 1)),
+    }
 }
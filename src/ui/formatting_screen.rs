@@ -1,6 +1,7 @@
-use iced::{Alignment, Border, Color, Element, Length, Shadow, Task, Vector, border::Radius, widget::{Column, Row, container, text, text_editor}};
+use derive_more::Display;
+use iced::{Alignment, Border, Color, Element, Length, Shadow, Task, Vector, border::Radius, widget::{Column, Row, container, pick_list, text, text_editor}};
 
-use crate::{appdata::AppData, message::{Formatting, TEST_MESSAGE}};
+use crate::{appdata::AppData, message::{Formatting, TEST_MESSAGE}, messangers::{Key, Messenger}};
 
 use super::Message as MainMessage;
 
@@ -19,11 +20,26 @@ const HELP: &str = "Спеціальні коди, які можна вписа
 
 pub struct FormattingScreen {
     pub editor: text_editor::Content,
+    target: TemplateTarget,
+}
+
+/// Whose template is edited. Messengers, categories and groups without own template use the global one
+#[derive(Debug, Clone, PartialEq, Display)]
+pub enum TemplateTarget {
+    #[display("Загальний шаблон")]
+    Global,
+    #[display("Шаблон {_0}")]
+    Messenger(Messenger),
+    #[display("Категорія: {_0}")]
+    Category(String),
+    #[display("Група: {_1}")]
+    Group(Key, String),
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     TextEditor(text_editor::Action),
+    TargetSelected(TemplateTarget),
 }
 
 impl From<Message> for MainMessage {
@@ -41,14 +57,20 @@ impl FormattingScreen {
 
         Self {
             editor: content,
+            target: TemplateTarget::Global,
         }
     }
 
-    pub fn update(&mut self, message: Message, _data: &mut AppData) -> Task<MainMessage> {
+    pub fn update(&mut self, message: Message, data: &mut AppData) -> Task<MainMessage> {
         match message {
             Message::TextEditor(action) => {
                 self.editor.perform(action);
             },
+            Message::TargetSelected(target) => {
+                self.store(data);
+                self.target = target;
+                self.load(data);
+            },
         }
         Task::none()
     }
@@ -57,7 +79,53 @@ impl FormattingScreen {
         Formatting::parse(&self.editor.text())
     }
 
-    pub fn view(&self) -> Element<'_, Message> {
+    /// Puts edited template into settings. Empty template of messenger, category or group means the global one is used
+    pub fn store(&self, data: &mut AppData) {
+        let formatting = (!self.editor.text().trim().is_empty()).then(|| self.formatting());
+        match &self.target {
+            TemplateTarget::Global => data.formatting = Some(self.formatting()),
+            TemplateTarget::Messenger(messenger) => match formatting {
+                Some(formatting) => _ = data.messenger_formatting.insert(*messenger, formatting),
+                None => _ = data.messenger_formatting.remove(messenger),
+            },
+            TemplateTarget::Category(name) => if let Some(category) = data.categories.iter_mut().find(|c| c.name() == name) {
+                category.formatting = formatting;
+            },
+            TemplateTarget::Group(key, _) => if let Some(group) = data.groups.get_mut(key) {
+                group.formatting = formatting;
+            },
+        }
+    }
+
+    /// Shows template of the current target, switching to the global one if target was removed
+    pub fn load(&mut self, data: &AppData) {
+        let formatting = match &self.target {
+            TemplateTarget::Global => Some(data.formatting.as_ref()),
+            TemplateTarget::Messenger(messenger) => Some(data.messenger_formatting.get(messenger)),
+            TemplateTarget::Category(name) => data.categories.iter().find(|c| c.name() == name).map(|c| c.formatting.as_ref()),
+            TemplateTarget::Group(key, _) => data.groups.get(key).map(|g| g.formatting.as_ref()),
+        };
+        let Some(formatting) = formatting else {
+            self.target = TemplateTarget::Global;
+            return self.load(data)
+        };
+        self.editor = match formatting {
+            Some(f) => text_editor::Content::with_text(&f.to_string()),
+            None => text_editor::Content::new(),
+        };
+    }
+
+    pub fn view(&self, data: &AppData) -> Element<'_, Message> {
+        let mut groups = data.groups.iter()
+            .filter(|(_, group)| group.active())
+            .map(|(key, group)| TemplateTarget::Group(key.clone(), group.title.clone()))
+            .collect::<Vec<_>>();
+        groups.sort_by_key(|target| target.to_string());
+        let targets = [TemplateTarget::Global, TemplateTarget::Messenger(Messenger::Signal), TemplateTarget::Messenger(Messenger::Whatsapp)].into_iter()
+            .chain(data.categories.iter().map(|c| TemplateTarget::Category(c.name().to_owned())))
+            .chain(groups)
+            .collect::<Vec<_>>();
+
         container(
             Row::new()
             .padding(15)
@@ -66,9 +134,15 @@ impl FormattingScreen {
             .width(Length::Fill)
             .align_y(Alignment::Center)
             .push(
-                text_editor(&self.editor)
-                .height(Length::Fill)
-                .on_action(Message::TextEditor)
+                Column::new()
+                .spacing(10)
+                .push(pick_list(targets, Some(self.target.clone()), Message::TargetSelected).width(Length::Fill))
+                .push(
+                    text_editor(&self.editor)
+                    .placeholder("Використовується загальний шаблон")
+                    .height(Length::Fill)
+                    .on_action(Message::TextEditor)
+                )
             )
             .push(
                 container(
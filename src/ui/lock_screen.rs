@@ -0,0 +1,95 @@
+use iced::{Alignment, Border, Element, Length, Task, widget::{Column, button, container, text, text_input}};
+use zeroize::Zeroizing;
+
+use crate::crypto;
+
+use super::{Message as MainMessage, ext::PushMaybe};
+
+/// Asks passphrase of encrypted profile before settings are loaded
+#[derive(Default)]
+pub struct LockScreen {
+    passphrase: Zeroizing<String>,
+    unlocking: bool,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    PassphraseEdit(String),
+    Unlock,
+    UnlockFailed(String),
+}
+
+impl From<Message> for MainMessage {
+    fn from(value: Message) -> Self {
+        MainMessage::LockScrMessage(value)
+    }
+}
+
+impl LockScreen {
+    pub fn update(&mut self, message: Message) -> Task<MainMessage> {
+        match message {
+            Message::PassphraseEdit(passphrase) => {
+                self.passphrase = Zeroizing::new(passphrase);
+            },
+            Message::Unlock if !self.unlocking => {
+                self.unlocking = true;
+                self.error = None;
+                let passphrase = self.passphrase.clone();
+                // Key derivation is deliberately slow, so it runs off the UI thread
+                return Task::perform(
+                    tokio::task::spawn_blocking(move || crypto::unlock(&passphrase)),
+                    |res| match res {
+                        Ok(Ok(())) => MainMessage::Unlocked,
+                        Ok(Err(e)) => Message::UnlockFailed(e.to_string()).into(),
+                        Err(e) => Message::UnlockFailed(e.to_string()).into(),
+                    }
+                );
+            },
+            Message::Unlock => (),
+            Message::UnlockFailed(e) => {
+                log::warn!("Failed to unlock profile: {e}");
+                self.unlocking = false;
+                self.error = Some(e);
+            },
+        }
+        Task::none()
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        container(
+            Column::new()
+            .width(400)
+            .spacing(15)
+            .align_x(Alignment::Center)
+            .push(
+                text("Профіль зашифровано")
+                .size(24)
+            )
+            .push(
+                text_input("Пароль", &self.passphrase)
+                .secure(true)
+                .style(|theme: &iced::Theme, status| text_input::Style {
+                    border: Border::default().rounded(10).color(theme.extended_palette().secondary.weak.color).width(1),
+                    ..text_input::default(theme, status)
+                })
+                .on_input(Message::PassphraseEdit)
+                .on_submit(Message::Unlock)
+            )
+            .push(
+                button(if self.unlocking { "Розблокування..." } else { "Розблокувати" })
+                .on_press_maybe((!self.unlocking && !self.passphrase.is_empty()).then_some(Message::Unlock))
+                .style(button::primary)
+                .padding(10)
+            )
+            .push_maybe(
+                self.error.as_ref().map(|e| {
+                    text(format!("Не вдалося розблокувати: {e}"))
+                    .style(text::danger)
+                })
+            )
+        )
+        .center(Length::Fill)
+        .into()
+    }
+}
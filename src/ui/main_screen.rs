@@ -1,9 +1,9 @@
-use std::{collections::{HashMap, VecDeque}, sync::Arc, time::Instant};
+use std::{collections::VecDeque, sync::Arc, time::Instant};
 
-use iced::{Alignment, Animation, Border, Color, Element, Length, Task, alignment::Horizontal, border::Radius, widget::{Column, Row, button, container, qr_code, responsive, scrollable, space, text, text_editor}};
+use iced::{Alignment, Animation, Border, Color, Element, Length, Task, alignment::Horizontal, border::Radius, widget::{Column, Row, button, container, pick_list, qr_code, responsive, scrollable, space, text, text_editor}};
 use serde::{Deserialize, Serialize};
 
-use crate::{icon, message::{MessageInner, OperatorMessage, SendMode}, messangers::Key, ui::{AppData, message_history::SendMessageInfo}};
+use crate::{icon, message::{Formatting, OperatorMessage, Priority, SendMode}, messangers::Key, runtime::{self, Core}, send_categories::{RouteReason, RoutingInfo, route}, ui::{AppData, message_history::SendMessageInfo}};
 
 use super::Message as MainMessage;
 use super::ext::PushMaybe;
@@ -14,7 +14,9 @@ pub enum Message {
     SetRegisterUrl(Option<url::Url>),
     SetWhatsappUrl(Option<String>),
     TextEdit(text_editor::Action),
-    SendMessage(String, Option<String>, Option<u64>, Option<String>, Option<String>),
+    SendMessage(String, RoutingInfo),
+    /// Operator message sent as is, rendered with templates of its groups
+    SendOperatorMessage(Arc<OperatorMessage>),
     SendMessagePressed,
     SetGroups(Vec<(Key, String)>),
     UpdateMessageHistory,
@@ -27,6 +29,9 @@ pub enum Message {
     Cancel(usize),
     RefreshMessage(usize),
     Expand(usize),
+    ShowRoute(bool),
+    /// Priority of the next sent message, raises priorities given by categories
+    PriorityChosen(Priority),
     SendMessageDirect(Arc<SendMessageInfo>),
 }
 
@@ -46,7 +51,11 @@ pub(super) struct MainScreen {
     pub edit: Option<Arc<SendMessageInfo>>,
     now: Instant,
     pub message_queue: Vec<OperatorMessage>,
-    pub cur_message: Option<OperatorMessage>
+    pub cur_message: Option<OperatorMessage>,
+    /// Routing preview of the message being composed is shown
+    show_route: bool,
+    /// Priority chosen for the message being composed, reset after sending
+    priority: Option<Priority>,
 }
 
 impl MainScreen {
@@ -63,10 +72,12 @@ impl MainScreen {
             now: Instant::now(),
             message_queue: Vec::new(),
             cur_message: None,
+            show_route: false,
+            priority: None,
         }
     }
 
-    pub fn update(&mut self, message: Message, now: Instant, data: &mut AppData) -> Task<MainMessage> {
+    pub fn update(&mut self, message: Message, now: Instant, core: &mut Core) -> Task<MainMessage> {
         self.now = now;
 
         match message {
@@ -113,94 +124,39 @@ impl MainScreen {
                 self.message_content.perform(action);
             },
             Message::SendMessagePressed => {
-                let (text, freq, network, source, comment) = if let Some(message) = self.cur_message.take() {
-                    let text = self.message_content.text();
-                    let MessageInner {frequency, network_id, source, comment, ..} = message.0;
-                    (text, Some(frequency), network_id, Some(source), comment)
+                let text = self.message_content.text();
+                let priority = self.priority.take();
+                if let Some(message) = self.cur_message.as_mut() && priority.is_some() {
+                    message.0.priority = priority;
                 }
-                else {
-                    (self.message_content.text(), None, None, None, None)
+                let send = match self.cur_message.take() {
+                    // Unchanged message gets templates of categories and groups
+                    Some(message) if text.trim_end() == message.format(core.data.formatting.as_ref()).trim_end() => Message::SendOperatorMessage(Arc::new(message)),
+                    Some(message) => Message::SendMessage(text, RoutingInfo::from(&message)),
+                    None => Message::SendMessage(text, RoutingInfo { priority, ..Default::default() }),
                 };
 
                 self.message_content = text_editor::Content::new();
 
                 return Task::batch([
                     Task::done(Message::NextMessage.into()),
-                    Task::done(Message::SendMessage(text, freq, network, source, comment).into()),
+                    Task::done(send.into()),
                 ])
             }
-            Message::SendMessage(message, freq, network, source, comment) => {
-                let mut message = SendMessageInfo::new(message, freq);
-                let mut groups: HashMap<&Key, SendMode> = HashMap::new();
-                let mut use_general = false;
-
-                if let Some(network) = network {
-                    log::info!("Has network {}", &network);
-
-                    for category in data.categories.iter().filter(|c| c.active) {
-                        if category.contains_network(&network) {
-                            for (key, mode) in category.groups.iter() {
-                                groups.entry(key)
-                                .and_modify(|mode| mode.update(*mode))
-                                .or_insert(*mode);
-                            }
-                            use_general |= category.use_general;
-                        }
-                    }
-                }
-                if let Some(source) = source {
-                    log::info!("Has source {}", &source);
-
-                    for category in data.categories.iter().filter(|c| c.active) {
-                        if category.contains_source(&source) {
-                            for (key, mode) in category.groups.iter() {
-                                groups.entry(key)
-                                .and_modify(|mode| mode.update(*mode))
-                                .or_insert(*mode);
-                            }
-                            use_general |= category.use_general;
-                        }
-                    }
-                }
-                if let Some(comment) = comment {
-                    log::info!("Has comment {}", &comment);
-
-                    for category in data.categories.iter().filter(|c| c.active) {
-                        if category.contains_comment(&comment) {
-                            for (key, mode) in category.groups.iter() {
-                                groups.entry(key)
-                                .and_modify(|mode| mode.update(*mode))
-                                .or_insert(*mode);
-                            }
-                            use_general |= category.use_general;
-                        }
-                    }
-                }
-
-                if use_general || groups.is_empty() {
-                    log::info!("Getting general");
-                    for (key, group) in data.groups.iter() {
-                        if group.active() {
-                            if let Some(v) = groups.get_mut(key) {
-                                v.update(group.send_mode);
-                            }
-                            else {
-                                groups.insert(key, group.send_mode);
-                            }
-                        }
-                    }
-                }
-                for (key, mode) in groups {
-                    message.push(key.clone(), mode);
-                }
-                
-                let message = Arc::new(message);
-
-                if self.message_history.len() >= data.history_len as usize {
-                    self.message_history.pop_back();
-                }
-                self.message_history.push_front(message.clone());
+            Message::SendMessage(message, info) => {
+                let message = core.route_message(message, info);
+                self.push_history(message.clone(), core.data.history_len);
 
+                return Task::batch([
+                    Task::done(MainMessage::Notification("Початок відправки повідомлення".to_owned())),
+                    Task::done(MainMessage::SendMessage(
+                        message
+                    )),
+                ])
+            },
+            Message::SendOperatorMessage(message) => {
+                let message = core.route_operator_message(&message);
+                self.push_history(message.clone(), core.data.history_len);
 
                 return Task::batch([
                     Task::done(MainMessage::Notification("Початок відправки повідомлення".to_owned())),
@@ -209,26 +165,18 @@ impl MainScreen {
                     )),
                 ])
             },
+            Message::ShowRoute(show) => {
+                self.show_route = show;
+            },
+            Message::PriorityChosen(priority) => {
+                self.priority = Some(priority);
+            },
             Message::SendMessageDirect(message) => {
                 message.set_status(super::message_history::SendStatus::Pending, std::sync::atomic::Ordering::Relaxed);
                 return Task::done(MainMessage::SendMessage(message))
             },
             Message::SetGroups(groups) => {
-                let mut replace_map = groups
-                    .into_iter()
-                    .map(|(key, title)| {
-                        let group = Group { title, send_mode: data.groups.get(&key).map(|g| g.send_mode).unwrap_or(SendMode::Off) };
-                        (key, group)
-                    })
-                    .collect::<HashMap<_, _>>();
-                
-                replace_map.extend(
-                    std::mem::take(&mut data.groups)
-                    .into_iter()
-                    .filter(|(key, _)| matches!(key, Key::Signal(_)))
-                );
-
-                data.groups = replace_map;
+                core.set_groups(groups);
             },
             Message::UpdateMessageHistory => {
                 // Makes window redraw to display actual information
@@ -260,12 +208,8 @@ impl MainScreen {
             },
             Message::CancelEdit => {
                 self.message_content = text_editor::Content::new();
-                if self.message_history.len() >= data.history_len as usize {
-                    self.message_history.pop_back();
-                }
-                self.message_history.push_front(
-                    self.edit.take().unwrap()
-                );
+                let message = self.edit.take().unwrap();
+                self.push_history(message, core.data.history_len);
                 self.show_side_bar.go_mut(false, now);
             },
             Message::ConfirmEdit => {
@@ -273,24 +217,15 @@ impl MainScreen {
                 // We are making it mut, because we know that it already finished sending and is available only in `self.edit`
                 let message = Arc::get_mut(&mut arc_message).unwrap();
                 
-                let timestamps = message.groups_signal.iter().map(|group| group.timestamp.swap(0, std::sync::atomic::Ordering::Relaxed)).collect();
-                let whatsapp_ids = message.groups_whatsapp.iter_mut()
-                .map(|msg| {
-                    let mut id = msg.sent_id.lock().unwrap();
-                    std::mem::take(&mut *id)
-                })
-                .collect::<Vec<_>>();
+                let (timestamps, whatsapp_ids) = message.take_sent_ids();
 
                 let new_message = self.message_content.text();
                 self.message_content = text_editor::Content::new();
 
-                message.content = new_message;
+                message.set_content(new_message);
                 message.set_status(super::message_history::SendStatus::Pending, std::sync::atomic::Ordering::Relaxed);
 
-                if self.message_history.len() >= data.history_len as usize {
-                    self.message_history.pop_back();
-                }
-                self.message_history.push_front(arc_message.clone());
+                self.push_history(arc_message.clone(), core.data.history_len);
 
                 self.show_side_bar.go_mut(false, now);
 
@@ -299,7 +234,7 @@ impl MainScreen {
             Message::NextMessage => {
                 self.cur_message = self.message_queue.pop();
                 if let Some(message) = &self.cur_message {
-                    self.message_content = text_editor::Content::with_text(&message.format(data.formatting.as_ref()));
+                    self.message_content = text_editor::Content::with_text(&message.format(core.data.formatting.as_ref()));
                     self.show_side_bar.go_mut(true, now);
                 }
                 else {
@@ -312,6 +247,10 @@ impl MainScreen {
         Task::none()
     }
 
+    pub fn push_history(&mut self, message: Arc<SendMessageInfo>, history_len: u32) {
+        runtime::push_history(&mut self.message_history, message, history_len);
+    }
+
     fn maybe_hide(&mut self) {
         if self.register_url.is_none()
         && self.whatsapp_url.is_none()
@@ -350,7 +289,7 @@ impl MainScreen {
         .into()
     }
 
-    fn side_bar(&self) -> Element<'_, Message> {
+    fn side_bar<'a>(&'a self, data: &'a AppData) -> Element<'a, Message> {
         Element::new(
             Row::new()
             .align_y(Alignment::Center)
@@ -381,13 +320,68 @@ impl MainScreen {
                 .on_press(Message::ShowMessageHistory(!self.show_side_bar.value()))   
             )
             .push(
-                self.main_part()
+                self.main_part(data)
             )
             .height(Length::Fill)
         )
     }
 
-    fn main_part(&self) -> Element<'_, Message> {
+    /// Groups current message would be sent to, with send mode and categories that caused it
+    fn route_preview<'a>(&'a self, data: &'a AppData) -> Element<'a, Message> {
+        let mut info = self.cur_message.as_ref().map(RoutingInfo::from).unwrap_or_default();
+        info.text = Some(self.message_content.text());
+        let mut destinations = route(&data.categories, &data.groups, &data.blocked_groups, &info)
+            .into_iter()
+            .map(|(key, destination)| {
+                let title = data.groups.get(&key).map_or_else(|| key.to_string(), |g| g.title.clone());
+                (title, destination)
+            })
+            .collect::<Vec<_>>();
+        destinations.sort_by(|a, b| a.0.cmp(&b.0));
+        if destinations.is_empty() {
+            return text("Повідомлення нікуди не буде надіслано").into();
+        }
+
+        let list = destinations.into_iter().fold(
+            Column::new().spacing(5),
+            |col, (title, destination)| {
+                let reasons = destination.reasons.iter()
+                    .map(|(reason, _)| match reason {
+                        RouteReason::Category(name) => name.clone(),
+                        _ => reason.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                col.push(
+                    Row::new()
+                    .spacing(5)
+                    .align_y(Alignment::Center)
+                    .push(
+                        match destination.mode {
+                            SendMode::Frequency => icon!(radio),
+                            SendMode::Normal => icon!(send),
+                            SendMode::Off if destination.queued().next().is_some() => icon!(schedule),
+                            SendMode::Off => icon!(block),
+                        }
+                    )
+                    .push(
+                        Column::new()
+                        .push(text(title))
+                        .push(text(reasons).size(12))
+                    )
+                )
+            }
+        );
+
+        container(
+            scrollable(list)
+            .width(Length::Fill)
+        )
+        .max_height(200)
+        .into()
+    }
+
+    fn main_part<'a>(&'a self, data: &'a AppData) -> Element<'a, Message> {
         let mut col = Column::new()
         .width(self.show_side_bar.interpolate(0., 450., self.now))
         .height(Length::Fill)
@@ -452,6 +446,30 @@ impl MainScreen {
                     style
                 })
             )
+            .push_maybe(
+                self.edit.is_none().then(||
+                    Row::new()
+                    .spacing(5)
+                    .align_y(Alignment::Center)
+                    .push(
+                        button(
+                            Row::new()
+                            .spacing(5)
+                            .push(icon!(alt_route))
+                            .push(text("Куди піде?"))
+                        )
+                        .style(if self.show_route { button::primary } else { button::secondary })
+                        .on_press(Message::ShowRoute(!self.show_route))
+                    )
+                    .push(
+                        pick_list(Priority::ALL, self.priority, Message::PriorityChosen)
+                        .placeholder("Пріоритет")
+                    )
+                )
+            )
+            .push_maybe(
+                (self.show_route && self.edit.is_none()).then(|| self.route_preview(data))
+            )
             .push(
                 if self.edit.is_some() {
                     Element::from(
@@ -580,9 +598,9 @@ impl MainScreen {
         .push_maybe(
             (self.show_side_bar.is_animating(self.now) || self.show_side_bar.value()).then(|| {
                 #[cfg(debug_assertions)]
-                let el = self.side_bar();
+                let el = self.side_bar(data);
                 #[cfg(not(debug_assertions))]
-                let el = self.main_part();
+                let el = self.main_part(data);
                 el
             })
         )
@@ -594,9 +612,19 @@ impl MainScreen {
 pub struct Group {
     pub title: String,
     pub send_mode: SendMode,
+    /// Template operator messages are rendered with for this group, overrides templates of categories
+    #[serde(default)]
+    pub formatting: Option<Formatting>,
+    /// Most messages sent to the group per minute, unlimited when `None`
+    #[serde(default)]
+    pub rate_limit: Option<u32>,
 }
 
 impl Group {
+    pub fn new(title: String, send_mode: SendMode) -> Self {
+        Self { title, send_mode, formatting: None, rate_limit: None }
+    }
+
     pub fn active(&self) -> bool {
         self.send_mode.active()
     }
@@ -1,21 +1,31 @@
-use std::{sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering}}, time::Duration};
+use std::{collections::{BTreeSet, HashMap}, sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering}}, time::Duration};
 
 use futures::channel::mpsc::UnboundedSender;
 use iced::{Alignment, Border, Color, Element, Length, Shadow, Theme, Vector, widget::{Column, Row, button, container, mouse_area, progress_bar, space, text, tooltip}};
 use serde::{Deserialize, Serialize};
 use wacore_binary::jid::Jid;
 
-use crate::{appdata::AppData, icon, message::SendMode, messangers::Key, ui::ext::PushMaybe};
+use crate::{appdata::AppData, icon, message::{Priority, SendMode}, messangers::{AccountId, Key, Messenger}, ui::ext::PushMaybe};
 
 const TOOLTIP_DELAY: Duration = Duration::from_millis(500);
 
+/// Source of unique ids for messages sent during this session
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug)]
 pub struct SendMessageInfo {
+    pub id: u64,
     pub content: String,
+    /// Text sent to groups rendered with their own template instead of `content`
+    pub overrides: HashMap<Key, String>,
     pub freq: Option<String>,
+    /// Messages of higher priority are sent first
+    pub priority: Priority,
     pub status: AtomicU8,
     pub groups_signal: Vec<GroupInfoSignal>,
     pub groups_whatsapp: Vec<GroupInfoWhatsapp>,
+    /// Account jobs still sending the message, the last one to finish sets the final status
+    sending_jobs: AtomicUsize,
     cancel_handle: Mutex<Option<tokio::task::AbortHandle>>,
     expanded: AtomicBool,
 }
@@ -45,70 +55,90 @@ impl From<u8> for SendStatus {
 
 #[derive(Debug)]
 pub struct GroupInfoSignal {
+    pub account: AccountId,
     pub key: [u8; 32],
-    pub(super) timestamp: AtomicU64,
+    /// Timestamps of sent parts of the message, empty until it is sent
+    pub(super) timestamps: Mutex<Vec<u64>>,
+    failed: AtomicBool,
     pub send_mode: SendMode,
 }
 
 impl Clone for GroupInfoSignal {
     fn clone(&self) -> Self {
         Self {
+            account: self.account,
             key: self.key,
-            timestamp: AtomicU64::new(self.timestamp.load(Ordering::Relaxed)),
+            timestamps: Mutex::new(self.timestamps()),
+            failed: AtomicBool::new(self.failed()),
             send_mode: self.send_mode,
         }
     }
 }
 
 impl GroupInfoSignal {
-    pub fn new(key: [u8; 32], send_mode: SendMode) -> Self {
-        Self { key, timestamp: AtomicU64::new(0), send_mode }
+    pub fn new(account: AccountId, key: [u8; 32], send_mode: SendMode) -> Self {
+        Self { account, key, timestamps: Mutex::new(Vec::new()), failed: AtomicBool::new(false), send_mode }
     }
 
-    pub fn set_timestamp(&self, timestamp: u64, ordering: std::sync::atomic::Ordering) {
-        self.timestamp.store(timestamp, ordering);
+    pub fn set_timestamps(&self, timestamps: Vec<u64>) {
+        *self.timestamps.lock().unwrap() = timestamps;
     }
 
-    pub fn timestamp(&self, ordering: std::sync::atomic::Ordering) -> Option<u64> {
-        let timestamp = self.timestamp.load(ordering);
-        if timestamp == 0 {
-            None
-        }
-        else {
-            Some(timestamp)
-        }
+    /// Remembers one more sent part
+    pub fn push_timestamp(&self, timestamp: u64) {
+        self.timestamps.lock().unwrap().push(timestamp);
+    }
+
+    pub fn timestamps(&self) -> Vec<u64> {
+        self.timestamps.lock().unwrap().clone()
+    }
+
+    pub fn sent(&self) -> bool {
+        !self.timestamps.lock().unwrap().is_empty()
+    }
+
+    /// The last attempt to send to the group failed
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
     }
 
-    pub fn sent(&self, ordering: std::sync::atomic::Ordering) -> bool {
-        self.timestamp.load(ordering) != 0
+    pub fn set_failed(&self, failed: bool) {
+        self.failed.store(failed, Ordering::Relaxed);
     }
 }
 
 #[derive(Debug)]
 pub struct GroupInfoWhatsapp {
+    pub account: AccountId,
     pub key: Jid,
     sent: AtomicBool,
-    pub(super) sent_id: Mutex<String>,
+    /// Ids of sent parts of the message
+    pub(super) sent_ids: Mutex<Vec<String>>,
+    failed: AtomicBool,
     pub send_mode: SendMode,
 }
 
 impl Clone for GroupInfoWhatsapp {
     fn clone(&self) -> Self {
         Self {
+            account: self.account,
             key: self.key.clone(),
             sent: AtomicBool::new(self.sent.load(Ordering::Relaxed)),
-            sent_id: Mutex::new(self.sent_id.lock().unwrap().clone()),
+            sent_ids: Mutex::new(self.message_ids()),
+            failed: AtomicBool::new(self.failed()),
             send_mode: self.send_mode
         }
     }
 }
 
 impl GroupInfoWhatsapp {
-    pub fn new(key: Jid, send_mode: SendMode) -> Self {
+    pub fn new(account: AccountId, key: Jid, send_mode: SendMode) -> Self {
         Self {
+            account,
             key,
             sent: AtomicBool::new(false),
-            sent_id: Mutex::new(String::new()),
+            sent_ids: Mutex::new(Vec::new()),
+            failed: AtomicBool::new(false),
             send_mode
         }
     }
@@ -117,30 +147,44 @@ impl GroupInfoWhatsapp {
         self.sent.load(ordering)
     }
 
-    pub fn delete(&self, ordering: Ordering) {
-        self.sent.store(false, ordering);
+    pub fn set_ids(&self, ids: Vec<String>) {
+        let mut lock = self.sent_ids.lock().unwrap();
+        *lock = ids;
+        self.sent.store(!lock.is_empty(), Ordering::Relaxed);
     }
 
-    pub fn set_id(&self, id: String) {
-        let mut lock = self.sent_id.lock().unwrap();
-        *lock = id;
+    /// Remembers one more sent part
+    pub fn push_id(&self, id: String) {
+        self.sent_ids.lock().unwrap().push(id);
         self.sent.store(true, Ordering::Relaxed);
     }
 
-    pub fn message_id(&self) -> Option<String> {
-        let lock = self.sent_id.lock().unwrap();
-        (!lock.is_empty()).then(|| lock.clone())
+    pub fn message_ids(&self) -> Vec<String> {
+        self.sent_ids.lock().unwrap().clone()
+    }
+
+    /// The last attempt to send to the group failed
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub fn set_failed(&self, failed: bool) {
+        self.failed.store(failed, Ordering::Relaxed);
     }
 }
 
 impl SendMessageInfo {
     pub fn new(content: String, freq: Option<String>) -> Self {
         Self {
+            id: NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed),
             content,
+            overrides: HashMap::new(),
             freq,
+            priority: Priority::default(),
             status: AtomicU8::new(SendStatus::Pending as u8),
             groups_signal: Vec::new(),
             groups_whatsapp: Vec::new(),
+            sending_jobs: AtomicUsize::new(0),
             cancel_handle: Mutex::new(None),
             expanded: AtomicBool::new(false),
         }
@@ -148,14 +192,75 @@ impl SendMessageInfo {
 
     pub fn push(&mut self, group_key: Key, send_mode: SendMode) {
         match group_key {
-            Key::Signal(key) => self.groups_signal.push(GroupInfoSignal::new(key, send_mode)),
-            Key::Whatsapp(key) => self.groups_whatsapp.push(GroupInfoWhatsapp::new(key, send_mode)),
+            Key::Signal(account, key) => self.groups_signal.push(GroupInfoSignal::new(account, key, send_mode)),
+            Key::Whatsapp(account, key) => self.groups_whatsapp.push(GroupInfoWhatsapp::new(account, key, send_mode)),
+        }
+    }
+
+    /// Accounts of the messenger the message has groups in
+    pub fn accounts(&self, messenger: Messenger) -> BTreeSet<AccountId> {
+        match messenger {
+            Messenger::Signal => self.groups_signal.iter().map(|g| g.account).collect(),
+            Messenger::Whatsapp => self.groups_whatsapp.iter().map(|g| g.account).collect(),
+        }
+    }
+
+    /// Keys of groups the message failed to be sent to
+    pub fn failed_groups(&self) -> Vec<Key> {
+        self.groups_signal.iter()
+            .filter(|g| g.failed())
+            .map(|g| Key::Signal(g.account, g.key))
+            .chain(self.groups_whatsapp.iter().filter(|g| g.failed()).map(|g| Key::Whatsapp(g.account, g.key.clone())))
+            .collect()
+    }
+
+    /// Starts sending through `jobs` accounts, each of them calls [`Self::finish_job`] once it went through its groups
+    pub fn start_jobs(&self, jobs: usize) {
+        self.sending_jobs.store(jobs, Ordering::Relaxed);
+        if jobs == 0 {
+            self.set_final_status();
+        }
+    }
+
+    /// The last job to finish marks message failed if any group failed
+    pub fn finish_job(&self) {
+        if self.sending_jobs.fetch_update(Ordering::AcqRel, Ordering::Acquire, |jobs| jobs.checked_sub(1)) == Ok(1) {
+            self.set_final_status();
         }
     }
 
+    fn set_final_status(&self) {
+        let status = if self.failed_groups().is_empty() { SendStatus::Sent } else { SendStatus::Failed };
+        self.set_status(status, Ordering::Relaxed);
+    }
+
+    /// Text sent to the group
+    pub fn content_for(&self, key: &Key) -> &str {
+        self.overrides.get(key).unwrap_or(&self.content)
+    }
+
+    /// Replaces text sent to all groups, so groups with own template get the edited text too
+    pub fn set_content(&mut self, content: String) {
+        self.content = content;
+        self.overrides.clear();
+    }
+
+    /// Takes ids of already sent messages, so they can be used as edit targets.
+    /// Every group has ids of all parts the message was split into
+    pub fn take_sent_ids(&mut self) -> (Vec<Vec<u64>>, Vec<Vec<String>>) {
+        let timestamps = self.groups_signal.iter()
+        .map(|group| std::mem::take(&mut *group.timestamps.lock().unwrap()))
+        .collect();
+        let whatsapp_ids = self.groups_whatsapp.iter_mut()
+        .map(|group| std::mem::take(&mut *group.sent_ids.lock().unwrap()))
+        .collect();
+
+        (timestamps, whatsapp_ids)
+    }
+
     pub fn sent_count(&self) -> usize {
         self.groups_signal.iter()
-        .map(|g| g.sent(Ordering::Relaxed))
+        .map(|g| g.sent())
         .chain(
             self.groups_whatsapp.iter()
             .map(|g| g.sent(Ordering::Relaxed))
@@ -305,29 +410,32 @@ impl SendMessageInfo {
                         let wa = self.groups_whatsapp
                             .iter()
                             .map(|group| (
-                                data.groups.get(&Key::Whatsapp(group.key.clone()))
+                                data.groups.get(&Key::Whatsapp(group.account, group.key.clone()))
                                     .map(|group| group.title.as_str())
                                     .unwrap_or("Видалено"),
-                                group.sent(Ordering::Relaxed)
+                                group.sent(Ordering::Relaxed),
+                                group.failed()
                             ));
                         let sig = self.groups_signal
                             .iter()
                             .map(|group| (
-                                data.groups.get(&Key::Signal(group.key.clone()))
+                                data.groups.get(&Key::Signal(group.account, group.key))
                                     .map(|group| group.title.as_str())
                                     .unwrap_or("Видалено"),
-                                group.sent(Ordering::Relaxed)
+                                group.sent(),
+                                group.failed()
                             ));
                         
                         let mut data = wa.chain(sig).collect::<Vec<_>>();
-                        data.sort_by(|(s1, _), (s2, _)| s1.cmp(s2));
+                        data.sort_by(|(s1, ..), (s2, ..)| s1.cmp(s2));
 
                         Column::from_iter(
                             data.into_iter()
-                            .map(|(name, sent)| {
-                                match sent {
-                                    true => text(format!("{name}: Надіслано")).center().style(|theme: &iced::Theme| text::Style { color: Some(theme.extended_palette().success.strong.color) }),
-                                    false => text(format!("{name}: Надсилається")).center().style(|theme: &iced::Theme| text::Style { color: Some(theme.extended_palette().secondary.weak.text) }),
+                            .map(|(name, sent, failed)| {
+                                match (sent, failed) {
+                                    (true, _) => text(format!("{name}: Надіслано")).center().style(|theme: &iced::Theme| text::Style { color: Some(theme.extended_palette().success.strong.color) }),
+                                    (false, true) => text(format!("{name}: Помилка")).center().style(|theme: &iced::Theme| text::Style { color: Some(theme.extended_palette().danger.base.color) }),
+                                    (false, false) => text(format!("{name}: Надсилається")).center().style(|theme: &iced::Theme| text::Style { color: Some(theme.extended_palette().secondary.weak.text) }),
                                 }
                                 .into()
                             })
@@ -436,36 +544,64 @@ impl SendMessageInfo {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SaveMessageInfo {
     pub content: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub overrides: HashMap<Key, String>,
     pub freq: Option<String>,
+    /// Groups saved before multiple accounts were supported, all of them belong to the first account
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub groups_signal: Vec<([u8; 32], SendMode)>,
+    #[serde(default)]
+    pub signal_groups: Vec<(AccountId, [u8; 32], SendMode)>,
+    /// Same as `groups_signal`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub groups_whatsapp: Vec<(Jid, SendMode)>,
+    #[serde(default)]
+    pub whatsapp_groups: Vec<(AccountId, Jid, SendMode)>,
+    #[serde(default)]
+    pub priority: Priority,
 }
 
 impl From<&SendMessageInfo> for SaveMessageInfo {
     fn from(value: &SendMessageInfo) -> Self {
-        let groups_signal = value.groups_signal.iter().map(|g| (g.key, g.send_mode)).collect();
-        let groups_whatsapp = value.groups_whatsapp.iter().map(|g| (g.key.clone(), g.send_mode)).collect();
+        let signal_groups = value.groups_signal.iter().map(|g| (g.account, g.key, g.send_mode)).collect();
+        let whatsapp_groups = value.groups_whatsapp.iter().map(|g| (g.account, g.key.clone(), g.send_mode)).collect();
 
         Self {
             content: value.content.clone(),
+            overrides: value.overrides.clone(),
             freq: value.freq.clone(),
-            groups_signal,
-            groups_whatsapp,
+            groups_signal: Vec::new(),
+            signal_groups,
+            groups_whatsapp: Vec::new(),
+            whatsapp_groups,
+            priority: value.priority,
         }
     }
 }
 
 impl From<SaveMessageInfo> for SendMessageInfo {
     fn from(value: SaveMessageInfo) -> Self {
-        let groups_signal = value.groups_signal.into_iter().map(|(key, mode)| GroupInfoSignal::new(key, mode)).collect();
-        let groups_whatsapp = value.groups_whatsapp.into_iter().map(|(key, mode)| GroupInfoWhatsapp::new(key, mode)).collect();
+        let groups_signal = value.groups_signal.into_iter()
+            .map(|(key, mode)| (0, key, mode))
+            .chain(value.signal_groups)
+            .map(|(account, key, mode)| GroupInfoSignal::new(account, key, mode))
+            .collect();
+        let groups_whatsapp = value.groups_whatsapp.into_iter()
+            .map(|(key, mode)| (0, key, mode))
+            .chain(value.whatsapp_groups)
+            .map(|(account, key, mode)| GroupInfoWhatsapp::new(account, key, mode))
+            .collect();
 
         Self {
+            id: NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed),
             content: value.content,
+            overrides: value.overrides,
             freq: value.freq,
+            priority: value.priority,
             status: AtomicU8::new(SendStatus::Pending as u8),
             groups_signal,
             groups_whatsapp,
+            sending_jobs: AtomicUsize::new(0),
             cancel_handle: Mutex::new(None),
             expanded: AtomicBool::new(false),
         }
@@ -1,10 +1,10 @@
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{fs::File, io::Write};
 
 use iced::{Alignment, Border, Color, Element, Length, Padding, Shadow, Task, Vector, widget::{Column, Row, button, checkbox, column, container, pick_list, scrollable, svg, text, text_input}};
 use rfd::{FileHandle, MessageDialogResult};
 use tracing::{error, warn};
 
-use crate::{icon, message::Formatting, messangers::signal::SignalMessage, notification, send_categories::parse_networks_data, ui::{self, AppData, ext::PushMaybe, icons::{SIGNAL_ICON, WHATSAPP_ICON}, side_menu::LinkState, theme::Theme}};
+use crate::{appdata::transfer::{Change, Section, SettingsPart, Strategy}, crypto, icon, message::Formatting, messangers::{AccountId, Messenger}, notification, paths, send_categories::parse_networks_data, ui::{self, AppData, ext::PushMaybe, side_menu::LinkState, theme::Theme}};
 
 use super::Message as MainMessage;
 
@@ -28,6 +28,7 @@ pub enum Message {
     ToggleCustomFormat(bool),
     RecieveAddressEditChanged(String),
     HistoryLenEdit(String),
+    ApiTokenEdit(String),
     ThemeSelected(Theme),
     ChooseNetworkFile,
     NetworkFileChoosen(Option<FileHandle>),
@@ -36,11 +37,23 @@ pub enum Message {
     Import(Option<FileHandle>),
     ChooseExport,
     Export(Option<FileHandle>),
-    UpdateData(AppData),
+    ToggleExportSection(Section, bool),
+    ImportLoaded(Box<SettingsPart>),
+    ToggleImportSection(Section, bool),
+    ImportStrategySelected(Strategy),
+    ApplyImport,
+    CancelImport,
     ShowAlert(String, Box<Message>),
-    ClearSignal,
-    ClearWhatsapp,
+    ClearAccount(Messenger, AccountId),
+    AddAccount(Messenger),
     AddIcon,
+    ProfileSelected(String),
+    NewProfileEdit(String),
+    CreateProfile,
+    SwitchProfile(String),
+    PassphraseEdit(String),
+    PassphraseConfirmEdit(String),
+    EnableEncryption,
 }
 
 impl From<Message> for MainMessage {
@@ -53,6 +66,34 @@ impl From<Message> for MainMessage {
 pub(super) struct SettingsScreen {
     recieve_address_edit: String,
     address_correct: bool,
+    profiles: Vec<String>,
+    new_profile: String,
+    passphrase: String,
+    passphrase_confirm: String,
+    export_sections: Vec<Section>,
+    import: Option<Import>,
+}
+
+/// Settings file loaded for import, waiting for user to review changes
+#[derive(Debug)]
+struct Import {
+    part: SettingsPart,
+    sections: Vec<Section>,
+    strategy: Strategy,
+    preview: Vec<Change>,
+}
+
+impl Import {
+    fn update_preview(&mut self, data: &AppData) {
+        self.preview = self.part.apply(&mut data.clone(), &self.sections, self.strategy);
+    }
+}
+
+fn toggle_section(sections: &mut Vec<Section>, section: Section, state: bool) {
+    sections.retain(|s| *s != section);
+    if state {
+        sections.push(section);
+    }
 }
 
 impl SettingsScreen {
@@ -60,6 +101,12 @@ impl SettingsScreen {
         Self {
             recieve_address_edit: data.recieve_address.to_string(),
             address_correct: true,
+            profiles: paths::profiles(),
+            new_profile: String::new(),
+            passphrase: String::new(),
+            passphrase_confirm: String::new(),
+            export_sections: Section::ALL.to_vec(),
+            import: None,
         }
     }
 
@@ -107,6 +154,9 @@ impl SettingsScreen {
                     data.history_len = num;
                 }
             },
+            Message::ApiTokenEdit(token) => {
+                data.api_token = (!token.is_empty()).then_some(token);
+            },
             Message::ThemeSelected(theme) => {
                 data.theme = theme.clone();
                 return Task::done(MainMessage::ThemeChange(theme));
@@ -162,10 +212,10 @@ impl SettingsScreen {
             },
             Message::Export(path) => {
                 if let Some(path) = path {
-                    let data = data.clone();
+                    let part = SettingsPart::export(data, &self.export_sections);
                     return Task::future(async move {
                         let path = path;
-                        return match data.save_to(path.path()).await {
+                        return match part.save_to(path.path()).await {
                             Ok(_) => notification!("Налаштування збережено до: {}", path.path().to_string_lossy()),
                             Err(e) => {
                                 error!("Error saving settings: {}", &e);
@@ -175,12 +225,15 @@ impl SettingsScreen {
                     })
                 }
             },
+            Message::ToggleExportSection(section, state) => {
+                toggle_section(&mut self.export_sections, section, state);
+            },
             Message::Import(path) => {
                 if let Some(path) = path {
                     return Task::future(async move {
-                        match AppData::load_from(path.path()).await {
-                            Ok(data) => {
-                                Message::UpdateData(data).into()
+                        match SettingsPart::load_from(path.path()).await {
+                            Ok(part) => {
+                                Message::ImportLoaded(Box::new(part)).into()
                             },
                             Err(e) => {
                                 error!("Error loading settings: {}", &e);
@@ -190,9 +243,38 @@ impl SettingsScreen {
                     })
                 }
             },
-            Message::UpdateData(new_data) => {
-                *data = new_data;
-                return Task::done(notification!("Налаштування завантажено!"));
+            Message::ImportLoaded(part) => {
+                if part.sections().is_empty() {
+                    return Task::done(notification!("У файлі немає налаштувань для завантаження"));
+                }
+                let mut import = Import { sections: part.sections(), part: *part, strategy: Strategy::default(), preview: Vec::new() };
+                import.update_preview(data);
+                self.import = Some(import);
+            },
+            Message::ToggleImportSection(section, state) => {
+                if let Some(import) = &mut self.import {
+                    toggle_section(&mut import.sections, section, state);
+                    import.update_preview(data);
+                }
+            },
+            Message::ImportStrategySelected(strategy) => {
+                if let Some(import) = &mut self.import {
+                    import.strategy = strategy;
+                    import.update_preview(data);
+                }
+            },
+            Message::ApplyImport => {
+                if let Some(import) = self.import.take() {
+                    import.part.apply(data, &import.sections, import.strategy);
+                    let mut tasks = vec![Task::done(notification!("Налаштування завантажено!"))];
+                    if import.sections.contains(&Section::Formatting) {
+                        tasks.push(Task::done(MainMessage::UpdateFormatting));
+                    }
+                    return Task::batch(tasks);
+                }
+            },
+            Message::CancelImport => {
+                self.import = None;
             },
             Message::ShowAlert(alert, message) => {
                 let message = message.as_ref().clone();
@@ -211,56 +293,57 @@ impl SettingsScreen {
                     }
                 );
             },
-            Message::ClearSignal => {
-                use std::fs::remove_file;
-                let base_path = match std::env::home_dir() {
-                    Some(mut v) => {
-                        v.push(".sender");
-                        v
-                    },
-                    None => PathBuf::new(),
-                };
-
-                if let Err(e) = remove_file(base_path.join("signal_data.db")) {
-                    warn!("Error while clearing signal: {}", e)
-                }
-                if let Err(e) = remove_file(base_path.join("signal_data.db-shm")) {
-                    warn!("Error while clearing signal: {}", e)
+            Message::ProfileSelected(profile) => {
+                if profile != paths::active_profile() {
+                    return Task::done(switch_profile_alert(profile).into());
                 }
-                if let Err(e) = remove_file(base_path.join("signal_data.db-wal")) {
-                    warn!("Error while clearing signal: {}", e)
+            },
+            Message::NewProfileEdit(profile) => {
+                self.new_profile = profile;
+            },
+            Message::CreateProfile => {
+                let profile = self.new_profile.trim().to_owned();
+                if paths::valid_profile_name(&profile) && !self.profiles.contains(&profile) {
+                    return Task::done(switch_profile_alert(profile).into());
                 }
-                data.signal_logged = false;
-                return Task::batch([
-                    Task::done(SignalMessage::Disconnect.into()),
-                    Task::done(ui::side_menu::Message::SetSignalState(LinkState::Unlinked).into()),
-                ]);
             },
-            Message::ClearWhatsapp => {
-                use std::fs::remove_file;
-                let base_path = match std::env::home_dir() {
-                    Some(mut v) => {
-                        v.push(".sender");
-                        v
-                    },
-                    None => PathBuf::new(),
-                };
-
-                if let Err(e) = remove_file(base_path.join("whatsapp_data.db")) {
-                    warn!("Error while clearing whatsapp: {}", e)
+            Message::SwitchProfile(profile) => {
+                return Task::done(MainMessage::SwitchProfile(profile));
+            },
+            Message::PassphraseEdit(passphrase) => {
+                self.passphrase = passphrase;
+            },
+            Message::PassphraseConfirmEdit(passphrase) => {
+                self.passphrase_confirm = passphrase;
+            },
+            Message::EnableEncryption => {
+                if self.passphrase_valid() {
+                    self.passphrase_confirm.clear();
+                    return Task::done(MainMessage::EnableEncryption(std::mem::take(&mut self.passphrase)));
                 }
-                if let Err(e) = remove_file(base_path.join("whatsapp_data.db-shm")) {
-                    warn!("Error while clearing whatsapp: {}", e)
+            },
+            Message::ClearAccount(messenger, account) => {
+                if let Err(e) = crypto::wipe_store(&messenger.store_path(account)) {
+                    warn!("Error while clearing {}: {}", messenger, e)
                 }
-                if let Err(e) = remove_file(base_path.join("whatsapp_data.db-wal")) {
-                    warn!("Error while clearing whatsapp: {}", e)
+                if let Some(account) = data.account_mut(messenger, account) {
+                    account.logged = false;
                 }
-                data.whatsapp_logged = false;
+                let unlink = match messenger {
+                    Messenger::Signal => Task::done(ui::side_menu::Message::SetSignalState(account, LinkState::Unlinked).into()),
+                    Messenger::Whatsapp => Task::batch([
+                        Task::done(MainMessage::SetWhatsappClient(account, None)),
+                        Task::done(ui::side_menu::Message::SetWhatsappState(account, LinkState::Unlinked).into())
+                    ]),
+                };
                 return Task::batch([
-                    Task::done(MainMessage::SetWhatsappClient(None)),
-                    Task::done(ui::side_menu::Message::SetWhatsappState(LinkState::Unlinked).into())
+                    Task::done(MainMessage::RemoveAccount(messenger, account)),
+                    unlink,
                 ]);
             },
+            Message::AddAccount(messenger) => {
+                return Task::done(MainMessage::AddAccount(messenger));
+            },
         }
 
         Task::none()
@@ -331,6 +414,18 @@ impl SettingsScreen {
                                     .on_input(Message::HistoryLenEdit)
                                 ]
                             )
+                            .push(
+                                column![
+                                    text("Ключ доступу до API (застосовується після перезапуску серверу)"),
+                                    text_input("Ключ доступу до API", data.api_token.as_deref().unwrap_or_default())
+                                    .secure(true)
+                                    .style(|theme: &iced::Theme, status| text_input::Style {
+                                        border: Border::default().rounded(10).color(theme.extended_palette().secondary.weak.color).width(1),
+                                        ..text_input::default(theme, status)
+                                    })
+                                    .on_input(Message::ApiTokenEdit)
+                                ]
+                            )
                         )
                         .padding(20)
                         .style(container_style)
@@ -394,77 +489,60 @@ impl SettingsScreen {
                         .style(container_style)
                     )
                     .push(
-                        Row::new()
-                        .spacing(10)
-                        .push(
-                            button(
-                                Row::new()
-                                .spacing(5)
-                                .push(
-                                    icon!(file_open)
-                                )
-                                .push(
-                                    "Завантажити налаштування"
-                                )
+                        container(
+                            Row::new()
+                            .spacing(10)
+                            .align_y(Alignment::Center)
+                            .push(
+                                text("Профіль")
+                                .center()
                             )
-                            .on_press(Message::ChooseImport)
-                            .style(button_wrapper(button::secondary))
-                            .padding(10)
-                        )
-                        .push(
-                            button(
-                                Row::new()
-                                .spacing(5)
-                                .push(
-                                    icon!(save)
-                                )
-                                .push(
-                                    "Зберегти налаштування"
+                            .push(
+                                pick_list(
+                                    self.profiles.as_slice(),
+                                    Some(paths::active_profile().to_owned()),
+                                    Message::ProfileSelected
                                 )
+                                .style(|theme: &iced::Theme, status| {
+                                    let palette = theme.extended_palette();
+                                    pick_list::Style {
+                                        border: Border::default().rounded(10).color(palette.secondary.weak.color).width(1),
+                                        background: palette.background.base.color.into(),
+                                        ..pick_list::default(theme, status)
+                                    }
+                                })
                             )
-                            .on_press(Message::ChooseExport)
-                            .style(button_wrapper(button::primary))
-                            .padding(10)
-                        )
-                    )
-                    .push(
-                        Row::new()
-                        .spacing(10)
-                        .push(
-                            button(
-                                Row::new()
-                                .spacing(5)
-                                .align_y(Alignment::Center)
-                                .push(
-                                    svg(svg::Handle::from_memory(SIGNAL_ICON))
-                                    .style(|theme: &iced::Theme, _status| svg::Style { color: Some(theme.extended_palette().danger.base.text) })
-                                    .height(24)
-                                    .width(Length::Shrink)
-                                )
-                                .push("Видалити дані Signal")
+                            .push(
+                                text_input("Назва нового профілю", &self.new_profile)
+                                .style(|theme: &iced::Theme, status| text_input::Style {
+                                    border: Border::default().rounded(10).color(theme.extended_palette().secondary.weak.color).width(1),
+                                    ..text_input::default(theme, status)
+                                })
+                                .on_input(Message::NewProfileEdit)
+                                .on_submit(Message::CreateProfile)
                             )
-                            .on_press(Message::ShowAlert("Ви точно хочете видалити дані Signal?".to_owned(), Box::new(Message::ClearSignal)))
-                            .style(button_wrapper(button::danger))
-                            .padding(10)
-                        )
-                        .push(
-                            button(
-                                Row::new()
-                                .spacing(5)
-                                .align_y(Alignment::Center)
-                                .push(
-                                    svg(svg::Handle::from_memory(WHATSAPP_ICON))
-                                    .style(|theme: &iced::Theme, _status| svg::Style { color: Some(theme.extended_palette().danger.base.text) })
-                                    .height(24)
-                                    .width(Length::Shrink)
-                                )
-                                .push("Видалити дані Whatsapp")
+                            .push(
+                                button("Створити")
+                                .on_press_maybe(paths::valid_profile_name(&self.new_profile).then_some(Message::CreateProfile))
+                                .style(button_wrapper(button::primary))
                             )
-                            .on_press(Message::ShowAlert("Ви точно хочете видалити дані Whatsapp?".to_owned(), Box::new(Message::ClearWhatsapp)))
-                            .style(button_wrapper(button::danger))
-                            .padding(10)
                         )
+                        .padding(10)
+                        .center_x(Length::Fill)
+                        .style(container_style)
+                    )
+                    .push(
+                        container(self.transfer_view())
+                        .padding(20)
+                        .style(container_style)
+                    )
+                    .push(
+                        container(self.encryption_view())
+                        .padding(20)
+                        .style(container_style)
                     )
+                    .push(account_buttons(data, Messenger::Signal))
+                    .push(account_buttons(data, Messenger::Whatsapp))
                     .push_maybe({
                         #[cfg(any(target_os = "linux", debug_assertions))]
                         let is_linux = true;
@@ -514,6 +592,150 @@ impl SettingsScreen {
     }
 }
 
+impl SettingsScreen {
+    fn passphrase_valid(&self) -> bool {
+        !self.passphrase.is_empty() && self.passphrase == self.passphrase_confirm
+    }
+
+    /// Export and import of chosen settings sections, with changes preview of loaded file
+    fn transfer_view(&self) -> Element<'_, Message> {
+        let Some(import) = &self.import else {
+            return Column::new()
+            .spacing(10)
+            .width(Length::Fill)
+            .push(
+                Section::ALL.into_iter().fold(Row::new().spacing(15), |row, section| row.push(
+                    checkbox(self.export_sections.contains(&section))
+                    .label(section.to_string())
+                    .on_toggle(move |state| Message::ToggleExportSection(section, state))
+                ))
+            )
+            .push(
+                Row::new()
+                .spacing(10)
+                .push(
+                    button(
+                        Row::new()
+                        .spacing(5)
+                        .push(
+                            icon!(file_open)
+                        )
+                        .push(
+                            "Завантажити налаштування"
+                        )
+                    )
+                    .on_press(Message::ChooseImport)
+                    .style(button_wrapper(button::secondary))
+                    .padding(10)
+                )
+                .push(
+                    button(
+                        Row::new()
+                        .spacing(5)
+                        .push(
+                            icon!(save)
+                        )
+                        .push(
+                            "Зберегти налаштування"
+                        )
+                    )
+                    .on_press_maybe((!self.export_sections.is_empty()).then_some(Message::ChooseExport))
+                    .style(button_wrapper(button::primary))
+                    .padding(10)
+                )
+            )
+            .into()
+        };
+
+        let available = import.part.sections();
+        Column::new()
+        .spacing(10)
+        .width(Length::Fill)
+        .push(text("Завантаження налаштувань").size(18))
+        .push(
+            available.into_iter().fold(Row::new().spacing(15), |row, section| row.push(
+                checkbox(import.sections.contains(&section))
+                .label(section.to_string())
+                .on_toggle(move |state| Message::ToggleImportSection(section, state))
+            ))
+        )
+        .push(
+            pick_list(Strategy::ALL, Some(import.strategy), Message::ImportStrategySelected)
+            .style(|theme: &iced::Theme, status| {
+                let palette = theme.extended_palette();
+                pick_list::Style {
+                    border: Border::default().rounded(10).color(palette.secondary.weak.color).width(1),
+                    background: palette.background.base.color.into(),
+                    ..pick_list::default(theme, status)
+                }
+            })
+        )
+        .push(
+            if import.preview.is_empty() {
+                Element::from(text("Змін немає"))
+            }
+            else {
+                import.preview.iter()
+                .fold(Column::new().spacing(2), |column, change| column.push(text(change.to_string())))
+                .into()
+            }
+        )
+        .push(
+            Row::new()
+            .spacing(10)
+            .push(
+                button("Скасувати")
+                .on_press(Message::CancelImport)
+                .style(button_wrapper(button::secondary))
+                .padding(10)
+            )
+            .push(
+                button("Застосувати")
+                .on_press_maybe((!import.preview.is_empty()).then_some(Message::ApplyImport))
+                .style(button_wrapper(button::primary))
+                .padding(10)
+            )
+        )
+        .into()
+    }
+
+    fn encryption_view(&self) -> Element<'_, Message> {
+        if crypto::enabled() {
+            return text("Дані профілю зашифровано").width(Length::Fill).center().into()
+        }
+
+        let input_style = |theme: &iced::Theme, status| text_input::Style {
+            border: Border::default().rounded(10).color(theme.extended_palette().secondary.weak.color).width(1),
+            ..text_input::default(theme, status)
+        };
+        Column::new()
+        .spacing(10)
+        .width(Length::Fill)
+        .push(text("Шифрування даних профілю паролем. Месенджери потрібно буде привʼязати знову"))
+        .push(
+            text_input("Пароль", &self.passphrase)
+            .secure(true)
+            .style(input_style)
+            .on_input(Message::PassphraseEdit)
+        )
+        .push(
+            text_input("Повторіть пароль", &self.passphrase_confirm)
+            .secure(true)
+            .style(input_style)
+            .on_input(Message::PassphraseConfirmEdit)
+        )
+        .push(
+            button("Увімкнути шифрування")
+            .on_press_maybe(self.passphrase_valid().then(|| Message::ShowAlert(
+                "Увімкнути шифрування? Дані месенджерів буде видалено, застосунок буде перезапущено. Без пароля дані не відновити".to_owned(),
+                Box::new(Message::EnableEncryption),
+            )))
+            .style(button_wrapper(button::primary))
+        )
+        .into()
+    }
+}
+
 fn container_style(theme: &iced::Theme) -> container::Style {
     container::Style {
         background: Some(theme.extended_palette().background.weakest.color.into()),
@@ -534,6 +756,53 @@ fn button_wrapper<'a>(style_fn: impl Fn(&iced::Theme, button::Status) -> button:
     }
 }
 
+fn switch_profile_alert(profile: String) -> Message {
+    Message::ShowAlert(
+        format!("Перейти до профілю \"{profile}\"? Застосунок буде перезапущено"),
+        Box::new(Message::SwitchProfile(profile)),
+    )
+}
+
+/// Buttons clearing data of every account of the messenger and adding a new one
+fn account_buttons<'a>(data: &'a AppData, messenger: Messenger) -> Row<'a, Message> {
+    Row::new()
+    .spacing(10)
+    .extend(data.accounts(messenger).iter().map(|account| {
+        button(
+            Row::new()
+            .spacing(5)
+            .align_y(Alignment::Center)
+            .push(
+                messenger.icon()
+                .style(|theme: &iced::Theme, _status| svg::Style { color: Some(theme.extended_palette().danger.base.text) })
+                .height(24)
+                .width(Length::Shrink)
+            )
+            .push(text(format!("Видалити дані {}", account.name)))
+        )
+        .on_press(Message::ShowAlert(format!("Ви точно хочете видалити дані {}?", account.name), Box::new(Message::ClearAccount(messenger, account.id))))
+        .style(button_wrapper(button::danger))
+        .padding(10)
+        .into()
+    }))
+    .push(
+        button(
+            Row::new()
+            .spacing(5)
+            .align_y(Alignment::Center)
+            .push(
+                messenger.icon()
+                .height(24)
+                .width(Length::Shrink)
+            )
+            .push(text(format!("Додати обліковий запис {messenger}")))
+        )
+        .on_press(Message::AddAccount(messenger))
+        .style(button_wrapper(button::secondary))
+        .padding(10)
+    )
+}
+
 fn add_app_launcher() -> anyhow::Result<()> {
     let err = std::io::Error::from(std::io::ErrorKind::NotFound);
 
@@ -1,23 +1,23 @@
 use std::time::Instant;
 use derive_more::Display;
+use serde::Serialize;
 use iced::{Alignment, Animation, Border, Color, Element, Length, Padding, Task, border::Radius, widget::{Column, Row, Stack, button, container, mouse_area, opaque, space, svg, text, tooltip}};
 
-use crate::{icon, message::OperatorMessage, messangers::{signal::SignalMessage, whatsapp}, notification, ui::{AppData, Screen, ext::PushMaybe, icons::{SIGNAL_ICON, WHATSAPP_ICON}}};
+use crate::{appdata::Account, icon, message::OperatorMessage, messangers::{AccountId, Messenger}, notification, runtime::Core, ui::{Screen, ext::PushMaybe}};
 
 use super::Message as MainMessage;
 
 #[derive(Debug, Clone)]
 pub enum Message {
-    LinkSignal,
-    LinkWhatsapp,
+    Link(Messenger, AccountId),
     Categories,
     Settings,
     Format,
     Main,
     Animate,
     ToggleSideMenu,
-    SetSignalState(LinkState),
-    SetWhatsappState(LinkState),
+    SetSignalState(AccountId, LinkState),
+    SetWhatsappState(AccountId, LinkState),
     UpdateGroups,
     MessageFile,
 }
@@ -31,8 +31,6 @@ impl From<Message> for MainMessage {
 pub struct SideMenu {
     now: Instant,
     pub open: Animation<bool>,
-    pub signal_state: LinkState,
-    pub whatsapp_state: LinkState,
 }
 
 impl SideMenu {
@@ -42,19 +40,13 @@ impl SideMenu {
             open: Animation::new(false)
             .quick()
             .easing(iced::animation::Easing::EaseInOut),
-            signal_state: Default::default(),
-            whatsapp_state: Default::default(),
         }
     }
 
-    pub fn update(&mut self, message: Message, now: Instant, data: &mut AppData) -> Task<MainMessage> {
+    pub fn update(&mut self, message: Message, now: Instant, core: &mut Core) -> Task<MainMessage> {
         self.now = now;
         match message {
-            Message::LinkSignal => Task::done(SignalMessage::LinkBegin.into()),
-            Message::LinkWhatsapp => {
-                self.whatsapp_state = LinkState::Linking;
-                Task::future(whatsapp::start_whatsapp_task()).discard()
-            },
+            Message::Link(messenger, account) => super::run_jobs(core.link(messenger, account)),
             Message::Categories => Task::done(MainMessage::SetScreen(Screen::Categories)),
             Message::Settings => Task::done(MainMessage::SetScreen(Screen::Settings)),
             Message::Main => Task::done(MainMessage::SetScreen(Screen::Main)),
@@ -64,11 +56,8 @@ impl SideMenu {
                 self.open.go_mut(!self.open.value(), now);
                 Task::none()
             },
-            Message::SetSignalState(state) => {
-                self.signal_state = state;
-                if state == LinkState::Linked {
-                    data.signal_logged = true
-                }
+            Message::SetSignalState(account, state) => {
+                core.set_signal_state(account, state);
                 if state != LinkState::Linking {
                     Task::done(super::main_screen::Message::SetRegisterUrl(None).into())
                 }
@@ -76,11 +65,8 @@ impl SideMenu {
                     Task::none()
                 }
             },
-            Message::SetWhatsappState(state) => {
-                self.whatsapp_state = state;
-                if state == LinkState::Linked {
-                    data.whatsapp_logged = true
-                }
+            Message::SetWhatsappState(account, state) => {
+                core.set_whatsapp_state(account, state);
                 if state != LinkState::Linking {
                     Task::done(super::main_screen::Message::SetWhatsappUrl(None).into())
                 }
@@ -124,7 +110,8 @@ impl SideMenu {
         }
     }
 
-    pub fn minimized(&self, selected_screen: Screen, data: &AppData) -> Element<'_, Message> {
+    pub fn minimized<'a>(&'a self, selected_screen: Screen, core: &'a Core) -> Element<'a, Message> {
+        let data = &core.data;
         const BUTTON_PADDING: u32 = 5;
 
         container(
@@ -151,33 +138,12 @@ impl SideMenu {
                 .spacing(20)
                 .padding(Padding::default().bottom(15))
                 .align_x(Alignment::Center)
-                .push(
-                    sidebar_tooltip(
-                        button(
-                            svg(svg::Handle::from_memory(SIGNAL_ICON))
-                            .style(move |theme: &iced::Theme, _status| svg::Style {
-                                color: match self.signal_state {
-                                    LinkState::Linked => None,
-                                    LinkState::Disconnected => Some(Color { r: 0.8, ..Color::BLACK }),
-                                    _ => Some(theme.extended_palette().background.weaker.text),
-                                },
-                            })
-                            .height(30)
-                            .width(Length::Shrink)
-                        )
-                        .on_press_maybe((self.signal_state == LinkState::Unlinked).then_some(Message::LinkSignal))
-                        .style(button::subtle)
-                        .height(Length::Shrink)
-                        .padding(Padding::default().vertical(BUTTON_PADDING).horizontal(5)),
-                        text(format!("Signal: {}", self.signal_state))
-                    )
-                )
-                .push(
+                .extend(accounts(core).map(|(messenger, account, state)| {
                     sidebar_tooltip(
                         button(
-                            svg(svg::Handle::from_memory(WHATSAPP_ICON))
+                            messenger.icon()
                             .style(move |theme: &iced::Theme, _status| svg::Style {
-                                color: match self.whatsapp_state {
+                                color: match state {
                                     LinkState::Linked => None,
                                     LinkState::Disconnected => Some(Color { r: 0.8, ..Color::BLACK }),
                                     _ => Some(theme.extended_palette().background.weaker.text),
@@ -186,13 +152,14 @@ impl SideMenu {
                             .height(30)
                             .width(Length::Shrink)
                         )
-                        .on_press_maybe((self.whatsapp_state == LinkState::Unlinked).then_some(Message::LinkWhatsapp))
+                        .on_press_maybe((state == LinkState::Unlinked).then_some(Message::Link(messenger, account.id)))
                         .style(button::subtle)
                         .height(Length::Shrink)
                         .padding(Padding::default().vertical(BUTTON_PADDING).horizontal(5)),
-                        text(format!("Whatsapp: {}", self.whatsapp_state))
+                        text(format!("{}: {}", account.name, state))
                     )
-                )
+                    .into()
+                }))
                 .push(
                     iced::widget::rule::horizontal(3)
                     .style(|theme: &iced::Theme| iced::widget::rule::Style { 
@@ -282,7 +249,8 @@ impl SideMenu {
         .into()
     }
 
-    pub fn menu_content(&self, selected_screen: Screen, data: &AppData) -> Element<'_, Message> {
+    pub fn menu_content<'a>(&'a self, selected_screen: Screen, core: &'a Core) -> Element<'a, Message> {
+        let data = &core.data;
         Column::new()
         .padding(Padding::default().horizontal(5).vertical(10))
         .spacing(20)
@@ -305,11 +273,15 @@ impl SideMenu {
                 .center()
             )
         )
-        .push(
+        .extend(accounts(core).map(|(messenger, account, state)| {
+            let linked_color = match messenger {
+                Messenger::Signal => Color::from_rgb(0.0, 0.0, 0.7),
+                Messenger::Whatsapp => Color::from_rgb(0.0, 0.7, 0.0),
+            };
             menu_button(
-                svg(svg::Handle::from_memory(SIGNAL_ICON))
+                messenger.icon()
                     .style(move |theme: &iced::Theme, _status| svg::Style {
-                        color: match self.signal_state {
+                        color: match state {
                             LinkState::Linked => None,
                             LinkState::Disconnected => Some(Color { r: 0.8, ..Color::BLACK }),
                             _ => Some(theme.extended_palette().background.weaker.text),
@@ -318,38 +290,17 @@ impl SideMenu {
                     .content_fit(iced::ContentFit::Contain)
                     .height(30)
                     .width(30), 
-                text("Signal")
+                text(account.name.as_str())
                     .style(move |_| text::Style {
-                        color: (self.signal_state == LinkState::Linked).then_some(Color::from_rgb(0.0, 0.0, 0.7)),
+                        color: (state == LinkState::Linked).then_some(linked_color),
                     })
                     .height(Length::Fill)
                     .align_y(Alignment::Center),
-                (self.signal_state == LinkState::Unlinked).then_some(Message::LinkSignal),
+                (state == LinkState::Unlinked).then_some(Message::Link(messenger, account.id)),
                 false
             )
-        )
-        .push(
-            menu_button(
-                svg(svg::Handle::from_memory(WHATSAPP_ICON))
-                    .style(move |theme: &iced::Theme, _status| svg::Style {
-                        color: match self.whatsapp_state {
-                            LinkState::Linked => None,
-                            LinkState::Disconnected => Some(Color { r: 0.8, ..Color::BLACK }),
-                            _ => Some(theme.extended_palette().background.weaker.text),
-                        },
-                    })
-                    .width(30)
-                    .height(30), 
-                text("Whatsapp")
-                    .style(move |_| text::Style {
-                        color: (self.whatsapp_state == LinkState::Linked).then_some(Color::from_rgb(0.0, 0.7, 0.0)),
-                    })
-                    .height(Length::Fill)
-                    .align_y(Alignment::Center), 
-                (self.whatsapp_state == LinkState::Unlinked).then_some(Message::LinkWhatsapp),
-                false
-            )
-        )
+            .into()
+        }))
         .push(
             iced::widget::rule::horizontal(3)
             .style(|theme: &iced::Theme| iced::widget::rule::Style { 
@@ -432,7 +383,7 @@ impl SideMenu {
         .into()
     }
 
-    pub fn view(&self, selected_screen: Screen, data: &AppData) -> Element<'_, Message> {
+    pub fn view<'a>(&'a self, selected_screen: Screen, core: &'a Core) -> Element<'a, Message> {
         Stack::new()
         .push(
             container(
@@ -454,7 +405,7 @@ impl SideMenu {
         .push(
             opaque(
                 container(
-                    self.menu_content(selected_screen, data)
+                    self.menu_content(selected_screen, core)
                 )
                 .clip(true)
                 .width(
@@ -481,7 +432,7 @@ impl SideMenu {
 }
 
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Display)]
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Display, Serialize)]
 pub enum LinkState {
     #[default]
     #[display("Не прив'язано")]
@@ -495,6 +446,13 @@ pub enum LinkState {
 }
 
 
+/// Accounts of both messengers with their link state, in the order they are shown
+fn accounts(core: &Core) -> impl Iterator<Item = (Messenger, &Account, LinkState)> {
+    [Messenger::Signal, Messenger::Whatsapp]
+        .into_iter()
+        .flat_map(move |messenger| core.data.accounts(messenger).iter().map(move |account| (messenger, account, core.link_state(messenger, account.id))))
+}
+
 fn menu_button<'a>(icon: impl Into<Element<'a, Message>>, text: text::Text<'a>, on_press_maybe: Option<Message>, selected: bool) -> button::Button<'a, Message> {
     button(
         Row::new()